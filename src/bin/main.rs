@@ -1,38 +1,42 @@
 use bf_tools::{
     //bf,
-    ins::BfCode,
-    interpreter::{InterpCode, Interpreter},
-    optimizer::*, bf,
+    ins_parser::parse_str_with_spans,
+    interpreter::{bf2interp::bf_to_interp_with_debug_info, peephole, Interpreter},
+    optimizer::{opt_ins::bf_to_opt_with_spans, OptState},
 };
 
 fn main() {
 
     //TODO add cli tools app?
     //let ins: BfCode = bf!();
-    let ins: BfCode = include_str!("../../target/out.bf").parse().unwrap();
+    let source = include_str!("../../target/out.bf");
+    let (ins, map) = parse_str_with_spans(source).unwrap();
 
     //println!("chars_len: {}", ins.chars_len());
     //println!("{}", &ins);
 
-    let ins: OptCode = ins.into();
+    let (unoptimized, spans) = bf_to_opt_with_spans(ins, &map);
 
-    //println!("opt_len: {}", ins.ins_len());
-    //println!("{:?}", &ins);
+    //println!("opt_len: {}", unoptimized.ins_len());
+    //println!("{:?}", &unoptimized);
 
     let ins = OptState::builder()
         .add_default_passes()
         .build()
-        .run_passes(ins);
+        .run_passes(unoptimized.clone());
 
     //println!("opt_len: {}", ins.ins_len());
     //println!("{:?}", &ins);
 
-    let ins = BfCode::from(ins);
-
-    //println!("chars_len: {}", ins.chars_len());
-    //println!("{}", &ins);
-
-    let ins: InterpCode = ins.into();
+    // passes don't keep source spans, so they're attached only if code isn't changed
+    // (otherwise errors are reported without source line)
+    let spans = (ins == unoptimized).then_some(spans);
+    let (mut ins, mut debug) = bf_to_interp_with_debug_info(ins);
+    peephole::optimize_with_debug_info(&mut ins, &mut debug);
+    let debug = match spans {
+        Some(spans) => debug.with_spans(spans),
+        None => debug,
+    };
 
     //println!("{}", &ins);
 
@@ -42,8 +46,11 @@ fn main() {
         let mut interpreter = Interpreter::builder()
             //.set_stdout(&mut bf_output)
             .build();
-        interpreter.run(ins).unwrap();
-        
+        if let Err(err) = interpreter.run(ins) {
+            eprint!("{}", err.with_debug_info(&debug).render(source));
+            std::process::exit(1);
+        }
+
         println!("\ntape: {:?}", &interpreter.tape);
         println!("\nptr: {:?}", &interpreter.data_pointer);
     }
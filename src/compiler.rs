@@ -0,0 +1,750 @@
+use crate::ins::BfCode;
+use crate::masm::unescape;
+use crate::program::{Cell, ProgramBuilder};
+use std::collections::HashMap;
+
+/*
+    statements:
+    `var x = expr`                      declare variable (visible until end of block)
+    `x = expr`                          assignment
+    `if expr { } else if expr { } else { }`
+    `while expr { }`
+    `print "x = ", x, "\n"`             print strings and numbers (in decimal)
+    `put expr`                          print char
+    `read x`                            read char
+    expressions: u8 values with overflow, `||` `&&` `== !=` `< <= > >=` `+ -` `* / %`,
+    unary `-` `!`, numbers, char literals (`'a'`), variables, parentheses.
+    comparisons and logical operators give 0 or 1, `x / 0` is 0 and `x % 0` is x
+    text after `//` is comment
+*/
+
+/// Max nesting of blocks and expressions
+const MAX_DEPTH: usize = 256;
+
+/// Keywords (they can't be variable names)
+const KEYWORDS: [&str; 7] = ["var", "if", "else", "while", "print", "put", "read"];
+
+/// Binary operators by precedence (lowest first)
+const LEVELS: [&[(&str, BinOp)]; 6] = [
+    &[("||", BinOp::Or)],
+    &[("&&", BinOp::And)],
+    &[("==", BinOp::Eq), ("!=", BinOp::Ne)],
+    &[
+        ("<", BinOp::Lt),
+        ("<=", BinOp::Le),
+        (">", BinOp::Gt),
+        (">=", BinOp::Ge),
+    ],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Mod)],
+];
+
+/// Symbols (longer first)
+const SYMBOLS: [&str; 20] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "*", "/", "%", "!", "=", "(", ")", "{",
+    "}", ",",
+];
+
+/// Kind of [`CompileError`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompileErrorKind {
+    /// Char which can't start token, or invalid string
+    InvalidToken,
+    /// Token isn't expected here
+    Expected(&'static str),
+    /// Number doesn't fit in cell
+    NumberTooLarge,
+    /// Variable isn't declared
+    UnknownVariable(String),
+    /// Variable is already declared in same block
+    DuplicateVariable(String),
+    /// Blocks or expressions are nested deeper than compiler supports
+    TooDeep,
+}
+
+/// Error type of [`compile`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    /// line number (starts from 1)
+    pub line: usize,
+    /// Error kind
+    pub kind: CompileErrorKind,
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            CompileErrorKind::InvalidToken => write!(f, "invalid token"),
+            CompileErrorKind::Expected(expected) => write!(f, "expected {expected}"),
+            CompileErrorKind::NumberTooLarge => write!(f, "number doesn't fit in cell"),
+            CompileErrorKind::UnknownVariable(name) => write!(f, "unknown variable `{name}`"),
+            CompileErrorKind::DuplicateVariable(name) => {
+                write!(f, "variable `{name}` is already declared")
+            }
+            CompileErrorKind::TooDeep => write!(f, "code is nested too deep"),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+const fn error(line: usize, kind: CompileErrorKind) -> CompileError {
+    CompileError { line, kind }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Tok {
+    Num(u8),
+    Ident(String),
+    Str(Vec<u8>),
+    Sym(&'static str),
+}
+
+/// Split text into tokens with line numbers
+fn tokenize(text: &str) -> Result<Vec<(usize, Tok)>, CompileError> {
+    let mut res = Vec::new();
+    for (line, text) in text.lines().enumerate() {
+        let line = line + 1;
+        let text = text.split("//").next().unwrap_or_default();
+        let mut chars = text.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            let tok = match c {
+                c if c.is_whitespace() => continue,
+                c if c.is_ascii_alphabetic() || c == '_' => {
+                    let mut end = text.len();
+                    while let Some((i, c)) = chars.peek() {
+                        if !c.is_ascii_alphanumeric() && *c != '_' {
+                            end = *i;
+                            break;
+                        }
+                        chars.next();
+                    }
+                    Tok::Ident(text[start..end].to_string())
+                }
+                c if c.is_ascii_digit() => {
+                    let mut n = u32::from(c) - u32::from('0');
+                    while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                        n = (n * 10 + u32::from(c) - u32::from('0')).min(256);
+                    }
+                    let n = u8::try_from(n)
+                        .map_err(|_| error(line, CompileErrorKind::NumberTooLarge))?;
+                    Tok::Num(n)
+                }
+                '"' | '\'' => {
+                    let mut s = Vec::new();
+                    loop {
+                        let c = match chars.next() {
+                            Some((_, '\\')) => chars.next().and_then(|(_, c)| unescape(c)),
+                            Some((_, end)) if end == c => break,
+                            Some((_, c)) => Some(c),
+                            None => None,
+                        };
+                        let c = c.ok_or(error(line, CompileErrorKind::InvalidToken))?;
+                        s.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                    }
+                    match (c, s.as_slice()) {
+                        ('"', _) => Tok::Str(s),
+                        (_, [c]) => Tok::Num(*c),
+                        _ => return Err(error(line, CompileErrorKind::InvalidToken)),
+                    }
+                }
+                _ => {
+                    let sym = SYMBOLS
+                        .into_iter()
+                        .find(|sym| text[start..].starts_with(sym))
+                        .ok_or(error(line, CompileErrorKind::InvalidToken))?;
+                    for _ in 1..sym.len() {
+                        chars.next();
+                    }
+                    Tok::Sym(sym)
+                }
+            };
+            res.push((line, tok));
+        }
+    }
+    Ok(res)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Num(u8),
+    /// variable name and line
+    Var(String, usize),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug)]
+enum Item {
+    Str(Vec<u8>),
+    Expr(Expr),
+}
+
+#[derive(Debug)]
+enum StmtKind {
+    Var(String, Expr),
+    Assign(String, Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Print(Vec<Item>),
+    Put(Expr),
+    Read(String),
+}
+
+#[derive(Debug)]
+struct Stmt {
+    line: usize,
+    kind: StmtKind,
+}
+
+/// Recursive descent parser (nesting is limited by [`MAX_DEPTH`])
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<(usize, Tok)>>,
+    /// line of last token
+    line: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Tok> {
+        let (line, tok) = self.tokens.next()?;
+        self.line = line;
+        Some(tok)
+    }
+    /// Skip next token if it's symbol `sym`
+    fn skip(&mut self, sym: &str) -> bool {
+        self.tokens
+            .next_if(|(_, t)| matches!(t, Tok::Sym(s) if *s == sym))
+            .is_some()
+    }
+    fn expect(&mut self, sym: &'static str) -> Result<(), CompileError> {
+        match self.next() {
+            Some(Tok::Sym(s)) if s == sym => Ok(()),
+            _ => Err(error(self.line, CompileErrorKind::Expected(sym))),
+        }
+    }
+    fn name(&mut self) -> Result<String, CompileError> {
+        match self.next() {
+            Some(Tok::Ident(name)) if !KEYWORDS.contains(&name.as_str()) => Ok(name),
+            _ => Err(error(
+                self.line,
+                CompileErrorKind::Expected("variable name"),
+            )),
+        }
+    }
+    const fn check_depth(&self, depth: usize) -> Result<(), CompileError> {
+        if depth > MAX_DEPTH {
+            Err(error(self.line, CompileErrorKind::TooDeep))
+        } else {
+            Ok(())
+        }
+    }
+    /// Statements until `}` (or end of text for top level block)
+    fn block(&mut self, depth: usize) -> Result<Vec<Stmt>, CompileError> {
+        self.check_depth(depth)?;
+        let mut res = Vec::new();
+        loop {
+            match self.tokens.peek() {
+                None if depth == 0 => return Ok(res),
+                None => return Err(error(self.line, CompileErrorKind::Expected("}"))),
+                Some((_, Tok::Sym("}"))) if depth > 0 => {
+                    self.next();
+                    return Ok(res);
+                }
+                _ => res.push(self.stmt(depth)?),
+            }
+        }
+    }
+    fn stmt(&mut self, depth: usize) -> Result<Stmt, CompileError> {
+        let Some(Tok::Ident(word)) = self.next() else {
+            return Err(error(self.line, CompileErrorKind::Expected("statement")));
+        };
+        let line = self.line;
+        let kind = match word.as_str() {
+            "var" => {
+                let name = self.name()?;
+                self.expect("=")?;
+                StmtKind::Var(name, self.expr(depth)?)
+            }
+            "if" => {
+                let cond = self.expr(depth)?;
+                self.expect("{")?;
+                let then = self.block(depth + 1)?;
+                let mut otherwise = Vec::new();
+                if self
+                    .tokens
+                    .next_if(|(_, t)| matches!(t, Tok::Ident(w) if w == "else"))
+                    .is_some()
+                {
+                    if matches!(self.tokens.peek(), Some((_, Tok::Ident(w))) if w == "if") {
+                        self.check_depth(depth + 1)?;
+                        otherwise.push(self.stmt(depth + 1)?);
+                    } else {
+                        self.expect("{")?;
+                        otherwise = self.block(depth + 1)?;
+                    }
+                }
+                StmtKind::If(cond, then, otherwise)
+            }
+            "while" => {
+                let cond = self.expr(depth)?;
+                self.expect("{")?;
+                StmtKind::While(cond, self.block(depth + 1)?)
+            }
+            "print" => {
+                let mut items = Vec::new();
+                loop {
+                    items.push(
+                        match self.tokens.next_if(|(_, t)| matches!(t, Tok::Str(_))) {
+                            Some((_, Tok::Str(s))) => Item::Str(s),
+                            _ => Item::Expr(self.expr(depth)?),
+                        },
+                    );
+                    if !self.skip(",") {
+                        break;
+                    }
+                }
+                StmtKind::Print(items)
+            }
+            "put" => StmtKind::Put(self.expr(depth)?),
+            "read" => StmtKind::Read(self.name()?),
+            _ if KEYWORDS.contains(&word.as_str()) => {
+                return Err(error(line, CompileErrorKind::Expected("statement")))
+            }
+            _ => {
+                self.expect("=")?;
+                StmtKind::Assign(word, self.expr(depth)?)
+            }
+        };
+        Ok(Stmt { line, kind })
+    }
+    fn expr(&mut self, depth: usize) -> Result<Expr, CompileError> {
+        self.binary(0, depth)
+    }
+    fn binary(&mut self, level: usize, mut depth: usize) -> Result<Expr, CompileError> {
+        let Some(ops) = LEVELS.get(level) else {
+            return self.unary(depth);
+        };
+        let mut lhs = self.binary(level + 1, depth)?;
+        while let Some((_, op)) = ops.iter().find(|(sym, _)| self.skip(sym)) {
+            // left operand gets deeper with each operator
+            depth += 1;
+            self.check_depth(depth)?;
+            let rhs = self.binary(level + 1, depth)?;
+            lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+    fn unary(&mut self, depth: usize) -> Result<Expr, CompileError> {
+        self.check_depth(depth)?;
+        match self.next() {
+            Some(Tok::Num(n)) => Ok(Expr::Num(n)),
+            Some(Tok::Ident(name)) if !KEYWORDS.contains(&name.as_str()) => {
+                Ok(Expr::Var(name, self.line))
+            }
+            Some(Tok::Sym("-")) => Ok(Expr::Neg(Box::new(self.unary(depth + 1)?))),
+            Some(Tok::Sym("!")) => Ok(Expr::Not(Box::new(self.unary(depth + 1)?))),
+            Some(Tok::Sym("(")) => {
+                let expr = self.expr(depth + 1)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            _ => Err(error(self.line, CompileErrorKind::Expected("expression"))),
+        }
+    }
+}
+
+/// Code generator on top of [`ProgramBuilder`]
+///
+/// Expressions are evaluated into temporary cells, which are freed by their users
+#[derive(Default)]
+struct Compiler {
+    scopes: Vec<HashMap<String, Cell>>,
+}
+
+impl Compiler {
+    fn lookup(&self, name: &str, line: usize) -> Result<Cell, CompileError> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+            .ok_or_else(|| error(line, CompileErrorKind::UnknownVariable(name.to_string())))
+    }
+
+    fn block(&mut self, b: &mut ProgramBuilder, stmts: &[Stmt]) -> Result<(), CompileError> {
+        self.scopes.push(HashMap::new());
+        for stmt in stmts {
+            self.stmt(b, stmt)?;
+        }
+        for cell in self.scopes.pop().into_iter().flat_map(HashMap::into_values) {
+            b.free(cell);
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, b: &mut ProgramBuilder, stmt: &Stmt) -> Result<(), CompileError> {
+        match &stmt.kind {
+            StmtKind::Var(name, value) => {
+                let cell = self.eval(b, value)?;
+                let scope = self.scopes.last_mut().expect("statement is in block");
+                if scope.insert(name.clone(), cell).is_some() {
+                    let kind = CompileErrorKind::DuplicateVariable(name.clone());
+                    return Err(error(stmt.line, kind));
+                }
+            }
+            StmtKind::Assign(name, value) => {
+                let cell = self.lookup(name, stmt.line)?;
+                if let Some(n) = increment(name, value) {
+                    b.add(cell, n);
+                } else {
+                    let val = self.eval(b, value)?;
+                    b.clear(cell).add_to(val, cell).free(val);
+                }
+            }
+            StmtKind::If(cond, then, otherwise) => {
+                let cond = self.eval(b, cond)?;
+                let is_zero = (!otherwise.is_empty()).then(|| {
+                    let is_zero = b.temp();
+                    b.add(is_zero, 1);
+                    is_zero
+                });
+                let mut res = Ok(());
+                take_if(b, cond, |b| {
+                    res = self.block(b, then);
+                    if let Some(is_zero) = is_zero {
+                        b.sub(is_zero, 1);
+                    }
+                });
+                res?;
+                b.free(cond);
+                if let Some(is_zero) = is_zero {
+                    let mut res = Ok(());
+                    take_if(b, is_zero, |b| res = self.block(b, otherwise));
+                    res?;
+                    b.free(is_zero);
+                }
+            }
+            StmtKind::While(cond, body) => {
+                let cell = self.eval(b, cond)?;
+                let mut res = Ok(());
+                b.while_nonzero(cell, |b| {
+                    res = self.block(b, body).and_then(|()| {
+                        let val = self.eval(b, cond)?;
+                        b.clear(cell).add_to(val, cell).free(val);
+                        Ok(())
+                    });
+                });
+                res?;
+                b.free(cell);
+            }
+            StmtKind::Print(items) => {
+                for item in items {
+                    match item {
+                        Item::Str(s) => {
+                            b.print_str(s);
+                        }
+                        Item::Expr(expr) => {
+                            let val = self.eval(b, expr)?;
+                            print_number(b, val);
+                        }
+                    }
+                }
+            }
+            StmtKind::Put(expr) => {
+                let val = self.eval(b, expr)?;
+                b.print(val).free(val);
+            }
+            StmtKind::Read(name) => {
+                let cell = self.lookup(name, stmt.line)?;
+                b.read_into(cell);
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluate expression into new temporary cell
+    fn eval(&mut self, b: &mut ProgramBuilder, expr: &Expr) -> Result<Cell, CompileError> {
+        Ok(match expr {
+            Expr::Num(n) => {
+                let res = b.temp();
+                b.add(res, *n);
+                res
+            }
+            Expr::Var(name, line) => {
+                let var = self.lookup(name, *line)?;
+                let (res, tmp) = (b.temp(), b.temp());
+                b.copy(var, res, tmp).free(tmp);
+                res
+            }
+            Expr::Neg(expr) => {
+                let val = self.eval(b, expr)?;
+                let res = b.temp();
+                b.sub_from(val, res).free(val);
+                res
+            }
+            Expr::Not(expr) => {
+                let val = self.eval(b, expr)?;
+                not(b, val)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.eval(b, lhs)?;
+                if let (BinOp::Add | BinOp::Sub, Expr::Num(n)) = (op, &**rhs) {
+                    b.add(
+                        lhs,
+                        if *op == BinOp::Add {
+                            *n
+                        } else {
+                            n.wrapping_neg()
+                        },
+                    );
+                    return Ok(lhs);
+                }
+                let rhs = self.eval(b, rhs)?;
+                binary(b, *op, lhs, rhs)
+            }
+        })
+    }
+}
+
+/// Value added to variable `name` by assignment `name = name + n` or `name = name - n`
+fn increment(name: &str, value: &Expr) -> Option<u8> {
+    match value {
+        Expr::Binary(op @ (BinOp::Add | BinOp::Sub), lhs, rhs) => match (&**lhs, &**rhs) {
+            (Expr::Var(var, _), Expr::Num(n)) if var == name => Some(if *op == BinOp::Add {
+                *n
+            } else {
+                n.wrapping_neg()
+            }),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Run `body` if `cell` isn't zero and clear `cell`
+fn take_if(b: &mut ProgramBuilder, cell: Cell, body: impl FnOnce(&mut ProgramBuilder)) {
+    b.while_nonzero(cell, |b| {
+        body(b);
+        b.clear(cell);
+    });
+}
+
+/// 1 if `val` is zero, 0 otherwise (`val` is freed)
+fn not(b: &mut ProgramBuilder, val: Cell) -> Cell {
+    let res = b.temp();
+    b.add(res, 1);
+    take_if(b, val, |b| {
+        b.sub(res, 1);
+    });
+    b.free(val);
+    res
+}
+
+/// 1 if `lhs < rhs`, 0 otherwise (operands are freed)
+///
+/// Both operands are decremented until one of them is zero
+fn less(b: &mut ProgramBuilder, lhs: Cell, rhs: Cell) -> Cell {
+    let res = b.temp();
+    b.while_nonzero(rhs, |b| {
+        b.if_else(
+            lhs,
+            |b| {
+                b.sub(lhs, 1);
+            },
+            |b| {
+                // stop after decrement
+                b.add(res, 1).set(rhs, 1);
+            },
+        );
+        b.sub(rhs, 1);
+    });
+    b.free(lhs).free(rhs);
+    res
+}
+
+/// Quotient and remainder of `lhs / rhs` (operands are freed)
+///
+/// `lhs` is counted down while remainder is counted up,
+/// remainder is reset each time it reaches `rhs`
+fn divmod(b: &mut ProgramBuilder, lhs: Cell, rhs: Cell) -> (Cell, Cell) {
+    let (quot, rem, left, tmp) = (b.temp(), b.temp(), b.temp(), b.temp());
+    b.copy(rhs, left, tmp).free(tmp);
+    b.while_nonzero(lhs, |b| {
+        b.sub(lhs, 1).add(rem, 1).sub(left, 1);
+        b.if_else(
+            left,
+            |_| {},
+            |b| {
+                let tmp = b.temp();
+                b.add(quot, 1).clear(rem).copy(rhs, left, tmp).free(tmp);
+            },
+        );
+    });
+    b.free(left).free(rhs);
+    (quot, rem)
+}
+
+/// Evaluate binary operator (operands are freed)
+fn binary(b: &mut ProgramBuilder, op: BinOp, lhs: Cell, rhs: Cell) -> Cell {
+    match op {
+        BinOp::Add => {
+            b.add_to(rhs, lhs).free(rhs);
+            lhs
+        }
+        BinOp::Sub => {
+            b.sub_from(rhs, lhs).free(rhs);
+            lhs
+        }
+        BinOp::Mul => {
+            let res = b.temp();
+            b.while_nonzero(lhs, |b| {
+                let (val, tmp) = (b.temp(), b.temp());
+                b.sub(lhs, 1).copy(rhs, val, tmp).free(tmp).add_to(val, res);
+            });
+            b.free(lhs).free(rhs);
+            res
+        }
+        BinOp::Div | BinOp::Mod => {
+            let (quot, rem) = divmod(b, lhs, rhs);
+            let (res, other) = if op == BinOp::Div {
+                (quot, rem)
+            } else {
+                (rem, quot)
+            };
+            b.free(other);
+            res
+        }
+        BinOp::Eq | BinOp::Ne => {
+            b.sub_from(rhs, lhs).free(rhs);
+            let res = not(b, lhs);
+            if op == BinOp::Ne {
+                not(b, res)
+            } else {
+                res
+            }
+        }
+        BinOp::Lt => less(b, lhs, rhs),
+        BinOp::Gt => less(b, rhs, lhs),
+        BinOp::Ge => {
+            let res = less(b, lhs, rhs);
+            not(b, res)
+        }
+        BinOp::Le => {
+            let res = less(b, rhs, lhs);
+            not(b, res)
+        }
+        BinOp::And | BinOp::Or => {
+            let res = b.temp();
+            if op == BinOp::And {
+                take_if(b, lhs, |b| {
+                    take_if(b, rhs, |b| {
+                        b.add(res, 1);
+                    })
+                });
+            } else {
+                take_if(b, lhs, |b| {
+                    b.set(res, 1);
+                });
+                take_if(b, rhs, |b| {
+                    b.set(res, 1);
+                });
+            }
+            b.free(lhs).free(rhs);
+            res
+        }
+    }
+}
+
+/// Print `val` in decimal without leading zeros (`val` is freed)
+fn print_number(b: &mut ProgramBuilder, val: Cell) {
+    let ten = b.temp();
+    b.add(ten, 10);
+    let (high, ones) = divmod(b, val, ten);
+    let (has_tens, tmp) = (b.temp(), b.temp());
+    b.copy(high, has_tens, tmp).free(tmp);
+    let ten = b.temp();
+    b.add(ten, 10);
+    let (hundreds, tens) = divmod(b, high, ten);
+    take_if(b, hundreds, |b| {
+        b.add(hundreds, b'0').print(hundreds);
+    });
+    take_if(b, has_tens, |b| {
+        b.add(tens, b'0').print(tens);
+    });
+    b.add(ones, b'0').print(ones);
+    b.free(hundreds).free(tens).free(ones);
+}
+
+/// Compile program of small structured language to [`BfCode`]
+///
+/// Variables are `u8` cells with overflow. Statements:
+/// - `var x = expr` - declare variable (visible until end of block)
+/// - `x = expr`
+/// - `if expr { } else if expr { } else { }`, `while expr { }`
+/// - `print "x = ", x, "\n"` - print strings and numbers in decimal
+/// - `put expr` - print char, `read x` - read char
+///
+/// Expressions have operators `||`, `&&`, `== !=`, `< <= > >=`, `+ -`, `* / %` (by precedence),
+/// unary `-` and `!`, numbers, char literals (`'a'`) and parentheses.
+/// Comparisons and logical operators give 0 or 1, `x / 0` is 0 and `x % 0` is `x`.
+/// Text after `//` is comment
+/// ```
+/// # use bf_tools::{ compiler::compile, optimizer::OptState, interpreter::Interpreter };
+/// let code = compile(r#"
+///     var n = 2
+///     while n < 20 {
+///         var d = 2
+///         var prime = 1
+///         while d * d <= n && prime {
+///             if n % d == 0 { prime = 0 }
+///             d = d + 1
+///         }
+///         if prime { print n, " " }
+///         n = n + 1
+///     }
+///     print "\n"
+/// "#).unwrap();
+///
+/// let code = OptState::default().run_passes(code.into());
+/// let mut output = Vec::new();
+/// Interpreter::builder().set_stdout(&mut output).build().run(code).unwrap();
+/// assert_eq!(output, b"2 3 5 7 11 13 17 19 \n");
+/// ```
+/// Errors point to source lines:
+/// ```
+/// # use bf_tools::compiler::{ compile, CompileErrorKind };
+/// let err = compile("var x = 1\ny = x + 1").unwrap_err();
+/// assert_eq!(err.line, 2);
+/// assert_eq!(err.kind, CompileErrorKind::UnknownVariable("y".to_string()));
+/// assert_eq!(err.to_string(), "line 2: unknown variable `y`");
+/// ```
+/// # Errors
+/// return `Err` if program isn't valid
+pub fn compile(text: &str) -> Result<BfCode, CompileError> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?.into_iter().peekable(),
+        line: 1,
+    };
+    let stmts = parser.block(0)?;
+    let mut b = ProgramBuilder::new();
+    Compiler::default().block(&mut b, &stmts)?;
+    Ok(b.build())
+}
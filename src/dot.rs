@@ -0,0 +1,216 @@
+use crate::interpreter::{InterpCode, InterpIns};
+use crate::optimizer::opt_ins::{OptBlock, OptCode};
+use crate::visit::{Tree, Visit};
+use std::fmt::Write;
+
+/// Nesting of clusters which is shown with indentation (output of deep loops stays linear)
+const MAX_INDENT: usize = 16;
+
+/// Escape text for quoted DOT string, lines are left-justified
+fn escape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                res.push('\\');
+                res.push(c);
+            }
+            '\n' => res.push_str("\\l"),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+/// Writer of [`opt_to_dot`] graph
+struct OptGraph {
+    /// [`OptCode::offset`] of loops sorted by id (see [`OptCode::loop_offsets`])
+    offsets: Vec<(usize, Option<isize>)>,
+    /// pre-order id of next block
+    id: usize,
+    nodes: String,
+    edges: String,
+    depth: usize,
+    /// nodes (with edge labels) which are followed by next node
+    prev: Vec<(String, &'static str)>,
+    /// loop heads of enclosing loops
+    heads: Vec<String>,
+}
+
+impl OptGraph {
+    fn line(&mut self, text: impl std::fmt::Display) {
+        for _ in 0..=self.depth.min(MAX_INDENT) {
+            self.nodes.push_str("    ");
+        }
+        // writing to `String` doesn't fail
+        let _ = writeln!(self.nodes, "{text}");
+    }
+    /// Connect previous nodes to `node`
+    fn connect(&mut self, node: &str) {
+        for (from, label) in std::mem::take(&mut self.prev) {
+            let _ = write!(self.edges, "    {from} -> {node}");
+            let _ = if label.is_empty() {
+                writeln!(self.edges, ";")
+            } else {
+                writeln!(self.edges, " [label=\"{label}\"];")
+            };
+        }
+    }
+    fn node(&mut self, label: impl std::fmt::Display) {
+        let node = format!("b{}", self.id);
+        self.id += 1;
+        self.line(format_args!(
+            "{node} [label=\"{}\"];",
+            escape(&label.to_string())
+        ));
+        self.connect(&node);
+        self.prev.push((node, ""));
+    }
+}
+
+impl Visit<OptCode> for OptGraph {
+    fn visit_node(&mut self, block: &OptBlock) {
+        match block {
+            OptBlock::Block(bb) => self.node(bb),
+            OptBlock::IOIns(io) => self.node(io),
+            OptBlock::Loop(_) => {}
+        }
+    }
+    fn enter_loop(&mut self, _body: &OptCode) -> bool {
+        let offset = self
+            .offsets
+            .binary_search_by_key(&self.id, |(id, _)| *id)
+            .map_or(None, |i| self.offsets[i].1);
+        let label = match offset {
+            Some(0) => "balanced".to_string(),
+            Some(offset) => format!("unbalanced, offset {offset:+}"),
+            None => "unbalanced, unknown offset".to_string(),
+        };
+        let id = self.id;
+        self.line(format_args!("subgraph cluster_{id} {{"));
+        self.depth += 1;
+        self.line(format_args!("label=\"{label}\";"));
+        let head = format!("b{}", self.id);
+        self.id += 1;
+        self.line(format_args!("{head} [shape=diamond, label=\"loop\"];"));
+        self.connect(&head);
+        self.prev.push((head.clone(), "nonzero"));
+        self.heads.push(head);
+        true
+    }
+    fn leave_loop(&mut self, _body: &OptCode) {
+        if let Some(head) = self.heads.pop() {
+            self.connect(&head);
+            self.prev.push((head, "zero"));
+        }
+        self.depth -= 1;
+        self.line("}");
+    }
+}
+
+/// Render [`OptCode`] as Graphviz DOT graph
+///
+/// Nodes are basic blocks and IO instructions (in their text form, see [`crate::optimizer::text`])
+/// and loop heads. Loop body is a cluster labeled with it's [`OptCode::offset`]:
+/// `balanced` loops return pointer back each iteration.
+/// Edges follow control flow, loop heads have `nonzero` edge into body and `zero` edge after loop
+/// ```
+/// # use bf_tools::{ bf, dot::opt_to_dot, optimizer::OptCode };
+/// let dot = opt_to_dot(&OptCode::from(bf!(+++[->++<]>[>].)));
+/// assert!(dot.starts_with("digraph opt {"));
+/// assert!(dot.contains("b0 [label=\"bb off=+0 { +0:3 }\"];"));
+/// assert!(dot.contains("label=\"balanced\";"));
+/// assert!(dot.contains("label=\"unbalanced, offset +1\";"));
+/// assert!(dot.contains("b1 -> b2 [label=\"nonzero\"];"));
+/// assert!(dot.contains("b2 -> b1;"));
+/// assert!(dot.contains("b1 -> b3 [label=\"zero\"];"));
+/// ```
+pub fn opt_to_dot(code: &OptCode) -> String {
+    let mut graph = OptGraph {
+        offsets: code.loop_offsets(),
+        id: 0,
+        nodes: String::new(),
+        edges: String::new(),
+        depth: 0,
+        prev: vec![("start".to_string(), "")],
+        heads: Vec::new(),
+    };
+    graph.line("node [shape=box, fontname=\"monospace\"];");
+    graph.line("start [shape=oval];");
+    code.visit(&mut graph);
+    graph.line("end [shape=oval];");
+    graph.connect("end");
+    format!("digraph opt {{\n{}{}}}\n", graph.nodes, graph.edges)
+}
+
+/// Render [`InterpCode`] as Graphviz DOT control flow graph
+///
+/// Nodes are basic blocks (instructions between jumps and jump targets) listed with their indices.
+/// Edges are labeled with jump kind (`JmpT`, `JmpF`, `Jmp`), fallthrough edges aren't labeled
+/// ```
+/// # use bf_tools::{ dot::interp_to_dot, interpreter::asm::parse_asm };
+/// let code = parse_asm("
+///         jmp_f [input_offset], 'end
+///     loop:
+///         sub 1, [0]
+///         jmp_t [input_offset], 'loop
+///     end:
+///         putchar [0]
+/// ").unwrap();
+/// let dot = interp_to_dot(&code);
+/// assert!(dot.contains("n1 [label=\"1: sub 1, [0]\\l2: jmp_t [input_offset], '1\\l\"];"));
+/// assert!(dot.contains("n0 -> n3 [label=\"JmpF\"];"));
+/// assert!(dot.contains("n1 -> n1 [label=\"JmpT\"];"));
+/// assert!(dot.contains("n1 -> n3;"));
+/// assert!(dot.contains("n3 -> end;"));
+/// ```
+pub fn interp_to_dot(code: &InterpCode) -> String {
+    let len = code.0.len();
+    let target = |dest: u32| -> usize { (dest as usize).min(len) };
+    // instructions which start basic blocks
+    let mut leaders = vec![false; len + 1];
+    leaders[0] = true;
+    leaders[len] = true;
+    for (i, ins) in code.0.iter().enumerate() {
+        if let InterpIns::JmpT { dest } | InterpIns::JmpF { dest } | InterpIns::Jmp { dest } = ins {
+            leaders[target(*dest)] = true;
+            leaders[i + 1] = true;
+        }
+    }
+    let name = |i: usize| {
+        if i == len {
+            "end".to_string()
+        } else {
+            format!("n{i}")
+        }
+    };
+    let mut nodes = String::new();
+    let mut edges = String::new();
+    let _ = writeln!(nodes, "    node [shape=box, fontname=\"monospace\"];");
+    let _ = writeln!(nodes, "    start [shape=oval];");
+    let _ = writeln!(edges, "    start -> {};", name(0));
+    let mut start = 0;
+    while start < len {
+        let end = (start + 1..=len).find(|i| leaders[*i]).unwrap_or(len);
+        let label: String = (start..end)
+            .map(|i| format!("{i}: {}\n", code.0[i]))
+            .collect();
+        let _ = writeln!(nodes, "    {} [label=\"{}\"];", name(start), escape(&label));
+        let (jump, fallthrough) = match code.0[end - 1] {
+            InterpIns::JmpT { dest } => (Some(("JmpT", dest)), true),
+            InterpIns::JmpF { dest } => (Some(("JmpF", dest)), true),
+            InterpIns::Jmp { dest } => (Some(("Jmp", dest)), false),
+            _ => (None, true),
+        };
+        if let Some((kind, dest)) = jump {
+            let dest = name(target(dest));
+            let _ = writeln!(edges, "    {} -> {dest} [label=\"{kind}\"];", name(start));
+        }
+        if fallthrough {
+            let _ = writeln!(edges, "    {} -> {};", name(start), name(end));
+        }
+        start = end;
+    }
+    let _ = writeln!(nodes, "    end [shape=oval];");
+    format!("digraph interp {{\n{nodes}{edges}}}\n")
+}
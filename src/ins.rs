@@ -1,3 +1,5 @@
+use crate::visit::{Tree, Visit};
+
 /// Single BF instruction
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BfIns {
@@ -25,7 +27,21 @@ pub enum BfIns {
 }
 
 /// Collection of [`BfIns`] instructions
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// All traversals (including `Clone`, `PartialEq` and `Drop`) are iterative,
+/// so code of any nesting depth can be processed (except `Debug` formatting)
+/// ```
+/// # use bf_tools::{ ins::BfCode, interpreter::Interpreter };
+/// let depth = 100_000;
+/// let code: BfCode = ("+".to_string() + &"[>".repeat(depth) + &"]".repeat(depth))
+///     .parse()
+///     .unwrap();
+/// assert_eq!(code.ins_len(), 2 * depth + 1);
+/// assert_eq!(code.to_string().len(), 3 * depth + 1);
+/// assert_eq!(code.clone(), code);
+/// Interpreter::default().run(code).unwrap();
+/// ```
+#[derive(Debug)]
 pub struct BfCode(pub Vec<BfIns>);
 
 impl BfCode {
@@ -38,12 +54,9 @@ impl BfCode {
     /// ```
     #[inline]
     pub fn ins_len(&self) -> usize {
-        self.0.iter().fold(0, |v, i| {
-            v + match i {
-                BfIns::Loop(inner) => 1 + inner.ins_len(),
-                _ => 1,
-            }
-        })
+        let mut len = Len::new(1, |_| 1);
+        self.visit(&mut len);
+        len.len
     }
     /// bf code length in characters
     /// ```
@@ -52,14 +65,108 @@ impl BfCode {
     /// ```
     #[inline]
     pub fn chars_len(&self) -> usize {
-        self.0.iter().fold(0, |v, i| {
-            v + match i {
-                BfIns::Loop(inner) => 2 + inner.chars_len(),
-                BfIns::Add(v) | BfIns::Sub(v) => *v as usize,
-                BfIns::PtrAdd(v) | BfIns::PtrSub(v) => *v,
-                BfIns::Getchar | BfIns::Putchar => 1,
+        // `[` and `]`
+        let mut len = Len::new(2, |ins| match ins {
+            BfIns::Add(v) | BfIns::Sub(v) => *v as usize,
+            BfIns::PtrAdd(v) | BfIns::PtrSub(v) => *v,
+            _ => 1,
+        });
+        self.visit(&mut len);
+        len.len
+    }
+}
+
+/// Sum of lengths of all instructions
+struct Len<F> {
+    len: usize,
+    /// length of loop without it's body
+    loop_len: usize,
+    ins_len: F,
+}
+
+impl<F: Fn(&BfIns) -> usize> Len<F> {
+    const fn new(loop_len: usize, ins_len: F) -> Self {
+        Self {
+            len: 0,
+            loop_len,
+            ins_len,
+        }
+    }
+}
+
+impl<F: Fn(&BfIns) -> usize> Visit<BfCode> for Len<F> {
+    fn visit_node(&mut self, ins: &BfIns) {
+        self.len += (self.ins_len)(ins);
+    }
+    fn enter_loop(&mut self, _body: &BfCode) -> bool {
+        self.len += self.loop_len;
+        true
+    }
+}
+
+impl Clone for BfCode {
+    fn clone(&self) -> Self {
+        let mut cur = (self.0.iter(), Vec::with_capacity(self.0.len()));
+        // enclosing loops with already cloned instructions
+        let mut stack = Vec::new();
+        loop {
+            match cur.0.next() {
+                Some(BfIns::Loop(inner)) => {
+                    let inner = (inner.0.iter(), Vec::with_capacity(inner.0.len()));
+                    stack.push(std::mem::replace(&mut cur, inner));
+                }
+                // not a loop, so clone isn't recursive
+                Some(ins) => cur.1.push(ins.clone()),
+                None => match stack.pop() {
+                    Some(parent) => {
+                        let (_, inner) = std::mem::replace(&mut cur, parent);
+                        cur.1.push(BfIns::Loop(BfCode(inner)));
+                    }
+                    None => return BfCode(cur.1),
+                },
+            }
+        }
+    }
+}
+
+impl PartialEq for BfCode {
+    fn eq(&self, other: &Self) -> bool {
+        if self.0.len() != other.0.len() {
+            return false;
+        }
+        let mut stack = vec![(self.0.iter(), other.0.iter())];
+        while let Some((a, b)) = stack.last_mut() {
+            match (a.next(), b.next()) {
+                (Some(BfIns::Loop(a)), Some(BfIns::Loop(b))) => {
+                    if a.0.len() != b.0.len() {
+                        return false;
+                    }
+                    stack.push((a.0.iter(), b.0.iter()));
+                }
+                // at least one isn't a loop, so comparison isn't recursive
+                (Some(a), Some(b)) if a != b => return false,
+                (Some(_), Some(_)) => {}
+                (None, None) => {
+                    stack.pop();
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Eq for BfCode {}
+
+impl Drop for BfCode {
+    fn drop(&mut self) {
+        // move nested loop bodies out, so each of them is dropped empty
+        let mut stack = std::mem::take(&mut self.0);
+        while let Some(ins) = stack.pop() {
+            if let BfIns::Loop(mut inner) = ins {
+                stack.append(&mut inner.0);
             }
-        })
+        }
     }
 }
 
@@ -67,18 +174,25 @@ impl std::fmt::Display for BfCode {
     #[inline]
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use std::fmt::Write;
-        for i in &self.0 {
-            let (ch, cnt) = match i {
-                BfIns::Add(cnt) => ('+', *cnt as usize),
-                BfIns::Sub(cnt) => ('-', *cnt as usize),
-                BfIns::PtrAdd(cnt) => ('>', *cnt),
-                BfIns::PtrSub(cnt) => ('<', *cnt),
-                BfIns::Putchar => ('.', 1),
-                BfIns::Getchar => (',', 1),
-                BfIns::Loop(iner) => {
+        let mut stack = vec![self.0.iter()];
+        while let Some(iter) = stack.last_mut() {
+            let (ch, cnt) = match iter.next() {
+                Some(BfIns::Add(cnt)) => ('+', *cnt as usize),
+                Some(BfIns::Sub(cnt)) => ('-', *cnt as usize),
+                Some(BfIns::PtrAdd(cnt)) => ('>', *cnt),
+                Some(BfIns::PtrSub(cnt)) => ('<', *cnt),
+                Some(BfIns::Putchar) => ('.', 1),
+                Some(BfIns::Getchar) => (',', 1),
+                Some(BfIns::Loop(iner)) => {
                     f.write_char('[')?;
-                    iner.fmt(f)?;
-                    f.write_char(']')?;
+                    stack.push(iner.0.iter());
+                    continue;
+                }
+                None => {
+                    stack.pop();
+                    if !stack.is_empty() {
+                        f.write_char(']')?;
+                    }
                     continue;
                 }
             };
@@ -89,6 +203,91 @@ impl std::fmt::Display for BfCode {
         Ok(())
     }
 }
+
+/// Spelling of BF instructions in BF-like language
+///
+/// Custom dialect is made from tokens of `+ - > < . , [ ]`:
+/// ```
+/// # use bf_tools::{ bf, ins::Dialect };
+/// let code = bf!(+[->+<].);
+/// assert_eq!(Dialect::BRAINFUCK.render(&code), code.to_string());
+/// assert_eq!(Dialect::OOK.render(&bf!(+.)), "Ook. Ook. Ook! Ook.");
+///
+/// let words = Dialect { tokens: ["inc", "dec", "right", "left", "out", "in", "do", "end"], separator: " " };
+/// assert_eq!(words.render(&bf!(++[-])), "inc inc do dec end");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Dialect {
+    /// Tokens of `+ - > < . , [ ]`
+    pub tokens: [&'static str; 8],
+    /// Text between tokens
+    pub separator: &'static str,
+}
+
+impl Dialect {
+    /// Plain brainfuck
+    pub const BRAINFUCK: Self = Self {
+        tokens: ["+", "-", ">", "<", ".", ",", "[", "]"],
+        separator: "",
+    };
+    /// Ook! (every instruction is pair of `Ook.`, `Ook?` or `Ook!`)
+    pub const OOK: Self = Self {
+        tokens: [
+            "Ook. Ook.", "Ook! Ook!", "Ook. Ook?", "Ook? Ook.", "Ook! Ook.", "Ook. Ook!", "Ook! Ook?", "Ook? Ook!",
+        ],
+        separator: " ",
+    };
+
+    /// Write `code` in this dialect
+    pub fn render(&self, code: &BfCode) -> String {
+        let mut render = Render {
+            dialect: self,
+            res: String::new(),
+        };
+        code.visit(&mut render);
+        render.res
+    }
+}
+
+/// Writer of [`Dialect::render`] text
+struct Render<'a> {
+    dialect: &'a Dialect,
+    res: String,
+}
+
+impl Render<'_> {
+    fn push(&mut self, token: usize) {
+        if !self.res.is_empty() {
+            self.res.push_str(self.dialect.separator);
+        }
+        self.res.push_str(self.dialect.tokens[token]);
+    }
+}
+
+impl Visit<BfCode> for Render<'_> {
+    fn visit_node(&mut self, ins: &BfIns) {
+        let (token, cnt) = match ins {
+            BfIns::Add(cnt) => (0, *cnt as usize),
+            BfIns::Sub(cnt) => (1, *cnt as usize),
+            BfIns::PtrAdd(cnt) => (2, *cnt),
+            BfIns::PtrSub(cnt) => (3, *cnt),
+            BfIns::Putchar => (4, 1),
+            BfIns::Getchar => (5, 1),
+            BfIns::Loop(_) => return,
+        };
+        for _ in 0..cnt {
+            self.push(token);
+        }
+    }
+    fn enter_loop(&mut self, _body: &BfCode) -> bool {
+        self.push(6);
+        true
+    }
+    fn leave_loop(&mut self, _body: &BfCode) {
+        self.push(7);
+    }
+}
+
 /// Creates [`BfCode`] object from
 ///
 /// Example:
@@ -19,6 +19,48 @@ impl std::fmt::Display for BfParseError {
 }
 impl std::error::Error for BfParseError {}
 
+/// Half-open range of char positions `start..end` in parsed source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    /// position of first char
+    pub start: usize,
+    /// position after last char
+    pub end: usize,
+}
+
+impl Span {
+    /// Create new span
+    #[inline]
+    pub const fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+    /// Smallest span that covers both `self` and `other`
+    #[inline]
+    #[must_use]
+    pub fn join(self, other: Self) -> Self {
+        Self {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// Source positions of [`BfCode`] instructions
+///
+/// `spans[i]` is the span of i-th instruction in pre-order
+/// (loop comes before it's inner instructions and covers both brackets)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    /// Instruction spans in pre-order
+    pub spans: Vec<Span>,
+}
+
 /// # Parse sequence of chars into [`BfCode`] object
 /// example:
 /// ```
@@ -29,10 +71,52 @@ impl std::error::Error for BfParseError {}
 /// ```
 /// # Errors
 /// return `Err` if string contains invalid bracket sequense like `]]` (all others strings is valid bf code)
+#[inline]
 pub fn parse_chars(chars: impl Iterator<Item = char>) -> Result<BfCode, BfParseError> {
+    parse_chars_impl(chars, None)
+}
+
+/// Same as [`parse_chars`] but also return [`SourceMap`] of parsed code
+/// ```
+/// # use bf_tools::{ bf, ins_parser::{ parse_chars_with_spans, Span } };
+/// let (code, map) = parse_chars_with_spans("+ [-]".chars()).unwrap();
+/// assert_eq!(code, bf!(+[-]));
+/// assert_eq!(map.spans, vec![Span::new(0, 1), Span::new(2, 5), Span::new(3, 4)]);
+/// ```
+/// # Errors
+/// return `Err` if string contains invalid bracket sequense like `]]` (all others strings is valid bf code)
+pub fn parse_chars_with_spans(
+    chars: impl Iterator<Item = char>,
+) -> Result<(BfCode, SourceMap), BfParseError> {
+    let mut map = SourceMap::default();
+    let code = parse_chars_impl(chars, Some(&mut map))?;
+    Ok((code, map))
+}
+
+fn parse_chars_impl(
+    chars: impl Iterator<Item = char>,
+    mut map: Option<&mut SourceMap>,
+) -> Result<BfCode, BfParseError> {
     let mut loops_stack = Vec::new();
     loops_stack.push(BfCode(Vec::new()));
+    // pre-order indices of currently opened loops
+    let mut open_spans = Vec::new();
     for (pos, ch) in chars.enumerate() {
+        if let Some(map) = map.as_deref_mut() {
+            match ch {
+                '+' | '-' | '>' | '<' | '.' | ',' => map.spans.push(Span::new(pos, pos + 1)),
+                '[' => {
+                    open_spans.push(map.spans.len());
+                    map.spans.push(Span::new(pos, pos + 1));
+                }
+                ']' => {
+                    if let Some(i) = open_spans.pop() {
+                        map.spans[i].end = pos + 1;
+                    }
+                }
+                _ => {}
+            }
+        }
         match ch {
             '+' => {
                 if let Some(last) = loops_stack.last_mut() {
@@ -123,6 +207,17 @@ where
     parse_chars(s.into().chars())
 }
 
+/// Same as [`parse_str`] but also return [`SourceMap`] of parsed code
+/// # Errors
+/// return `Err` if string contains invalid bracket sequense like `]]` (all others strings is valid bf code)
+#[inline]
+pub fn parse_str_with_spans<'a, T>(s: T) -> Result<(BfCode, SourceMap), BfParseError>
+where
+    T: Into<&'a str>,
+{
+    parse_chars_with_spans(s.into().chars())
+}
+
 impl std::str::FromStr for BfCode {
     type Err = BfParseError;
     #[inline]
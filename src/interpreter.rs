@@ -1,133 +1,295 @@
-// extended & flat instruction set (to speedup code)
-#[doc(hidden)]
+/// Instruction of lowered code executed by [`Interpreter`]
+///
+/// Cells are addressed relative to data pointer: `[offset]` is cell `ptr - offset`.
+/// `input_offset` is interpreter register set by [`InterpIns::SetInputOffset`].
+/// Jump destinations are instruction indices
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InterpIns {
-    Set { val: u8, offset: u32 }, // cells[ptr - offset] = val
-    Add { val: u8, offset: u32 }, // cells[ptr - offset] += val
-    Sub { val: u8, offset: u32 }, // cells[ptr - offset] -= val
-    Mul { val: u8, offset: u32 }, // cells[ptr - offset] *= val
-
-    PtrAdd { offset: u32 }, // ptr += offset
-    PtrSub { offset: u32 }, // ptr -= offset
+    /// `cells[ptr - offset] = val`
+    Set {
+        /// new cell value
+        val: u8,
+        /// cell offset
+        offset: u32,
+    },
+    /// `cells[ptr - offset] += val`
+    Add {
+        /// added value
+        val: u8,
+        /// cell offset
+        offset: u32,
+    },
+    /// `cells[ptr - offset] -= val`
+    Sub {
+        /// subtracted value
+        val: u8,
+        /// cell offset
+        offset: u32,
+    },
+    /// `cells[ptr - offset] *= val`
+    Mul {
+        /// multiplier
+        val: u8,
+        /// cell offset
+        offset: u32,
+    },
+
+    /// `ptr += offset`
+    PtrAdd {
+        /// pointer increment
+        offset: u32,
+    },
+    /// `ptr -= offset`
+    PtrSub {
+        /// pointer decrement
+        offset: u32,
+    },
 
     //TODO instructions for loops like [>] [<]?
 
     //TODO more register-like variables like input_offset?
-    SetInputOffset { new_input_offset: u32 }, // input_offset = new_input_offset
-
-    AddMove { mul: u8, to: u32 }, // cells[ptr - to] += cells[ptr - input_offset] * mul; cells[ptr - input_offset] = 0
-    SubMove { mul: u8, to: u32 }, // cells[ptr - to] -= cells[ptr - input_offset] * mul; cells[ptr - input_offset] = 0
-    MulMove { mul: u8, to: u32 }, // cells[ptr - to] *= cells[ptr - input_offset] * mul; cells[ptr - input_offset] = 0
-    Move { to: u32 }, // cells[ptr - to] = cells[ptr - input_offset]; cells[ptr - input_offset] = 0
-    Copy { to: u32 }, // cells[ptr - to] = cells[ptr - input_offset];
-
-    Putchar { offset: u32 }, // putchar(cells[ptr - offset])
-    Getchar { offset: u32 }, // cells[ptr - offset] = getchar()
-
-    JmpT { dest: u32 }, // if cells[ptr - input_offset] != 0 { ip = dest; }
-    JmpF { dest: u32 }, // if cells[ptr - input_offset] == 0 { ip = dest; }
-    Jmp { dest: u32 },  // ip = dest;
+    /// `input_offset = new_input_offset`
+    SetInputOffset {
+        /// new value of `input_offset` register
+        new_input_offset: u32,
+    },
+
+    /// `cells[ptr - to] += cells[ptr - input_offset] * mul; cells[ptr - input_offset] = 0`
+    AddMove {
+        /// multiplier
+        mul: u8,
+        /// destination cell offset
+        to: u32,
+    },
+    /// `cells[ptr - to] -= cells[ptr - input_offset] * mul; cells[ptr - input_offset] = 0`
+    SubMove {
+        /// multiplier
+        mul: u8,
+        /// destination cell offset
+        to: u32,
+    },
+    /// `cells[ptr - to] *= cells[ptr - input_offset] * mul; cells[ptr - input_offset] = 0`
+    MulMove {
+        /// multiplier
+        mul: u8,
+        /// destination cell offset
+        to: u32,
+    },
+    /// `cells[ptr - to] = cells[ptr - input_offset]; cells[ptr - input_offset] = 0`
+    Move {
+        /// destination cell offset
+        to: u32,
+    },
+    /// `cells[ptr - to] += cells[ptr - input_offset]`
+    Copy {
+        /// destination cell offset
+        to: u32,
+    },
+
+    /// `putchar(cells[ptr - offset])`
+    Putchar {
+        /// cell offset
+        offset: u32,
+    },
+    /// `cells[ptr - offset] = getchar()`
+    Getchar {
+        /// cell offset
+        offset: u32,
+    },
+
+    /// `if cells[ptr - input_offset] != 0 { ip = dest; }`
+    JmpT {
+        /// destination instruction index
+        dest: u32,
+    },
+    /// `if cells[ptr - input_offset] == 0 { ip = dest; }`
+    JmpF {
+        /// destination instruction index
+        dest: u32,
+    },
+    /// `ip = dest;`
+    Jmp {
+        /// destination instruction index
+        dest: u32,
+    },
+
+    /// Next `len` instructions never access cells before tape start or after `ptr + max_offset`
+    ///
+    /// Interpreter skips offset checks inside region, so this claim is trusted
+    Unchecked {
+        /// count of instructions in region
+        len: u32,
+        /// highest accessed cell relative to pointer before region
+        max_offset: u32,
+    },
 }
 
 /// Collection of [`InterpIns`] instructions
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct InterpCode(pub Vec<InterpIns>);
 
-impl std::fmt::Display for InterpCode {
+impl std::fmt::Display for InterpIns {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for ins in &self.0 {
-            match ins {
-                InterpIns::Set { val, offset } => {
-                    f.write_fmt(format_args!("set {val}, [{offset}]\n"))?
-                }
-                InterpIns::Add { val, offset } => {
-                    f.write_fmt(format_args!("add {val}, [{offset}]\n"))?
-                }
-                InterpIns::Sub { val, offset } => {
-                    f.write_fmt(format_args!("sub {val}, [{offset}]\n"))?
-                }
-                InterpIns::Mul { val, offset } => {
-                    f.write_fmt(format_args!("mul {val}, [{offset}]\n"))?
-                }
+        match self {
+            InterpIns::Set { val, offset } => {
+                f.write_fmt(format_args!("set {val}, [{offset}]"))?
+            }
+            InterpIns::Add { val, offset } => {
+                f.write_fmt(format_args!("add {val}, [{offset}]"))?
+            }
+            InterpIns::Sub { val, offset } => {
+                f.write_fmt(format_args!("sub {val}, [{offset}]"))?
+            }
+            InterpIns::Mul { val, offset } => {
+                f.write_fmt(format_args!("mul {val}, [{offset}]"))?
+            }
+
+            InterpIns::PtrAdd { offset } => f.write_fmt(format_args!("ptr_add {offset}"))?,
+            InterpIns::PtrSub { offset } => f.write_fmt(format_args!("ptr_sub {offset}"))?,
 
-                InterpIns::PtrAdd { offset } => f.write_fmt(format_args!("ptr_add {offset}\n"))?,
-                InterpIns::PtrSub { offset } => f.write_fmt(format_args!("ptr_sub {offset}\n"))?,
+            InterpIns::SetInputOffset { new_input_offset } => {
+                f.write_fmt(format_args!("set_input_offset {new_input_offset}"))?
+            }
 
-                InterpIns::SetInputOffset { new_input_offset } => {
-                    f.write_fmt(format_args!("set_input_offset {new_input_offset}\n"))?
-                }
+            InterpIns::AddMove { mul, to } => {
+                f.write_fmt(format_args!("add_move [input_offset]*{mul}, [{to}]"))?
+            }
+            InterpIns::SubMove { mul, to } => {
+                f.write_fmt(format_args!("sub_move [input_offset]*{mul}, [{to}]"))?
+            }
+            InterpIns::MulMove { mul, to } => {
+                f.write_fmt(format_args!("mul_move [input_offset]*{mul}, [{to}]"))?
+            }
+            InterpIns::Move { to } => {
+                f.write_fmt(format_args!("move [input_offset], [{to}]"))?
+            }
+            InterpIns::Copy { to } => {
+                f.write_fmt(format_args!("copy [input_offset], [{to}]"))?
+            }
 
-                InterpIns::AddMove { mul, to } => {
-                    f.write_fmt(format_args!("add_move [input_offset]*{mul}, [{to}]\n"))?
-                }
-                InterpIns::SubMove { mul, to } => {
-                    f.write_fmt(format_args!("sub_move [input_offset]*{mul}, [{to}]\n"))?
-                }
-                InterpIns::MulMove { mul, to } => {
-                    f.write_fmt(format_args!("mul_move [input_offset]*{mul}, [{to}]\n"))?
-                }
-                InterpIns::Move { to } => {
-                    f.write_fmt(format_args!("move [input_offset], [{to}]\n"))?
-                }
-                InterpIns::Copy { to } => {
-                    f.write_fmt(format_args!("copy [input_offset], [{to}]\n"))?
-                }
+            InterpIns::Putchar { offset } => {
+                f.write_fmt(format_args!("putchar [{offset}]"))?
+            }
+            InterpIns::Getchar { offset } => {
+                f.write_fmt(format_args!("getchar [{offset}]"))?
+            }
 
-                InterpIns::Putchar { offset } => {
-                    f.write_fmt(format_args!("putchar [{offset}]\n"))?
-                }
-                InterpIns::Getchar { offset } => {
-                    f.write_fmt(format_args!("getchar [{offset}]\n"))?
-                }
+            InterpIns::JmpT { dest } => {
+                f.write_fmt(format_args!("jmp_t [input_offset], '{dest}"))?
+            }
+            InterpIns::JmpF { dest } => {
+                f.write_fmt(format_args!("jmp_f [input_offset], '{dest}"))?
+            }
+            InterpIns::Jmp { dest } => f.write_fmt(format_args!("jmp '{dest}"))?,
 
-                InterpIns::JmpT { dest } => {
-                    f.write_fmt(format_args!("jmp_t [input_offset], '{dest}\n"))?
-                }
-                InterpIns::JmpF { dest } => {
-                    f.write_fmt(format_args!("jmp_f [input_offset], '{dest}\n"))?
-                }
-                InterpIns::Jmp { dest } => f.write_fmt(format_args!("jmp '{dest}\n"))?,
+            InterpIns::Unchecked { len, max_offset } => {
+                f.write_fmt(format_args!("unchecked {len}, [{max_offset}]"))?
             }
         }
         Ok(())
     }
 }
 
-/// Converter from [`crate::ins::BfCode`] to [`InterpCode`]
-pub mod bf2interp;
-/// Interpreter run implementation
-pub mod run;
-
-/// InterpreteError
-#[derive(Debug)]
-pub enum InterpreteError {
-    /// invalid data pointer index (-1 etc.)
-    DataPointerUnderflow,
-    /// data pointer - offset < 0
-    InvalidOffset,
-    /// Getchar | Putchar fails with io error
-    IOError(std::io::Error),
-}
-
-impl std::fmt::Display for InterpreteError {
+impl std::fmt::Display for InterpCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("InterpreteError") //TODO
+        for ins in &self.0 {
+            writeln!(f, "{ins}")?;
+        }
+        Ok(())
     }
 }
 
-impl std::error::Error for InterpreteError {}
+/// Converter from [`crate::ins::BfCode`] to [`InterpCode`]
+pub mod bf2interp;
+/// Interpreter run implementation
+pub mod run;
+/// Mapping of lowered code back to source
+pub mod debug_info;
+/// Execution profiler
+pub mod profile;
+/// Saving & restoring execution state
+pub mod state;
+/// Reverse execution debugger
+pub mod time_travel;
+/// IO recording & replay
+pub mod record;
+/// Scripted IO for interactive programs
+pub mod expect;
+/// UTF-8 aware text streams
+pub mod text_io;
+/// Buffered IO adapters
+pub mod buffered;
+/// Runtime errors
+pub mod error;
+/// Tape storage backends
+pub mod tape;
+/// Source code coverage
+pub mod coverage;
+/// Text assembler for lowered code
+pub mod asm;
+/// On-disk encoding of lowered code
+pub mod bytecode;
+/// Peephole optimizer for lowered code
+pub mod peephole;
+/// Threaded code execution backend
+pub mod threaded;
+
+pub use error::{ErrorLocation, InterpreteError};
+use tape::Tape;
 
 /// Types which can be passed as stdin to [`Interpreter`]
 pub trait InterprIOIn: std::fmt::Debug {
     /// Execute getchar bf instruction 
+    /// # Errors
+    /// return `Err` if reading from underlying stream fails
     fn getchar(&mut self) -> std::io::Result<u8>;
+    /// Execute getchar bf instruction at `step` (count of instructions executed before)
+    ///
+    /// Called by [`Interpreter`] instead of [`InterprIOIn::getchar`]
+    /// # Errors
+    /// return `Err` if reading from underlying stream fails
+    #[inline]
+    fn getchar_at(&mut self, step: u64) -> std::io::Result<u8> {
+        let _ = step;
+        self.getchar()
+    }
+    /// Check if next getchar can return without waiting for underlying stream
+    ///
+    /// [`Interpreter`] flushes output before Getchar if this returns `false`
+    #[inline]
+    fn has_buffered_input(&self) -> bool {
+        false
+    }
 }
 /// Types which can be passed as stdout to [`Interpreter`]
 pub trait InterprIOOut: std::fmt::Debug {
     /// Execute putchar bf instruction 
+    /// # Errors
+    /// return `Err` if writing to underlying stream fails
     fn putchar(&mut self, ch: u8) -> std::io::Result<()>;
+    /// Execute putchar bf instruction at `step` (count of instructions executed before)
+    ///
+    /// Called by [`Interpreter`] instead of [`InterprIOOut::putchar`]
+    /// # Errors
+    /// return `Err` if writing to underlying stream fails
+    #[inline]
+    fn putchar_at(&mut self, ch: u8, step: u64) -> std::io::Result<()> {
+        let _ = step;
+        self.putchar(ch)
+    }
     /// Flush output
+    /// # Errors
+    /// return `Err` if writing to underlying stream fails
     fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    /// Flush output at end of program, including incomplete data (like partial UTF-8 char)
+    ///
+    /// Unlike [`InterprIOOut::flush`] it's called by [`Interpreter`] only after program stops
+    /// # Errors
+    /// return `Err` if writing to underlying stream fails
+    #[inline]
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.flush()
+    }
 }
 
 // default implementations
@@ -147,93 +309,53 @@ impl<T: std::io::Write + std::fmt::Debug> InterprIOOut for T {
     }
 }
 
-/// Wrapper for output streams that help group bytes into utf-8 chars 
-#[derive(Debug)]
-pub struct DefaultWriter<T: std::io::Write + std::fmt::Debug> 
-{
-    inner: T,
-    buf: [u8; 8],
-    buf_i: usize
-}
-
-impl<T: std::io::Write + std::fmt::Debug> DefaultWriter<T> {
-    /// Create new writer
-    pub fn new(inner: T) -> Self {
-        Self {
-            inner,
-            buf: [0; 8],
-            buf_i: 0
-        }
-    }
-
-    fn write_byte(&mut self, v: u8) -> std::io::Result<()> {
-        if v.is_ascii() {
-            if self.buf_i != 0 {
-                // ascii char can't be a valid continuation of utf-8 sequence 
-                self.force_write()?;
-            }
-            self.inner.write_all(&[v])
-        } else {
-            self.buf[self.buf_i] = v;
-            self.buf_i += 1;
-            self.try_write()
-        }
-    }
-
-    fn try_write(&mut self) -> std::io::Result<()> {
-        if self.buf_i == 7 || std::str::from_utf8(&self.buf[..self.buf_i]).is_ok() {
-            self.force_write()
-        } else {
-            Ok(())
-        }
-    }
-    fn force_write(&mut self) -> std::io::Result<()> {
-        if std::str::from_utf8(&self.buf[..self.buf_i]).is_ok() {
-            self.inner.write_all(&self.buf[..self.buf_i])?;
-        } else {
-            for _ in 0..self.buf_i {
-                self.inner.write_all(&[b' '])?; // fill with empty symbol instead of invalid utf-8
-            }
-        }
-        self.buf_i = 0;
-        Ok(())
-    }
-}
-
-impl<T: std::io::Write + std::fmt::Debug> InterprIOOut for DefaultWriter<T> {
-    fn putchar(&mut self, ch: u8) -> std::io::Result<()> {
-        self.write_byte(ch)
-    }
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.force_write()?;
-        self.inner.flush()
-    }
-}
-
-// TODO default reader (read as chars and send as bytes)?
+use buffered::{BufferedInput, BufferedOutput};
+use std::io::IsTerminal;
 
-// TODO ascii-only reader|writer?
+pub use text_io::{DefaultReader, DefaultWriter, InvalidUtf8Policy, NonAsciiPolicy};
 
 /// Interpreter
 #[derive(Debug)]
-pub struct Interpreter<'a> {
+pub struct Interpreter<'a, T: Tape = Vec<u8>> {
     /// Data tape for interpreter
-    pub tape: Vec<u8>,
+    pub tape: T,
     /// Current pointer location on tape
     pub data_pointer: usize,
+    /// Index of next instruction to execute
+    pub ip: usize,
+    /// Offset of cell used by `jmp_t`/`jmp_f` and `*_move` instructions
+    pub input_offset: u32,
+    /// Count of executed instructions since interpreter creation or [`Interpreter::reset`]
+    pub steps: u64,
+    /// Count of bytes read by Getchar instructions
+    pub input_pos: u64,
+    /// Count of bytes written by Putchar instructions
+    pub output_pos: u64,
     /// input for Getchar instuction
     pub io_in: Box<dyn InterprIOIn + 'a>,
     /// output for Putchar instuction
     pub io_out: Box<dyn InterprIOOut + 'a>,
+    /// Execution counters (if profiling enabled)
+    pub profile: Option<profile::Profile>,
+    /// Instruction dispatch strategy
+    pub backend: threaded::Backend,
+    /// Code compiled by [`threaded::Backend::Threaded`] in last call
+    compiled: Option<threaded::Compiled<T>>,
 }
 
 /// Builder for [`Interpreter`]
 #[derive(Debug)]
-pub struct InterpreterBuilder<'a> {
+pub struct InterpreterBuilder<'a, T: Tape = Vec<u8>> {
+    /// data tape
+    tape: T,
     /// input for Getchar instuction
     io_in: Box<dyn InterprIOIn + 'a>,
     /// output for Putchar instuction
     io_out: Box<dyn InterprIOOut + 'a>,
+    /// collect execution counters
+    profiling: bool,
+    /// instruction dispatch strategy
+    backend: threaded::Backend,
 }
 
 impl<'a> Interpreter<'a> {
@@ -242,11 +364,20 @@ impl<'a> Interpreter<'a> {
     pub fn builder() -> InterpreterBuilder<'a> {
         InterpreterBuilder::new()
     }
-    /// Clear interpreter's tape & set data pointer to 0
+}
+
+impl<T: Tape> Interpreter<'_, T> {
+    /// Clear interpreter's tape & set data pointer and all counters to 0
     #[inline]
     pub fn reset(&mut self) {
         self.data_pointer = 0;
-        self.tape.clear();
+        Tape::clear(&mut self.tape);
+        self.ip = 0;
+        self.input_offset = 0;
+        self.steps = 0;
+        self.input_pos = 0;
+        self.output_pos = 0;
+        self.compiled = None;
     }
 }
 
@@ -262,18 +393,34 @@ impl<'a> InterpreterBuilder<'a> {
     #[inline]
     pub fn new() -> Self {
         Self {
-            io_in: Box::from(std::io::stdin()),
-            io_out: Box::from(DefaultWriter::new(std::io::stdout())),
+            io_in: Box::from(DefaultReader::new(BufferedInput::new(std::io::stdin()))),
+            io_out: Box::from(DefaultWriter::new(
+                BufferedOutput::new(std::io::stdout()).line_flush(std::io::stdout().is_terminal()),
+            )),
+            profiling: false,
+            backend: threaded::Backend::Match,
+            tape: Vec::new(),
         }
     }
+}
+
+impl<'a, T: Tape> InterpreterBuilder<'a, T> {
     /// finish building [`Interpreter`] and return result
     #[inline]
-    pub fn build(self) -> Interpreter<'a> {
+    pub fn build(self) -> Interpreter<'a, T> {
         Interpreter {
-            tape: Vec::new(),
+            tape: self.tape,
             data_pointer: 0,
+            ip: 0,
+            input_offset: 0,
+            steps: 0,
+            input_pos: 0,
+            output_pos: 0,
             io_in: self.io_in,
             io_out: self.io_out,
+            profile: self.profiling.then(profile::Profile::new),
+            backend: self.backend,
+            compiled: None,
         }
     }
     /// set input stream
@@ -288,6 +435,29 @@ impl<'a> InterpreterBuilder<'a> {
         self.io_out = Box::from(io_out);
         self
     }
+    /// set tape backend (default is [`Vec<u8>`], see [`tape::PagedTape`] for sparse tape)
+    #[inline]
+    pub fn set_tape<U: Tape>(self, tape: U) -> InterpreterBuilder<'a, U> {
+        InterpreterBuilder {
+            tape,
+            io_in: self.io_in,
+            io_out: self.io_out,
+            profiling: self.profiling,
+            backend: self.backend,
+        }
+    }
+    /// collect [`profile::Profile`] during execution
+    #[inline]
+    pub const fn enable_profiling(mut self) -> Self {
+        self.profiling = true;
+        self
+    }
+    /// set instruction dispatch strategy (see [`threaded::Backend`])
+    #[inline]
+    pub const fn set_backend(mut self, backend: threaded::Backend) -> Self {
+        self.backend = backend;
+        self
+    }
 }
 
 impl Default for InterpreterBuilder<'_> {
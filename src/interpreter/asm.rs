@@ -0,0 +1,300 @@
+use super::{InterpCode, InterpIns};
+use std::collections::HashMap;
+
+/// Error type of [`parse_asm`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmParseError {
+    /// Mnemonic isn't name of any instruction
+    UnknownInstruction {
+        /// line number (starts from 1)
+        line: usize,
+    },
+    /// Wrong count or format of instruction operands
+    InvalidOperand {
+        /// line number (starts from 1)
+        line: usize,
+    },
+    /// Jump to label which isn't defined
+    UndefinedLabel {
+        /// line number (starts from 1)
+        line: usize,
+        /// label name
+        label: String,
+    },
+    /// Label defined more than once
+    DuplicateLabel {
+        /// line number (starts from 1)
+        line: usize,
+        /// label name
+        label: String,
+    },
+    /// [`InterpIns::Unchecked`] marker, it's claims can't be verified
+    UncheckedRegion {
+        /// line number (starts from 1)
+        line: usize,
+    },
+}
+
+impl std::fmt::Display for AsmParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownInstruction { line } => write!(f, "line {line}: unknown instruction"),
+            Self::InvalidOperand { line } => write!(f, "line {line}: invalid operands"),
+            Self::UndefinedLabel { line, label } => {
+                write!(f, "line {line}: undefined label `{label}`")
+            }
+            Self::DuplicateLabel { line, label } => {
+                write!(f, "line {line}: label `{label}` is already defined")
+            }
+            Self::UncheckedRegion { line } => write!(f, "line {line}: unchecked region"),
+        }
+    }
+}
+
+impl std::error::Error for AsmParseError {}
+
+/// Parse [`InterpCode`] from it's text form
+///
+/// One instruction per line, in same syntax as [`InterpCode`] `Display` output.
+/// Jump destination is `'` followed by instruction index or by label.
+/// Label is defined by `name:` before instruction (or on it's own line),
+/// text after `;` is comment.
+///
+/// `unchecked` markers are rejected, because interpreter trusts them
+/// (so text of code lowered by [`super::bf2interp`] can't be parsed back)
+/// ```
+/// # use bf_tools::interpreter::{ asm::{ parse_asm, AsmParseError }, Interpreter };
+/// let code = parse_asm("
+///     add 3, [0]
+///     loop:
+///         ptr_add 1
+///         add 2, [0]          ; cells[1] += 2
+///         ptr_sub 1
+///         sub 1, [0]
+///         jmp_t [input_offset], 'loop
+/// ").unwrap();
+/// let mut interpreter = Interpreter::default();
+/// interpreter.run(code.clone()).unwrap();
+/// assert_eq!(interpreter.tape[..2], [0, 6]);
+///
+/// // `Display` output is parsed back to same code
+/// assert_eq!(parse_asm(&code.to_string()), Ok(code.clone()));
+/// assert_eq!(parse_asm(&code.with_labels().to_string()), Ok(code));
+///
+/// // false claim would let `ptr_sub` go below tape start
+/// let err = parse_asm("unchecked 1, [0]\nptr_sub 3");
+/// assert_eq!(err, Err(AsmParseError::UncheckedRegion { line: 1 }));
+/// ```
+/// # Errors
+/// return `Err` if line isn't valid instruction, jump label is undefined
+/// or code contains `unchecked` marker
+pub fn parse_asm(s: &str) -> Result<InterpCode, AsmParseError> {
+    // (line number, instruction text)
+    let mut lines = Vec::new();
+    let mut labels = HashMap::new();
+    for (line, text) in s.lines().enumerate() {
+        let line = line + 1;
+        let mut text = text.split(';').next().unwrap_or_default().trim();
+        while let Some((label, rest)) = split_label(text) {
+            if labels.insert(label, lines.len()).is_some() {
+                return Err(AsmParseError::DuplicateLabel {
+                    line,
+                    label: label.to_string(),
+                });
+            }
+            text = rest.trim_start();
+        }
+        if !text.is_empty() {
+            lines.push((line, text));
+        }
+    }
+    lines
+        .into_iter()
+        .map(|(line, text)| parse_ins(text, line, &labels))
+        .collect::<Result<_, _>>()
+        .map(InterpCode)
+}
+
+/// Split `name:` from start of line
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (label, rest) = text.split_once(':')?;
+    is_label(label).then_some((label, rest))
+}
+
+fn is_label(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_ins(
+    text: &str,
+    line: usize,
+    labels: &HashMap<&str, usize>,
+) -> Result<InterpIns, AsmParseError> {
+    let (name, args) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let args: Vec<_> = args
+        .split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .collect();
+    let invalid = || AsmParseError::InvalidOperand { line };
+    let num = |arg: &str| arg.parse::<u32>().map_err(|_| invalid());
+    let val = |arg: &str| arg.parse::<u8>().map_err(|_| invalid());
+    let cell = |arg: &str| {
+        arg.strip_prefix('[')
+            .and_then(|a| a.strip_suffix(']'))
+            .ok_or_else(invalid)
+            .and_then(num)
+    };
+    let input = |arg: &str| (arg == "[input_offset]").then_some(()).ok_or_else(invalid);
+    let input_mul = |arg: &str| {
+        arg.strip_prefix("[input_offset]*")
+            .ok_or_else(invalid)
+            .and_then(val)
+    };
+    let dest = |arg: &str| {
+        let target = arg.strip_prefix('\'').ok_or_else(invalid)?;
+        if let Ok(dest) = target.parse::<u32>() {
+            return Ok(dest);
+        }
+        if !is_label(target) {
+            return Err(invalid());
+        }
+        labels
+            .get(target)
+            .map(|ip| *ip as u32)
+            .ok_or_else(|| AsmParseError::UndefinedLabel {
+                line,
+                label: target.to_string(),
+            })
+    };
+    let ins = match (name, args.as_slice()) {
+        ("set", [v, o]) => InterpIns::Set {
+            val: val(v)?,
+            offset: cell(o)?,
+        },
+        ("add", [v, o]) => InterpIns::Add {
+            val: val(v)?,
+            offset: cell(o)?,
+        },
+        ("sub", [v, o]) => InterpIns::Sub {
+            val: val(v)?,
+            offset: cell(o)?,
+        },
+        ("mul", [v, o]) => InterpIns::Mul {
+            val: val(v)?,
+            offset: cell(o)?,
+        },
+        ("ptr_add", [o]) => InterpIns::PtrAdd { offset: num(o)? },
+        ("ptr_sub", [o]) => InterpIns::PtrSub { offset: num(o)? },
+        ("set_input_offset", [o]) => InterpIns::SetInputOffset {
+            new_input_offset: num(o)?,
+        },
+        ("add_move", [m, t]) => InterpIns::AddMove {
+            mul: input_mul(m)?,
+            to: cell(t)?,
+        },
+        ("sub_move", [m, t]) => InterpIns::SubMove {
+            mul: input_mul(m)?,
+            to: cell(t)?,
+        },
+        ("mul_move", [m, t]) => InterpIns::MulMove {
+            mul: input_mul(m)?,
+            to: cell(t)?,
+        },
+        ("move", [i, t]) => {
+            input(i)?;
+            InterpIns::Move { to: cell(t)? }
+        }
+        ("copy", [i, t]) => {
+            input(i)?;
+            InterpIns::Copy { to: cell(t)? }
+        }
+        ("putchar", [o]) => InterpIns::Putchar { offset: cell(o)? },
+        ("getchar", [o]) => InterpIns::Getchar { offset: cell(o)? },
+        ("jmp_t", [i, d]) => {
+            input(i)?;
+            InterpIns::JmpT { dest: dest(d)? }
+        }
+        ("jmp_f", [i, d]) => {
+            input(i)?;
+            InterpIns::JmpF { dest: dest(d)? }
+        }
+        ("jmp", [d]) => InterpIns::Jmp { dest: dest(d)? },
+        ("unchecked", _) => return Err(AsmParseError::UncheckedRegion { line }),
+        (
+            "set" | "add" | "sub" | "mul" | "ptr_add" | "ptr_sub" | "set_input_offset" | "add_move"
+            | "sub_move" | "mul_move" | "move" | "copy" | "putchar" | "getchar" | "jmp_t" | "jmp_f"
+            | "jmp",
+            _,
+        ) => return Err(invalid()),
+        _ => return Err(AsmParseError::UnknownInstruction { line }),
+    };
+    Ok(ins)
+}
+
+impl std::str::FromStr for InterpCode {
+    type Err = AsmParseError;
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_asm(s)
+    }
+}
+
+/// [`InterpCode`] text with `L<index>:` labels at jump targets
+/// (returned by [`InterpCode::with_labels`])
+#[derive(Debug, Clone, Copy)]
+pub struct WithLabels<'a>(&'a InterpCode);
+
+impl InterpCode {
+    /// Display code with symbolic jump labels instead of instruction indices
+    /// ```
+    /// # use bf_tools::{ bf, interpreter::InterpCode };
+    /// let code = InterpCode::from(bf!(,[.,]));
+    /// assert!(code.with_labels().to_string().contains("jmp_t [input_offset], 'L"));
+    /// ```
+    #[inline]
+    pub const fn with_labels(&self) -> WithLabels<'_> {
+        WithLabels(self)
+    }
+}
+
+impl std::fmt::Display for WithLabels<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let code = &self.0 .0;
+        let mut targets = vec![false; code.len() + 1];
+        for ins in code {
+            if let InterpIns::Jmp { dest } | InterpIns::JmpT { dest } | InterpIns::JmpF { dest } =
+                ins
+            {
+                if let Some(t) = targets.get_mut(*dest as usize) {
+                    *t = true;
+                }
+            }
+        }
+        for (ip, ins) in code.iter().enumerate() {
+            if targets[ip] {
+                writeln!(f, "L{ip}:")?;
+            }
+            match ins {
+                InterpIns::JmpT { dest } if targets.get(*dest as usize) == Some(&true) => {
+                    writeln!(f, "    jmp_t [input_offset], 'L{dest}")?
+                }
+                InterpIns::JmpF { dest } if targets.get(*dest as usize) == Some(&true) => {
+                    writeln!(f, "    jmp_f [input_offset], 'L{dest}")?
+                }
+                InterpIns::Jmp { dest } if targets.get(*dest as usize) == Some(&true) => {
+                    writeln!(f, "    jmp 'L{dest}")?
+                }
+                _ => writeln!(f, "    {ins}")?,
+            }
+        }
+        if targets[code.len()] {
+            writeln!(f, "L{}:", code.len())?;
+        }
+        Ok(())
+    }
+}
@@ -1,8 +1,13 @@
-use super::{InterpCode, InterpIns};
+use super::{
+    debug_info::{BlockInfo, BlockKind, DebugInfo},
+    InterpCode, InterpIns,
+};
 use crate::optimizer::{
+    bounds::{loop_head, Bounds, BoundsTable, PtrRange},
     opt_ins::{OptBlock, IOOptIns},
     OptCode,
 };
+use std::iter::Peekable;
 
 /*
     bf_to_interp matches:
@@ -15,15 +20,178 @@ use crate::optimizer::{
 #[doc(hidden)]
 #[inline]
 pub fn bf_to_interp(code: impl Into<OptCode>) -> InterpCode {
-    let code: OptCode = code.into();
-    let ret = bf_to_interp_translate_impl(code);
-    //TODO remove useless repeating like "SetInputOffset 0"
-    InterpCode(ret)
+    let mut code = bf_to_interp_with_debug_info(code).0;
+    super::peephole::optimize(&mut code);
+    code
+}
+
+/// Same as [`bf_to_interp`] but also return [`DebugInfo`] for lowered code
+///
+/// Peephole pass isn't applied, so every block keeps it's own instructions
+/// (use [`super::peephole::optimize_with_debug_info`] if profiling isn't needed)
+/// ```
+/// # use bf_tools::{ bf, interpreter::{ bf2interp::bf_to_interp_with_debug_info, debug_info::BlockKind } };
+/// let (code, debug) = bf_to_interp_with_debug_info(bf!(+[>+<-].));
+/// assert_eq!(debug.ins_blocks.len(), code.0.len());
+/// assert_eq!(debug.blocks[1].kind, BlockKind::Loop);
+/// assert_eq!(debug.blocks[2].parent, Some(1));
+/// ```
+pub fn bf_to_interp_with_debug_info(code: impl Into<OptCode>) -> (InterpCode, DebugInfo) {
+    let mut code: OptCode = code.into();
+    let mut blocks = Vec::new();
+    let (ret, ins_blocks) = bf_to_interp_translate_impl(std::mem::take(&mut code.0), &mut blocks);
+    (InterpCode(ret), DebugInfo { ins_blocks, blocks })
+}
+
+/// Register all blocks of `code` (which is unreachable and won't be lowered)
+fn skip_blocks(code: &[OptBlock], parent: Option<usize>, blocks: &mut Vec<BlockInfo>) {
+    let mut stack = vec![(code.iter(), parent)];
+    while let Some((iter, parent)) = stack.last_mut() {
+        let parent = *parent;
+        let Some(bl) = iter.next() else {
+            stack.pop();
+            continue;
+        };
+        let id = blocks.len();
+        blocks.push(BlockInfo::new(parent, BlockKind::of(bl)));
+        if let OptBlock::Loop(inner) = bl {
+            stack.push((inner.0.iter(), Some(id)));
+        }
+    }
+}
+
+fn is_deadloop(mut block: &OptBlock) -> bool {
+    loop {
+        match block {
+            OptBlock::Loop(inner) => match inner.0.as_slice() {
+                [] => return true,
+                [inner @ OptBlock::Loop(_)] => block = inner,
+                _ => return false,
+            },
+            _ => return false,
+        }
+    }
+}
+
+/// Check if block can be lowered into [`InterpIns::Unchecked`] region
+/// when executed with data pointer in `entry` range
+fn is_provably_safe(block: &OptBlock, bounds: Bounds, entry: PtrRange) -> bool {
+    !is_deadloop(block)
+        && bounds.access.max.is_some_and(|max| u32::try_from(max).is_ok())
+        && bounds.at(entry).access.is_non_negative()
+}
+
+/// What to finish after all blocks of [`Frame`] are lowered
+#[derive(Debug, Clone, Copy)]
+enum Cont {
+    /// Whole program
+    Root,
+    /// [`InterpIns::Unchecked`] region with marker at `marker`
+    Region { marker: usize, bounds: Bounds },
+    /// Body of loop `id` which starts at `body_beg`
+    Loop { id: usize, body_beg: usize },
+}
+
+/// Lowering state of single sequence of blocks
+///
+/// Code executed with data pointer in `entry` range.
+/// When `checked` is set, runs of blocks proven to stay inside tape are wrapped into [`InterpIns::Unchecked`] regions
+struct Frame {
+    code: Peekable<std::vec::IntoIter<OptBlock>>,
+    parent: Option<usize>,
+    entry: PtrRange,
+    checked: bool,
+    cont: Cont,
+}
+
+impl Frame {
+    fn new(code: Vec<OptBlock>, parent: Option<usize>, entry: PtrRange, checked: bool, cont: Cont) -> Self {
+        Self {
+            code: code.into_iter().peekable(),
+            parent,
+            entry,
+            checked,
+            cont,
+        }
+    }
 }
 
-fn bf_to_interp_translate_impl(code: OptCode) -> Vec<InterpIns> {
+/// Lower `code`, loops and regions are lowered with explicit stack of [`Frame`]s
+///
+/// Block ids are assigned in pre-order, so they are also indices in [`BoundsTable`].
+/// All code is pushed into single buffer, forward jumps and region lengths are patched when frame is finished
+fn bf_to_interp_translate_impl(
+    code: Vec<OptBlock>,
+    blocks: &mut Vec<BlockInfo>,
+) -> (Vec<InterpIns>, Vec<usize>) {
+    let table = BoundsTable::new(&code);
     let mut ret = Vec::new();
-    for bl in code.0 {
+    let mut ins_blocks = Vec::new();
+    // data pointer is never negative before execution
+    let mut cur = Frame::new(code, None, PtrRange::point(0), true, Cont::Root);
+    // enclosing loops and regions
+    let mut stack = Vec::new();
+    loop {
+        let Some(bl) = cur.code.next() else {
+            let Some(parent) = stack.pop() else {
+                return (ret, ins_blocks);
+            };
+            match std::mem::replace(&mut cur, parent).cont {
+                Cont::Root => unreachable!("root frame is never on stack"),
+                Cont::Region { marker, bounds } => {
+                    let region_len = (ret.len() - marker - 1) as u32;
+                    if let InterpIns::Unchecked { len, .. } = &mut ret[marker] {
+                        *len = region_len;
+                    }
+                    cur.entry = bounds.at(cur.entry).exit;
+                }
+                Cont::Loop { id, body_beg } => {
+                    ret.push(InterpIns::SetInputOffset {
+                        new_input_offset: 0,
+                    });
+                    ret.push(InterpIns::JmpT {
+                        dest: body_beg as u32,
+                    });
+                    let loop_body_end = ret.len();
+                    if let InterpIns::JmpF { dest } = &mut ret[body_beg - 1] {
+                        *dest = loop_body_end as u32;
+                    }
+                    ins_blocks.resize(ret.len(), id);
+                }
+            }
+            continue;
+        };
+        let id = blocks.len();
+        let bounds = table.blocks[id].bounds;
+        if cur.checked && is_provably_safe(&bl, bounds, cur.entry) {
+            let mut region_bounds = bounds;
+            let mut region = vec![bl];
+            let mut next_id = id + table.blocks[id].size;
+            while let Some(next) = cur.code.peek() {
+                let next_bounds = table.blocks[next_id].bounds;
+                if !is_provably_safe(next, next_bounds, region_bounds.at(cur.entry).exit) {
+                    break;
+                }
+                region_bounds = region_bounds.then(next_bounds);
+                next_id += table.blocks[next_id].size;
+                region.extend(cur.code.next());
+            }
+            let cont = Cont::Region {
+                marker: ret.len(),
+                bounds: region_bounds,
+            };
+            ret.push(InterpIns::Unchecked {
+                len: 0,
+                max_offset: region_bounds.access.max.unwrap_or_default() as u32,
+            });
+            ins_blocks.push(id);
+            let inner = Frame::new(region, cur.parent, cur.entry, false, cont);
+            stack.push(std::mem::replace(&mut cur, inner));
+            continue;
+        }
+        let loop_entry = cur.entry;
+        cur.entry = bounds.at(cur.entry).exit;
+        blocks.push(BlockInfo::new(cur.parent, BlockKind::of(&bl)));
         match bl {
             OptBlock::Block(inner) => {
                 let max_ptr_offset = inner
@@ -83,10 +251,16 @@ fn bf_to_interp_translate_impl(code: OptCode) -> Vec<InterpIns> {
             }
 
             OptBlock::Loop(mut inner) => {
+                // block id of innermost loop of `[[...]]` chain
+                let mut inner_id = id;
+                let mut head = loop_head(loop_entry, table.blocks[id].body);
                 while matches!(inner.0.as_slice(), [OptBlock::Loop(_)]) {
-                    match inner.0.into_iter().next() {
+                    match std::mem::take(&mut inner.0).into_iter().next() {
                         Some(OptBlock::Loop(new_inner)) => {
+                            inner_id = blocks.len();
+                            blocks.push(BlockInfo::new(Some(inner_id - 1), BlockKind::Loop));
                             inner = new_inner;
+                            head = loop_head(head, table.blocks[inner_id].body);
                         }
                         _ => unreachable!(),
                     }
@@ -97,7 +271,10 @@ fn bf_to_interp_translate_impl(code: OptCode) -> Vec<InterpIns> {
                     [] => {
                         let at = ret.len();
                         ret.push(InterpIns::Jmp { dest: at as u32 }); //TODO indicate in some way about deadloop?
-                        break;
+                        ins_blocks.resize(ret.len(), id);
+                        let rest: Vec<_> = cur.code.by_ref().collect();
+                        skip_blocks(&rest, cur.parent, blocks);
+                        continue;
                     }
                     // [-] or [+]
                     [OptBlock::Block(inner)]
@@ -107,6 +284,7 @@ fn bf_to_interp_translate_impl(code: OptCode) -> Vec<InterpIns> {
                                 *v == 1 || *v == 255
                             }).unwrap_or_default() =>
                     {
+                        blocks.push(BlockInfo::new(Some(inner_id), BlockKind::Block));
                         ret.push(InterpIns::Set { val: 0, offset: 0 });
                     }
                     // something like [>++<-]
@@ -115,6 +293,7 @@ fn bf_to_interp_translate_impl(code: OptCode) -> Vec<InterpIns> {
                             && inner.ptr_offset == 0
                             && inner.ins.iter().any(|(pos, val)| *pos == 0 && (*val == 1 || *val == 2)) =>
                     {
+                        blocks.push(BlockInfo::new(Some(inner_id), BlockKind::Block));
                         let mut offset = 0;
                         let mut is_add = false;
                         let mut mul = 1;
@@ -152,37 +331,25 @@ fn bf_to_interp_translate_impl(code: OptCode) -> Vec<InterpIns> {
                     }
                     // TODO matcher for multiplication
                     _ => {
-                        let mut inner = bf_to_interp_translate_impl(inner);
-                        let loop_body_end = ret.len() + 4 + inner.len();
                         ret.push(InterpIns::SetInputOffset {
                             new_input_offset: 0,
                         });
-                        ret.push(InterpIns::JmpF {
-                            dest: loop_body_end as u32,
-                        });
-                        let loop_body_beg = ret.len();
-                        //update jump location
-                        inner.iter_mut().for_each(|ins| match ins {
-                            InterpIns::Jmp { dest }
-                            | InterpIns::JmpT { dest }
-                            | InterpIns::JmpF { dest } => {
-                                *dest += loop_body_beg as u32;
-                            }
-                            _ => {}
-                        });
-                        ret.append(&mut inner);
-                        ret.push(InterpIns::SetInputOffset {
-                            new_input_offset: 0,
-                        });
-                        ret.push(InterpIns::JmpT {
-                            dest: loop_body_beg as u32,
-                        });
+                        // destination is set when loop body is lowered
+                        ret.push(InterpIns::JmpF { dest: 0 });
+                        let body_beg = ret.len();
+                        ins_blocks.resize(body_beg, id);
+                        let body = std::mem::take(&mut inner.0);
+                        let cont = Cont::Loop { id, body_beg };
+                        let inner = Frame::new(body, Some(inner_id), head, cur.checked, cont);
+                        stack.push(std::mem::replace(&mut cur, inner));
+                        continue;
                     }
                 }
             }
         }
+        // every instruction pushed in this iteration lowered from block `id`
+        ins_blocks.resize(ret.len(), id);
     }
-    ret
 }
 
 impl<T: Into<OptCode>> From<T> for InterpCode {
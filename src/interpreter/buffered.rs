@@ -0,0 +1,167 @@
+use super::InterprIOIn;
+use std::io::{Read, Write};
+
+const DEFAULT_CAPACITY: usize = 8 * 1024;
+
+/// Output stream that collects bytes and writes them to `inner` in large chunks
+///
+/// Buffer is written when it's full, on [`Write::flush`] and on drop.
+/// In line mode (for interactive programs) buffer is also written after every newline
+/// ```
+/// # use bf_tools::interpreter::{ buffered::BufferedOutput, InterprIOOut };
+/// let mut out = Vec::new();
+/// let mut writer = BufferedOutput::new(&mut out).line_flush(true);
+/// for b in *b"hi\nthere" {
+///     writer.putchar(b).unwrap();
+/// }
+/// assert_eq!(writer.buffer(), b"there");
+/// # drop(writer);
+/// assert_eq!(out, b"hi\nthere");
+/// ```
+#[derive(Debug)]
+pub struct BufferedOutput<T: Write> {
+    inner: T,
+    buf: Vec<u8>,
+    capacity: usize,
+    line_flush: bool,
+}
+
+impl<T: Write> BufferedOutput<T> {
+    /// Create new buffered stream with default capacity (8 KiB)
+    #[inline]
+    pub fn new(inner: T) -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY, inner)
+    }
+    /// Create new buffered stream with `capacity` bytes buffer
+    pub fn with_capacity(capacity: usize, inner: T) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            inner,
+            buf: Vec::with_capacity(capacity),
+            capacity,
+            line_flush: false,
+        }
+    }
+    /// Write buffer after every newline
+    #[inline]
+    #[must_use]
+    pub const fn line_flush(mut self, enable: bool) -> Self {
+        self.line_flush = enable;
+        self
+    }
+    /// Bytes not written to underlying stream yet
+    #[inline]
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+    /// Underlying stream
+    #[inline]
+    pub const fn get_ref(&self) -> &T {
+        &self.inner
+    }
+    fn write_buf(&mut self) -> std::io::Result<()> {
+        if !self.buf.is_empty() {
+            let res = self.inner.write_all(&self.buf);
+            self.buf.clear();
+            res?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Write> Write for BufferedOutput<T> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        let (head, tail) = match data.iter().rposition(|b| *b == b'\n') {
+            Some(i) if self.line_flush => data.split_at(i + 1),
+            _ => (&[][..], data),
+        };
+        if !head.is_empty() {
+            self.buf.extend_from_slice(head);
+            self.write_buf()?;
+        }
+        if self.buf.len() + tail.len() > self.capacity {
+            self.write_buf()?;
+        }
+        if tail.len() >= self.capacity {
+            self.inner.write_all(tail)?;
+        } else {
+            self.buf.extend_from_slice(tail);
+        }
+        Ok(data.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.write_buf()?;
+        self.inner.flush()
+    }
+}
+
+impl<T: Write> Drop for BufferedOutput<T> {
+    fn drop(&mut self) {
+        // errors can't be reported from drop, use `flush` to check them
+        let _ = self.write_buf();
+    }
+}
+
+/// Input stream that reads `inner` in large chunks
+///
+/// Reports buffered bytes with [`InterprIOIn::has_buffered_input`],
+/// so [`super::Interpreter`] flushes output only before Getchar which can block
+/// ```
+/// # use bf_tools::interpreter::{ buffered::BufferedInput, InterprIOIn };
+/// let mut reader = BufferedInput::new(&b"ab"[..]);
+/// assert!(!reader.has_buffered_input());
+/// assert_eq!(reader.getchar().unwrap(), b'a');
+/// assert!(reader.has_buffered_input());
+/// assert_eq!(reader.getchar().unwrap(), b'b');
+/// assert!(reader.getchar().is_err());
+/// ```
+#[derive(Debug)]
+pub struct BufferedInput<T: Read> {
+    inner: T,
+    buf: Box<[u8]>,
+    pos: usize,
+    len: usize,
+}
+
+impl<T: Read> BufferedInput<T> {
+    /// Create new buffered stream with default capacity (8 KiB)
+    #[inline]
+    pub fn new(inner: T) -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY, inner)
+    }
+    /// Create new buffered stream with `capacity` bytes buffer
+    pub fn with_capacity(capacity: usize, inner: T) -> Self {
+        Self {
+            inner,
+            buf: vec![0; capacity.max(1)].into_boxed_slice(),
+            pos: 0,
+            len: 0,
+        }
+    }
+    /// Get underlying stream (buffered bytes are lost)
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Read + std::fmt::Debug> InterprIOIn for BufferedInput<T> {
+    fn getchar(&mut self) -> std::io::Result<u8> {
+        while self.pos == self.len {
+            self.pos = 0;
+            self.len = 0;
+            match self.inner.read(&mut self.buf) {
+                Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                Ok(len) => self.len = len,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        self.pos += 1;
+        Ok(self.buf[self.pos - 1])
+    }
+    #[inline]
+    fn has_buffered_input(&self) -> bool {
+        self.pos < self.len
+    }
+}
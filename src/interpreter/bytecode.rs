@@ -0,0 +1,559 @@
+use super::{InterpCode, InterpIns};
+
+/// First bytes of every bytecode file
+pub const MAGIC: [u8; 4] = *b"BFC\0";
+/// Instruction set version written by [`Bytecode::to_bytes`] (the only supported one)
+pub const VERSION: u16 = 1;
+/// Cell width in bits (the only supported one)
+pub const CELL_BITS: u8 = 8;
+
+/// Kind of tape program expects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TapeKind {
+    /// Contiguous tape ([`Vec<u8>`])
+    #[default]
+    Contiguous,
+    /// Sparse tape ([`super::tape::PagedTape`])
+    Paged,
+}
+
+/// Tape settings stored in bytecode header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct TapeConfig {
+    /// Tape backend
+    pub kind: TapeKind,
+    /// Tape memory limit in bytes
+    pub memory_limit: Option<u64>,
+}
+
+/// Error type of [`Bytecode::from_bytes`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BytecodeError {
+    /// Data doesn't start with [`MAGIC`]
+    BadMagic,
+    /// Instruction set version isn't supported
+    UnsupportedVersion {
+        /// version from header
+        version: u16,
+    },
+    /// Cell width isn't supported
+    UnsupportedCellWidth {
+        /// cell width from header
+        bits: u8,
+    },
+    /// Unknown tape kind in header
+    InvalidTapeConfig,
+    /// Unknown instruction opcode
+    UnknownOpcode {
+        /// byte position of opcode
+        pos: usize,
+        /// opcode
+        opcode: u8,
+    },
+    /// Operand doesn't fit into it's type
+    InvalidOperand {
+        /// byte position of operand
+        pos: usize,
+    },
+    /// Data ends in the middle of header or instruction
+    UnexpectedEnd,
+    /// Data continues after last instruction
+    TrailingBytes {
+        /// byte position after last instruction
+        pos: usize,
+    },
+    /// Code contains [`InterpIns::Unchecked`] marker, it's claims can't be verified
+    UncheckedRegion {
+        /// byte position of marker
+        pos: usize,
+    },
+    /// Decoded code is rejected by [`verify`]
+    Invalid(VerifyError),
+}
+
+impl std::fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a bytecode file"),
+            Self::UnsupportedVersion { version } => {
+                write!(f, "unsupported instruction set version {version}")
+            }
+            Self::UnsupportedCellWidth { bits } => write!(f, "unsupported cell width {bits}"),
+            Self::InvalidTapeConfig => write!(f, "invalid tape config"),
+            Self::UnknownOpcode { pos, opcode } => {
+                write!(f, "unknown opcode {opcode} at byte {pos}")
+            }
+            Self::InvalidOperand { pos } => write!(f, "invalid operand at byte {pos}"),
+            Self::UnexpectedEnd => write!(f, "unexpected end of bytecode"),
+            Self::TrailingBytes { pos } => {
+                write!(f, "trailing bytes after instructions at byte {pos}")
+            }
+            Self::UncheckedRegion { pos } => write!(f, "unchecked region at byte {pos}"),
+            Self::Invalid(err) => write!(f, "invalid code: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Invalid(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<VerifyError> for BytecodeError {
+    #[inline]
+    fn from(err: VerifyError) -> Self {
+        Self::Invalid(err)
+    }
+}
+
+/// Error type of [`verify`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyError {
+    /// Jump destination is after end of code
+    JumpOutOfRange {
+        /// index of jump
+        ip: usize,
+        /// jump destination
+        dest: u32,
+    },
+    /// Instruction uses `input_offset` which may be not set on some path
+    UndefinedInputOffset {
+        /// index of instruction
+        ip: usize,
+    },
+    /// `Unchecked` region exceeds code, is nested,
+    /// or is entered not through it's marker
+    InvalidRegion {
+        /// index of `Unchecked` marker
+        ip: usize,
+    },
+}
+
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::JumpOutOfRange { ip, dest } => {
+                write!(f, "ip {ip}: jump destination {dest} is out of code")
+            }
+            Self::UndefinedInputOffset { ip } => {
+                write!(
+                    f,
+                    "ip {ip}: input offset may be used before set_input_offset"
+                )
+            }
+            Self::InvalidRegion { ip } => write!(f, "ip {ip}: invalid unchecked region"),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+const fn jump_dest(ins: &InterpIns) -> Option<u32> {
+    match ins {
+        InterpIns::Jmp { dest } | InterpIns::JmpT { dest } | InterpIns::JmpF { dest } => {
+            Some(*dest)
+        }
+        _ => None,
+    }
+}
+
+/// Check that `code` can be executed by [`super::Interpreter`]
+///
+/// - all jumps lead inside code (or to it's end)
+/// - `input_offset` is set by [`InterpIns::SetInputOffset`] on every path to instruction which uses it
+/// - [`InterpIns::Unchecked`] regions fit into code, aren't nested
+///   and can be entered only through marker
+///
+/// Claims of `Unchecked` regions about accessed cells are trusted,
+/// so code from untrusted source shouldn't contain them
+/// ([`Bytecode::from_bytes`] rejects them)
+/// ```
+/// # use bf_tools::{ bf, interpreter::{ InterpCode, bytecode::{ verify, VerifyError } } };
+/// assert_eq!(verify(&InterpCode::from(bf!(+[>+<-]))), Ok(()));
+///
+/// let code: InterpCode = "jmp_t [input_offset], '0".parse().unwrap();
+/// assert_eq!(verify(&code), Err(VerifyError::UndefinedInputOffset { ip: 0 }));
+/// ```
+/// # Errors
+/// return `Err` with first found problem
+pub fn verify(code: &InterpCode) -> Result<(), VerifyError> {
+    let code = &code.0;
+    for (ip, ins) in code.iter().enumerate() {
+        if let Some(dest) = jump_dest(ins).filter(|d| *d as usize > code.len()) {
+            return Err(VerifyError::JumpOutOfRange { ip, dest });
+        }
+    }
+    verify_regions(code)?;
+    verify_input_offset(code)
+}
+
+fn verify_regions(code: &[InterpIns]) -> Result<(), VerifyError> {
+    let mut region = vec![None; code.len()];
+    for (ip, ins) in code.iter().enumerate() {
+        if let InterpIns::Unchecked { len, .. } = ins {
+            let end = ip + 1 + *len as usize;
+            if region[ip].is_some() || end > code.len() {
+                return Err(VerifyError::InvalidRegion { ip });
+            }
+            region[ip + 1..end].fill(Some((ip, end)));
+        }
+    }
+    for (ip, ins) in code.iter().enumerate() {
+        let Some(dest) = jump_dest(ins).map(|d| d as usize) else {
+            continue;
+        };
+        let dest_region = region.get(dest).copied().flatten();
+        let valid = match (region[ip], dest_region) {
+            // jump inside region or to it's end
+            (Some((start, end)), _) => start < dest && dest <= end,
+            (None, Some(_)) => false,
+            (None, None) => true,
+        };
+        if !valid {
+            let (marker, _) = region[ip].or(dest_region).unwrap_or_default();
+            return Err(VerifyError::InvalidRegion { ip: marker });
+        }
+    }
+    Ok(())
+}
+
+fn verify_input_offset(code: &[InterpIns]) -> Result<(), VerifyError> {
+    // `None` - not reached, `Some(false)` - not set on some path
+    let mut set = vec![None; code.len() + 1];
+    set[0] = Some(false);
+    let mut work = vec![0];
+    while let Some(ip) = work.pop() {
+        let (Some(ins), Some(before)) = (code.get(ip), set[ip]) else {
+            continue;
+        };
+        if !before
+            && matches!(
+                ins,
+                InterpIns::JmpT { .. }
+                    | InterpIns::JmpF { .. }
+                    | InterpIns::AddMove { .. }
+                    | InterpIns::SubMove { .. }
+                    | InterpIns::MulMove { .. }
+                    | InterpIns::Move { .. }
+                    | InterpIns::Copy { .. }
+            )
+        {
+            return Err(VerifyError::UndefinedInputOffset { ip });
+        }
+        let after = before || matches!(ins, InterpIns::SetInputOffset { .. });
+        let fallthrough = !matches!(ins, InterpIns::Jmp { .. });
+        let jump = jump_dest(ins).map(|d| d as usize);
+        for next in fallthrough.then_some(ip + 1).into_iter().chain(jump) {
+            let joined = set[next].map_or(after, |s: bool| s && after);
+            if set[next] != Some(joined) {
+                set[next] = Some(joined);
+                work.push(next);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// [`InterpCode`] with header for storing on disk
+///
+/// Layout (all integers are little endian):
+///
+/// | bytes | content |
+/// |-------|---------|
+/// | 4 | [`MAGIC`] |
+/// | 2 | instruction set version ([`VERSION`]) |
+/// | 1 | cell width in bits ([`CELL_BITS`]) |
+/// | 1 | tape kind (0 - contiguous, 1 - paged) |
+/// | 8 | tape memory limit in bytes (0 - unlimited) |
+/// | 4 | instruction count |
+/// | ... | instructions |
+///
+/// Instruction is opcode byte followed by operands in declaration order of [`InterpIns`] fields:
+/// `u8` operands are single bytes, `u32` operands are LEB128 encoded.
+/// Opcodes are numbered in declaration order of [`InterpIns`] variants starting from 0.
+///
+/// [`InterpIns::Unchecked`] markers aren't stored, because loaded file can't be trusted,
+/// so loaded code runs with all checks
+/// ```
+/// # use bf_tools::{ bf, interpreter::{ InterpCode, InterpIns, Interpreter, bytecode::* } };
+/// let code = InterpCode::from(bf!(++++++++[>++++++++<-]>+.));
+/// let bytes = Bytecode::new(code.clone()).to_bytes();
+/// assert_eq!(bytes[..4], MAGIC);
+///
+/// let loaded = Bytecode::from_bytes(&bytes).unwrap();
+/// assert!(code.0.iter().any(|ins| matches!(ins, InterpIns::Unchecked { .. })));
+/// assert!(!loaded.code.0.iter().any(|ins| matches!(ins, InterpIns::Unchecked { .. })));
+///
+/// let mut out = Vec::new();
+/// Interpreter::builder().set_stdout(&mut out).build().run(loaded.code).unwrap();
+/// assert_eq!(out, b"A");
+///
+/// let mut bytes = bytes;
+/// bytes[4] = 2;
+/// assert_eq!(Bytecode::from_bytes(&bytes), Err(BytecodeError::UnsupportedVersion { version: 2 }));
+/// ```
+/// File with false claim of `Unchecked` region is rejected:
+/// ```
+/// # use bf_tools::interpreter::{ InterpCode, bytecode::* };
+/// let code: InterpCode = "ptr_sub 5\n add 1, [0]".parse().unwrap();
+/// let mut bytes = Bytecode::new(code).to_bytes();
+/// // insert `unchecked 2, [0]` before first instruction
+/// bytes[16] += 1;
+/// bytes.splice(20..20, [17, 2, 0]);
+/// assert_eq!(Bytecode::from_bytes(&bytes), Err(BytecodeError::UncheckedRegion { pos: 20 }));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bytecode {
+    /// Tape settings program expects
+    pub tape: TapeConfig,
+    /// Program code
+    pub code: InterpCode,
+}
+
+impl Bytecode {
+    /// Bytecode with default tape settings
+    #[inline]
+    pub fn new(code: InterpCode) -> Self {
+        Self {
+            tape: TapeConfig::default(),
+            code,
+        }
+    }
+    /// Set tape settings
+    #[inline]
+    #[must_use]
+    pub const fn with_tape(mut self, tape: TapeConfig) -> Self {
+        self.tape = tape;
+        self
+    }
+    /// Encode into bytes, [`InterpIns::Unchecked`] markers are dropped
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // new index of each instruction (jumps to marker lead to first instruction of region)
+        let mut new_ip = Vec::with_capacity(self.code.0.len() + 1);
+        let mut count = 0;
+        for ins in &self.code.0 {
+            new_ip.push(count);
+            if !matches!(ins, InterpIns::Unchecked { .. }) {
+                count += 1;
+            }
+        }
+        new_ip.push(count);
+        // invalid jumps after end of code stay invalid
+        let remap = |dest: u32| {
+            new_ip
+                .get(dest as usize)
+                .copied()
+                .unwrap_or_else(|| dest - new_ip.len() as u32 + 1 + count)
+        };
+        let mut out = Vec::with_capacity(20 + 3 * self.code.0.len());
+        out.extend(MAGIC);
+        out.extend(VERSION.to_le_bytes());
+        out.push(CELL_BITS);
+        out.push(match self.tape.kind {
+            TapeKind::Contiguous => 0,
+            TapeKind::Paged => 1,
+        });
+        out.extend(self.tape.memory_limit.unwrap_or_default().to_le_bytes());
+        out.extend(count.to_le_bytes());
+        for ins in &self.code.0 {
+            let ins = match *ins {
+                InterpIns::Unchecked { .. } => continue,
+                InterpIns::Jmp { dest } => InterpIns::Jmp {
+                    dest: remap(dest),
+                },
+                InterpIns::JmpT { dest } => InterpIns::JmpT {
+                    dest: remap(dest),
+                },
+                InterpIns::JmpF { dest } => InterpIns::JmpF {
+                    dest: remap(dest),
+                },
+                ins => ins,
+            };
+            encode(&ins, &mut out);
+        }
+        out
+    }
+    /// Write encoded bytes to `out`
+    /// # Errors
+    /// return `Err` if writing fails
+    #[inline]
+    pub fn write_to(&self, mut out: impl std::io::Write) -> std::io::Result<()> {
+        out.write_all(&self.to_bytes())
+    }
+    /// Decode and [`verify`] bytecode
+    /// # Errors
+    /// return `Err` if header is invalid or unsupported, instructions can't be decoded
+    /// or code is rejected by verifier
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BytecodeError> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(4)? != MAGIC {
+            return Err(BytecodeError::BadMagic);
+        }
+        let version = u16::from_le_bytes([reader.byte()?, reader.byte()?]);
+        if version != VERSION {
+            return Err(BytecodeError::UnsupportedVersion { version });
+        }
+        let bits = reader.byte()?;
+        if bits != CELL_BITS {
+            return Err(BytecodeError::UnsupportedCellWidth { bits });
+        }
+        let kind = match reader.byte()? {
+            0 => TapeKind::Contiguous,
+            1 => TapeKind::Paged,
+            _ => return Err(BytecodeError::InvalidTapeConfig),
+        };
+        let mut limit = [0; 8];
+        limit.copy_from_slice(reader.take(8)?);
+        let memory_limit = Some(u64::from_le_bytes(limit)).filter(|l| *l != 0);
+        let mut count = [0; 4];
+        count.copy_from_slice(reader.take(4)?);
+        let count = u32::from_le_bytes(count) as usize;
+        // every instruction takes at least 2 bytes
+        let mut code = Vec::with_capacity(count.min(bytes.len() / 2));
+        for _ in 0..count {
+            code.push(reader.ins()?);
+        }
+        if reader.pos != bytes.len() {
+            return Err(BytecodeError::TrailingBytes { pos: reader.pos });
+        }
+        let code = InterpCode(code);
+        verify(&code)?;
+        Ok(Self {
+            tape: TapeConfig { kind, memory_limit },
+            code,
+        })
+    }
+}
+
+fn write_u32(mut v: u32, out: &mut Vec<u8>) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn encode(ins: &InterpIns, out: &mut Vec<u8>) {
+    use InterpIns as I;
+    let (opcode, val, a, b) = match *ins {
+        I::Set { val, offset } => (0, Some(val), offset, None),
+        I::Add { val, offset } => (1, Some(val), offset, None),
+        I::Sub { val, offset } => (2, Some(val), offset, None),
+        I::Mul { val, offset } => (3, Some(val), offset, None),
+        I::PtrAdd { offset } => (4, None, offset, None),
+        I::PtrSub { offset } => (5, None, offset, None),
+        I::SetInputOffset { new_input_offset } => (6, None, new_input_offset, None),
+        I::AddMove { mul, to } => (7, Some(mul), to, None),
+        I::SubMove { mul, to } => (8, Some(mul), to, None),
+        I::MulMove { mul, to } => (9, Some(mul), to, None),
+        I::Move { to } => (10, None, to, None),
+        I::Copy { to } => (11, None, to, None),
+        I::Putchar { offset } => (12, None, offset, None),
+        I::Getchar { offset } => (13, None, offset, None),
+        I::JmpT { dest } => (14, None, dest, None),
+        I::JmpF { dest } => (15, None, dest, None),
+        I::Jmp { dest } => (16, None, dest, None),
+        I::Unchecked { len, max_offset } => (17, None, len, Some(max_offset)),
+    };
+    out.push(opcode);
+    out.extend(val);
+    write_u32(a, out);
+    if let Some(b) = b {
+        write_u32(b, out);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BytecodeError> {
+        let res = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or(BytecodeError::UnexpectedEnd)?;
+        self.pos += len;
+        Ok(res)
+    }
+    fn byte(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+    fn u32(&mut self) -> Result<u32, BytecodeError> {
+        let pos = self.pos;
+        let mut res = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            let bits = u32::from(byte & 0x7f);
+            if bits.checked_shl(shift).map(|v| v >> shift) != Some(bits) {
+                return Err(BytecodeError::InvalidOperand { pos });
+            }
+            res |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(res);
+            }
+        }
+        Err(BytecodeError::InvalidOperand { pos })
+    }
+    fn ins(&mut self) -> Result<InterpIns, BytecodeError> {
+        use InterpIns as I;
+        let pos = self.pos;
+        Ok(match self.byte()? {
+            0 => I::Set {
+                val: self.byte()?,
+                offset: self.u32()?,
+            },
+            1 => I::Add {
+                val: self.byte()?,
+                offset: self.u32()?,
+            },
+            2 => I::Sub {
+                val: self.byte()?,
+                offset: self.u32()?,
+            },
+            3 => I::Mul {
+                val: self.byte()?,
+                offset: self.u32()?,
+            },
+            4 => I::PtrAdd {
+                offset: self.u32()?,
+            },
+            5 => I::PtrSub {
+                offset: self.u32()?,
+            },
+            6 => I::SetInputOffset {
+                new_input_offset: self.u32()?,
+            },
+            7 => I::AddMove {
+                mul: self.byte()?,
+                to: self.u32()?,
+            },
+            8 => I::SubMove {
+                mul: self.byte()?,
+                to: self.u32()?,
+            },
+            9 => I::MulMove {
+                mul: self.byte()?,
+                to: self.u32()?,
+            },
+            10 => I::Move { to: self.u32()? },
+            11 => I::Copy { to: self.u32()? },
+            12 => I::Putchar {
+                offset: self.u32()?,
+            },
+            13 => I::Getchar {
+                offset: self.u32()?,
+            },
+            14 => I::JmpT { dest: self.u32()? },
+            15 => I::JmpF { dest: self.u32()? },
+            16 => I::Jmp { dest: self.u32()? },
+            17 => return Err(BytecodeError::UncheckedRegion { pos }),
+            opcode => return Err(BytecodeError::UnknownOpcode { pos, opcode }),
+        })
+    }
+}
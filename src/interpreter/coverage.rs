@@ -0,0 +1,321 @@
+use super::{
+    debug_info::{BlockKind, DebugInfo},
+    profile::Profile,
+    InterpCode, InterpIns,
+};
+
+/// Execution state of single loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LoopCoverage {
+    /// Loop never reached
+    NotReached,
+    /// Loop reached, but condition cell was always zero
+    NeverEntered {
+        /// How many times loop was reached
+        reached: u64,
+    },
+    /// Loop body was executed at least once
+    Entered {
+        /// How many times loop was reached
+        reached: u64,
+        /// How many times loop was reached with non-zero condition cell
+        entered: u64,
+    },
+}
+
+/// Source coverage collected from one or more profiled runs
+///
+/// Counters are taken from [`Profile`]
+/// (enable it with [`super::InterpreterBuilder::enable_profiling`]),
+/// and mapped to source chars with spans from [`DebugInfo`]
+/// ```
+/// # use bf_tools::{
+/// #     ins_parser::parse_str_with_spans,
+/// #     optimizer::opt_ins::bf_to_opt_with_spans,
+/// #     interpreter::{ Interpreter, bf2interp::bf_to_interp_with_debug_info, coverage::* },
+/// # };
+/// let source = "+[-]\n[>+<-]";
+/// let (code, map) = parse_str_with_spans(source).unwrap();
+/// let (code, spans) = bf_to_opt_with_spans(code, &map);
+/// let (code, debug) = bf_to_interp_with_debug_info(code);
+/// let debug = debug.with_spans(spans);
+///
+/// let mut coverage = Coverage::new(source, &code, &debug);
+/// let mut interpreter = Interpreter::builder().enable_profiling().build();
+/// interpreter.run(code.clone()).unwrap();
+/// coverage.add_profile(interpreter.profile.as_ref().unwrap());
+///
+/// assert_eq!(coverage.line_hits(), vec![(1, 1), (2, 1)]);
+/// assert_eq!(coverage.loops()[1].1, LoopCoverage::NeverEntered { reached: 1 });
+///
+/// // code in `unchecked` region (its marker isn't counted)
+/// let source = "+++\n>.";
+/// let (code, map) = parse_str_with_spans(source).unwrap();
+/// let (code, spans) = bf_to_opt_with_spans(code, &map);
+/// let (code, debug) = bf_to_interp_with_debug_info(code);
+/// assert!(code.to_string().starts_with("unchecked"));
+/// let mut coverage = Coverage::new(source, &code, &debug.with_spans(spans));
+/// let mut interpreter = Interpreter::builder().enable_profiling().build();
+/// interpreter.run(code.clone()).unwrap();
+/// coverage.add_profile(interpreter.profile.as_ref().unwrap());
+/// assert_eq!(coverage.line_hits(), vec![(1, 1), (2, 1)]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coverage {
+    source: Vec<char>,
+    code: InterpCode,
+    debug: DebugInfo,
+    /// Executions count for each block of [`DebugInfo`]
+    block_hits: Vec<u64>,
+    /// Count of loop entries (with non-zero condition) for each block
+    block_entered: Vec<u64>,
+}
+
+impl Coverage {
+    /// Create empty coverage for `code` compiled from `source`
+    ///
+    /// `debug` must contain spans (see [`DebugInfo::with_spans`])
+    pub fn new(source: &str, code: &InterpCode, debug: &DebugInfo) -> Self {
+        Self {
+            source: source.chars().collect(),
+            code: code.clone(),
+            debug: debug.clone(),
+            block_hits: vec![0; debug.blocks.len()],
+            block_entered: vec![0; debug.blocks.len()],
+        }
+    }
+
+    /// Add counters of profiled run
+    ///
+    /// `profile` must be collected by running the same code this coverage was created for.
+    /// Profile counters are accumulated, so [`Profile::clear`] should be called between runs
+    /// when same interpreter is reused
+    pub fn add_profile(&mut self, profile: &Profile) {
+        let count = |counts: &Vec<u64>, ip: usize| counts.get(ip).copied().unwrap_or_default();
+        let mut hits = vec![None; self.debug.blocks.len()];
+        let mut entered = vec![None; self.debug.blocks.len()];
+        for (ip, &block) in self.debug.ins_blocks.iter().enumerate() {
+            // markers aren't counted, so first counted instruction of region is used
+            if let Some(InterpIns::Unchecked { .. }) = self.code.0.get(ip) {
+                continue;
+            }
+            hits[block].get_or_insert(count(&profile.ins_counts, ip));
+            if self.debug.blocks[block].kind != BlockKind::Loop || entered[block].is_some() {
+                continue;
+            }
+            // first instruction that checks condition cell of loop
+            match self.code.0.get(ip) {
+                Some(
+                    InterpIns::JmpF { .. }
+                    | InterpIns::Set { .. }
+                    | InterpIns::AddMove { .. }
+                    | InterpIns::SubMove { .. },
+                ) => entered[block] = Some(count(&profile.nonzero_counts, ip)),
+                // deadloop
+                Some(InterpIns::Jmp { dest }) if *dest as usize == ip => {
+                    entered[block] = Some(count(&profile.ins_counts, ip));
+                }
+                _ => {}
+            }
+        }
+        // blocks without own instructions are executed with enclosing loop body
+        for block in 0..self.debug.blocks.len() {
+            let parent_entered = self.debug.blocks[block]
+                .parent
+                .and_then(|p| entered[p])
+                .unwrap_or_default();
+            let hits = *hits[block].get_or_insert(parent_entered);
+            self.block_hits[block] += hits;
+            if self.debug.blocks[block].kind == BlockKind::Loop {
+                self.block_entered[block] +=
+                    *entered[block].get_or_insert(parent_entered.min(hits));
+            }
+        }
+    }
+
+    /// Execution count for each source char (`None` for comments)
+    pub fn char_hits(&self) -> Vec<Option<u64>> {
+        let mut res = vec![None; self.source.len()];
+        let mut mark = |pos: usize, hits: u64| {
+            if let Some(c) = res.get_mut(pos) {
+                if matches!(
+                    self.source[pos],
+                    '+' | '-' | '<' | '>' | '.' | ',' | '[' | ']'
+                ) {
+                    *c = Some(hits);
+                }
+            }
+        };
+        // blocks may overlap with io instructions between them, so io goes last
+        for kind in [BlockKind::Block, BlockKind::Loop, BlockKind::IO] {
+            for (info, &hits) in self.debug.blocks.iter().zip(&self.block_hits) {
+                let Some(span) = info.span.filter(|_| info.kind == kind) else {
+                    continue;
+                };
+                if kind == BlockKind::Loop {
+                    mark(span.start, hits);
+                    mark(span.end.saturating_sub(1), hits);
+                } else {
+                    (span.start..span.end).for_each(|pos| mark(pos, hits));
+                }
+            }
+        }
+        res
+    }
+
+    /// Execution count for each line containing bf code as `(line_number, hits)`
+    ///
+    /// Line numbers start from 1.
+    /// Line is counted as executed if any of it's chars was executed
+    pub fn line_hits(&self) -> Vec<(usize, u64)> {
+        let mut res = Vec::new();
+        let mut line: (usize, Option<u64>) = (1, None);
+        for (ch, hits) in self.source.iter().zip(self.char_hits()) {
+            if let Some(hits) = hits {
+                line.1 = Some(line.1.unwrap_or_default().max(hits));
+            }
+            if *ch == '\n' {
+                if let Some(hits) = line.1 {
+                    res.push((line.0, hits));
+                }
+                line = (line.0 + 1, None);
+            }
+        }
+        if let Some(hits) = line.1 {
+            res.push((line.0, hits));
+        }
+        res
+    }
+
+    /// Coverage of all loops as `(block, state)` in source order
+    pub fn loops(&self) -> Vec<(usize, LoopCoverage)> {
+        self.debug
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, info)| info.kind == BlockKind::Loop)
+            .map(|(block, _)| {
+                let reached = self.block_hits[block];
+                let entered = self.block_entered[block];
+                let state = match (reached, entered) {
+                    (0, _) => LoopCoverage::NotReached,
+                    (reached, 0) => LoopCoverage::NeverEntered { reached },
+                    (reached, entered) => LoopCoverage::Entered { reached, entered },
+                };
+                (block, state)
+            })
+            .collect()
+    }
+
+    /// `(line, column)` of char at `pos` (both start from 1)
+    fn line_col(&self, pos: usize) -> (usize, usize) {
+        let before = &self.source[..pos.min(self.source.len())];
+        let line = 1 + before.iter().filter(|c| **c == '\n').count();
+        let col = 1 + before.iter().rev().take_while(|c| **c != '\n').count();
+        (line, col)
+    }
+
+    /// Write coverage in lcov `.info` format
+    ///
+    /// Each loop reported as branch with two outcomes: entered and skipped
+    /// # Errors
+    /// return `Err` if writing to `out` fails
+    pub fn write_lcov(
+        &self,
+        mut out: impl std::io::Write,
+        source_path: &str,
+    ) -> std::io::Result<()> {
+        writeln!(out, "TN:")?;
+        writeln!(out, "SF:{source_path}")?;
+        let (mut found, mut hit) = (0, 0);
+        for (block, state) in self.loops() {
+            let Some(span) = self.debug.blocks[block].span else {
+                continue;
+            };
+            let (line, _) = self.line_col(span.start);
+            let (entered, skipped) = match state {
+                LoopCoverage::NotReached => ("-".to_string(), "-".to_string()),
+                LoopCoverage::NeverEntered { reached } => ("0".to_string(), reached.to_string()),
+                LoopCoverage::Entered { reached, entered } => {
+                    (entered.to_string(), (reached - entered).to_string())
+                }
+            };
+            found += 2;
+            hit += (entered != "-" && entered != "0") as usize;
+            hit += (skipped != "-" && skipped != "0") as usize;
+            writeln!(out, "BRDA:{line},{block},0,{entered}")?;
+            writeln!(out, "BRDA:{line},{block},1,{skipped}")?;
+        }
+        writeln!(out, "BRF:{found}")?;
+        writeln!(out, "BRH:{hit}")?;
+        let lines = self.line_hits();
+        for (line, hits) in &lines {
+            writeln!(out, "DA:{line},{hits}")?;
+        }
+        writeln!(out, "LF:{}", lines.len())?;
+        writeln!(
+            out,
+            "LH:{}",
+            lines.iter().filter(|(_, hits)| *hits > 0).count()
+        )?;
+        writeln!(out, "end_of_record")
+    }
+}
+
+impl std::fmt::Display for Coverage {
+    /// Annotated source in gcov-like format
+    ///
+    /// Each line prefixed with it's execution count (`#####` - never executed, `-` - no code).
+    /// Partially executed lines are followed by `^` marks under never executed chars,
+    /// loops starting at line are listed after it
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let char_hits = self.char_hits();
+        let lines: std::collections::HashMap<_, _> = self.line_hits().into_iter().collect();
+        let mut loops = self.loops().into_iter().peekable();
+        let mut pos = 0;
+        for (i, line) in self.source.split(|c| *c == '\n').enumerate() {
+            let line_no = i + 1;
+            let count = match lines.get(&line_no) {
+                None => "-".to_string(),
+                Some(0) => "#####".to_string(),
+                Some(hits) => hits.to_string(),
+            };
+            let text: String = line.iter().collect();
+            writeln!(f, "{count:>9}:{line_no:>5}:{text}")?;
+
+            let line_hits = &char_hits[pos..pos + line.len()];
+            if lines.get(&line_no).is_some_and(|hits| *hits > 0)
+                && line_hits.contains(&Some(0))
+            {
+                let marks: String = line_hits
+                    .iter()
+                    .map(|h| if *h == Some(0) { '^' } else { ' ' })
+                    .collect();
+                writeln!(f, "{:>9}:{:>5}:{}", "", "", marks.trim_end())?;
+            }
+            while let Some((block, state)) = loops.peek().copied() {
+                let Some(span) = self.debug.blocks[block].span else {
+                    loops.next();
+                    continue;
+                };
+                let (loop_line, col) = self.line_col(span.start);
+                if loop_line > line_no {
+                    break;
+                }
+                let state = match state {
+                    LoopCoverage::NotReached => "never reached".to_string(),
+                    LoopCoverage::NeverEntered { reached } => {
+                        format!("never entered (reached {reached} times)")
+                    }
+                    LoopCoverage::Entered { reached, entered } => {
+                        format!("entered {entered} of {reached} times")
+                    }
+                };
+                writeln!(f, "{:>9}:{:>5}: loop at {loop_line}:{col} {state}", "", "")?;
+                loops.next();
+            }
+            pos += line.len() + 1;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{ins_parser::Span, optimizer::opt_ins::OptBlock};
+
+/// Kind of [`OptBlock`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockKind {
+    /// [`OptBlock::Block`]
+    Block,
+    /// [`OptBlock::IOIns`]
+    IO,
+    /// [`OptBlock::Loop`]
+    Loop,
+}
+
+impl BlockKind {
+    /// Get kind of block
+    #[inline]
+    pub const fn of(block: &OptBlock) -> Self {
+        match block {
+            OptBlock::Block(_) => Self::Block,
+            OptBlock::IOIns(_) => Self::IO,
+            OptBlock::Loop(_) => Self::Loop,
+        }
+    }
+}
+
+/// Info about single [`OptBlock`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockInfo {
+    /// Pre-order index of enclosing loop (`None` for top level blocks)
+    pub parent: Option<usize>,
+    /// Block kind
+    pub kind: BlockKind,
+    /// Block location in source code (if known)
+    pub span: Option<Span>,
+}
+
+impl BlockInfo {
+    /// Create block info without source location
+    #[inline]
+    pub const fn new(parent: Option<usize>, kind: BlockKind) -> Self {
+        Self {
+            parent,
+            kind,
+            span: None,
+        }
+    }
+}
+
+/// Relation between [`super::InterpCode`] and [`crate::optimizer::OptCode`] it was lowered from
+///
+/// Produced by [`super::bf2interp::bf_to_interp_with_debug_info`].
+/// Blocks are numbered in pre-order (loop comes before it's inner blocks).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    /// Block index for each lowered instruction
+    pub ins_blocks: Vec<usize>,
+    /// All blocks of source code in pre-order
+    pub blocks: Vec<BlockInfo>,
+}
+
+impl DebugInfo {
+    /// Attach block spans
+    /// (as returned by [`crate::optimizer::opt_ins::bf_to_opt_with_spans`])
+    /// ```
+    /// # use bf_tools::{
+    /// #     ins_parser::{ parse_str_with_spans, Span },
+    /// #     optimizer::opt_ins::bf_to_opt_with_spans,
+    /// #     interpreter::bf2interp::bf_to_interp_with_debug_info,
+    /// # };
+    /// let (code, map) = parse_str_with_spans("+[->+<]").unwrap();
+    /// let (code, spans) = bf_to_opt_with_spans(code, &map);
+    /// let (_code, debug) = bf_to_interp_with_debug_info(code);
+    /// let debug = debug.with_spans(spans);
+    /// assert_eq!(debug.blocks[1].span, Some(Span::new(1, 7)));
+    /// ```
+    #[must_use]
+    pub fn with_spans(mut self, spans: impl IntoIterator<Item = Span>) -> Self {
+        for (block, span) in self.blocks.iter_mut().zip(spans) {
+            block.span = Some(span);
+        }
+        self
+    }
+    /// Block index of instruction at `ip`
+    #[inline]
+    pub fn block_of(&self, ip: usize) -> Option<usize> {
+        self.ins_blocks.get(ip).copied()
+    }
+    /// Source span of instruction at `ip` (if known)
+    #[inline]
+    pub fn span_of(&self, ip: usize) -> Option<Span> {
+        self.block_of(ip)
+            .and_then(|b| self.blocks.get(b))
+            .and_then(|b| b.span)
+    }
+    /// Iterate over block and all it's enclosing loops (innermost first)
+    pub fn ancestors(&self, block: usize) -> impl Iterator<Item = usize> + '_ {
+        std::iter::successors(Some(block), |b| self.blocks.get(*b).and_then(|b| b.parent))
+    }
+}
@@ -0,0 +1,199 @@
+use super::{debug_info::DebugInfo, InterpIns};
+use crate::ins_parser::Span;
+
+/// Place in executed code where [`InterpreteError`] happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorLocation {
+    /// Index of failed instruction
+    pub ip: usize,
+    /// Failed instruction
+    pub ins: InterpIns,
+    /// Data pointer before instruction
+    pub data_pointer: usize,
+    /// Source span of instruction (see [`InterpreteError::with_debug_info`])
+    pub span: Option<Span>,
+}
+
+/// InterpreteError
+#[derive(Debug)]
+pub enum InterpreteError {
+    /// PtrSub moved data pointer below 0
+    DataPointerUnderflow {
+        /// Failed instruction
+        at: ErrorLocation,
+        /// Pointer decrement
+        offset: u32,
+    },
+    /// data pointer - offset < 0
+    InvalidOffset {
+        /// Failed instruction
+        at: ErrorLocation,
+        /// Offset of accessed cell
+        offset: u32,
+    },
+    /// Tape can't grow without exceeding memory limit
+    MemoryLimit {
+        /// Failed instruction (`None` if tape can't be allocated before execution)
+        at: Option<ErrorLocation>,
+        /// Memory limit in bytes
+        limit: usize,
+    },
+    /// Getchar | Putchar fails with io error
+    IOError {
+        /// Failed instruction (`None` if error happened during final output flush)
+        at: Option<ErrorLocation>,
+        /// Underlying error
+        err: std::io::Error,
+    },
+}
+
+impl InterpreteError {
+    /// Location of failed instruction
+    #[inline]
+    pub const fn location(&self) -> Option<&ErrorLocation> {
+        match self {
+            Self::DataPointerUnderflow { at, .. } | Self::InvalidOffset { at, .. } => Some(at),
+            Self::MemoryLimit { at, .. } | Self::IOError { at, .. } => at.as_ref(),
+        }
+    }
+    /// Attach source span of failed instruction
+    /// ```
+    /// # use bf_tools::{
+    /// #     ins_parser::{ parse_str_with_spans, Span },
+    /// #     optimizer::opt_ins::bf_to_opt_with_spans,
+    /// #     interpreter::{ bf2interp::bf_to_interp_with_debug_info, Interpreter },
+    /// # };
+    /// let source = "+>\n<<-";
+    /// let (code, map) = parse_str_with_spans(source).unwrap();
+    /// let (code, spans) = bf_to_opt_with_spans(code, &map);
+    /// let (code, debug) = bf_to_interp_with_debug_info(code);
+    /// let debug = debug.with_spans(spans);
+    ///
+    /// let err = Interpreter::default().run(code).unwrap_err().with_debug_info(&debug);
+    /// assert_eq!(err.location().unwrap().data_pointer, 0);
+    /// assert_eq!(err.location().unwrap().span, Some(Span::new(0, 6)));
+    /// assert!(err.render(source).to_string().starts_with("error: invalid offset"));
+    /// ```
+    #[must_use]
+    pub fn with_debug_info(mut self, debug: &DebugInfo) -> Self {
+        let at = match &mut self {
+            Self::DataPointerUnderflow { at, .. } | Self::InvalidOffset { at, .. } => Some(at),
+            Self::MemoryLimit { at, .. } | Self::IOError { at, .. } => at.as_mut(),
+        };
+        if let Some(at) = at {
+            at.span = at.span.or_else(|| debug.span_of(at.ip));
+        }
+        self
+    }
+    /// Human-readable error report with source line of failed instruction
+    #[inline]
+    pub const fn render<'a>(&'a self, source: &'a str) -> RenderedError<'a> {
+        RenderedError { err: self, source }
+    }
+    fn write_message(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DataPointerUnderflow { at, offset } => write!(
+                f,
+                "data pointer underflow: pointer {} moved left by {offset}",
+                at.data_pointer
+            ),
+            Self::InvalidOffset { at, offset } => write!(
+                f,
+                "invalid offset: cell {} - {offset} is out of tape",
+                at.data_pointer
+            ),
+            Self::MemoryLimit { limit, .. } => {
+                write!(f, "tape memory limit of {limit} bytes exceeded")
+            }
+            Self::IOError { err, .. } => write!(f, "io error: {err}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for InterpreteError {
+    #[inline]
+    fn from(err: std::io::Error) -> Self {
+        Self::IOError { at: None, err }
+    }
+}
+
+impl std::fmt::Display for InterpreteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_message(f)?;
+        if let Some(at) = self.location() {
+            write!(f, " (ip {}: `{}`", at.ip, at.ins)?;
+            if let Some(span) = at.span {
+                write!(f, ", source {span}")?;
+            }
+            f.write_str(")")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InterpreteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::IOError { err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Error report returned by [`InterpreteError::render`]
+///
+/// Formatted like compiler diagnostics:
+/// ```text
+/// error: data pointer underflow: pointer 1 moved left by 2
+///  --> 2:3
+///   |
+/// 2 | +[<<+>]
+///   |   ^^^^
+///   = ip 5: `ptr_sub 2`, data pointer 1
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RenderedError<'a> {
+    err: &'a InterpreteError,
+    source: &'a str,
+}
+
+impl std::fmt::Display for RenderedError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("error: ")?;
+        self.err.write_message(f)?;
+        f.write_str("\n")?;
+        let Some(at) = self.err.location() else {
+            return Ok(());
+        };
+        let chars: Vec<char> = self.source.chars().collect();
+        let line = at.span.filter(|span| span.start < chars.len()).map(|span| {
+            let line_start = chars[..span.start]
+                .iter()
+                .rposition(|c| *c == '\n')
+                .map_or(0, |i| i + 1);
+            let line_end = chars[span.start..]
+                .iter()
+                .position(|c| *c == '\n')
+                .map_or(chars.len(), |i| span.start + i);
+            let line_no = 1 + chars[..line_start].iter().filter(|c| **c == '\n').count();
+            (line_no, line_start, line_end, span)
+        });
+        let width = line.map_or(1, |(line_no, ..)| line_no.to_string().len());
+        let pad = " ".repeat(width);
+        if let Some((line_no, line_start, line_end, span)) = line {
+            let col = span.start - line_start;
+            // spans of multiline loops are underlined only to the end of first line
+            let len = span.end.min(line_end).saturating_sub(span.start).max(1);
+            let text: String = chars[line_start..line_end].iter().collect();
+            writeln!(f, "{pad}--> {line_no}:{}", col + 1)?;
+            writeln!(f, "{pad} |")?;
+            writeln!(f, "{line_no} | {text}")?;
+            writeln!(f, "{pad} | {}{}", " ".repeat(col), "^".repeat(len))?;
+        }
+        writeln!(
+            f,
+            "{pad} = ip {}: `{}`, data pointer {}",
+            at.ip, at.ins, at.data_pointer
+        )
+    }
+}
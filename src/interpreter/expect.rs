@@ -0,0 +1,389 @@
+use super::{
+    run::RunStatus, tape::Tape, InterpCode, InterprIOIn, InterprIOOut, InterpreteError, Interpreter,
+};
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+/// Single action of [`ExpectDriver`] script
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ScriptStep {
+    /// Wait until program prints text (any output before it is skipped)
+    Expect {
+        /// Expected text
+        text: Vec<u8>,
+        /// Max count of steps to wait
+        timeout: Option<u64>,
+    },
+    /// Next printed bytes must be exactly this text
+    ExpectExact {
+        /// Expected text
+        text: Vec<u8>,
+        /// Max count of steps to wait
+        timeout: Option<u64>,
+    },
+    /// Pass text to program input
+    Send(Vec<u8>),
+}
+
+/// Reason why program diverged from [`ExpectDriver`] script
+#[derive(Debug)]
+pub enum ExpectFailure {
+    /// Expected text wasn't printed in time
+    Timeout {
+        /// Index of failed script step
+        index: usize,
+        /// Expected text
+        expected: String,
+        /// Output printed while waiting
+        output: String,
+        /// Interpreter step of failure
+        step: u64,
+    },
+    /// Program printed something other than expected by [`ScriptStep::ExpectExact`]
+    Mismatch {
+        /// Index of failed script step
+        index: usize,
+        /// Expected text
+        expected: String,
+        /// Output printed while waiting
+        output: String,
+        /// Interpreter step of failure
+        step: u64,
+    },
+    /// Program requested input while script waits for output
+    InputWhileWaiting {
+        /// Index of failed script step
+        index: usize,
+        /// Expected text
+        expected: String,
+        /// Output printed while waiting
+        output: String,
+        /// Interpreter step of failure
+        step: u64,
+    },
+    /// Program requested input after all script input was sent
+    NoMoreInput {
+        /// Interpreter step of failure
+        step: u64,
+    },
+    /// Program finished before end of script
+    Unfinished {
+        /// Index of first not passed script step
+        index: usize,
+        /// Output printed after last passed step
+        output: String,
+    },
+    /// Interpreter failed for reason not related to script
+    Interpreter(InterpreteError),
+}
+
+impl std::fmt::Display for ExpectFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Timeout {
+                index,
+                expected,
+                output,
+                step,
+            } => write!(
+                f,
+                "script step {index}: timed out at step {step} waiting for {expected:?}, got {output:?}"
+            ),
+            Self::Mismatch {
+                index,
+                expected,
+                output,
+                step,
+            } => write!(
+                f,
+                "script step {index}: output diverged at step {step}: expected {expected:?}, got {output:?}"
+            ),
+            Self::InputWhileWaiting {
+                index,
+                expected,
+                output,
+                step,
+            } => write!(
+                f,
+                "script step {index}: program requested input at step {step} while waiting for {expected:?}, got {output:?}"
+            ),
+            Self::NoMoreInput { step } => {
+                write!(f, "program requested input at step {step} after end of script")
+            }
+            Self::Unfinished { index, output } => write!(
+                f,
+                "script step {index}: program finished before end of script, last output {output:?}"
+            ),
+            Self::Interpreter(err) => write!(f, "interpreter error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ExpectFailure {}
+
+#[derive(Debug, Default)]
+struct DriverState {
+    script: Vec<ScriptStep>,
+    /// index of current script step
+    pos: usize,
+    /// bytes sent to program but not read yet
+    input: VecDeque<u8>,
+    /// output printed since last passed step
+    output: Vec<u8>,
+    /// step when current expectation started waiting (`None` until execution starts)
+    since: Option<u64>,
+    failure: Option<ExpectFailure>,
+}
+
+impl DriverState {
+    /// Pass all `Send` steps before next expectation
+    fn advance(&mut self) {
+        while let Some(ScriptStep::Send(text)) = self.script.get(self.pos) {
+            self.input.extend(text);
+            self.pos += 1;
+        }
+    }
+    fn expected(&self) -> Option<(&[u8], Option<u64>, bool)> {
+        match self.script.get(self.pos)? {
+            ScriptStep::Expect { text, timeout } => Some((text, *timeout, false)),
+            ScriptStep::ExpectExact { text, timeout } => Some((text, *timeout, true)),
+            ScriptStep::Send(_) => None,
+        }
+    }
+    fn deadline(&self) -> Option<u64> {
+        let (_, timeout, _) = self.expected()?;
+        Some(self.since?.saturating_add(timeout?))
+    }
+    fn output_str(&self) -> String {
+        String::from_utf8_lossy(&self.output).into_owned()
+    }
+    fn fail(&mut self, failure: ExpectFailure) -> std::io::Error {
+        let err = std::io::Error::other(failure.to_string());
+        self.failure.get_or_insert(failure);
+        err
+    }
+    fn timeout_failure(&self, step: u64) -> Option<ExpectFailure> {
+        match (self.deadline(), self.expected()) {
+            (Some(deadline), Some((text, _, _))) if step > deadline => {
+                Some(ExpectFailure::Timeout {
+                    index: self.pos,
+                    expected: String::from_utf8_lossy(text).into_owned(),
+                    output: self.output_str(),
+                    step,
+                })
+            }
+            _ => None,
+        }
+    }
+    fn check_timeout(&mut self, step: u64) -> std::io::Result<()> {
+        // without `ExpectDriver::run` waiting starts with first io
+        self.since.get_or_insert(step);
+        match self.timeout_failure(step) {
+            Some(failure) => Err(self.fail(failure)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Scripted IO driver for interactive programs
+///
+/// Same driver should be passed as both input and output of [`Interpreter`]
+/// (it's cheap to clone, all clones share state).
+/// Timeouts are measured in interpreter steps from the moment expectation becomes active
+/// ```
+/// # use bf_tools::{ bf, interpreter::{ Interpreter, InterpCode, expect::* } };
+/// // print `?`, read char, print it back and print `!`
+/// let code = InterpCode::from(bf!(+++++++[>+++++++++<-]>.[-],.[-]<++++++[>+++++<-]>+++.));
+/// let driver = ExpectDriver::new()
+///     .timeout(1000)
+///     .expect("?")
+///     .send("x")
+///     .expect_exact("x!");
+/// let mut interpreter = Interpreter::builder()
+///     .set_stdin(driver.clone())
+///     .set_stdout(driver.clone())
+///     .build();
+/// driver.run(&mut interpreter, &code).unwrap();
+///
+/// let driver = ExpectDriver::new().expect("?").send("x").expect_exact("y!");
+/// let mut interpreter = Interpreter::builder()
+///     .set_stdin(driver.clone())
+///     .set_stdout(driver.clone())
+///     .build();
+/// let failure = driver.run(&mut interpreter, &code).unwrap_err();
+/// assert!(matches!(failure, ExpectFailure::Mismatch { index: 2, .. }));
+/// ```
+/// Steps executed before [`ExpectDriver::run`] aren't counted into timeout:
+/// ```
+/// # use bf_tools::{ bf, interpreter::{ Interpreter, InterpCode, expect::* } };
+/// let driver = ExpectDriver::new().timeout(100).expect("?");
+/// let mut interpreter = Interpreter::builder()
+///     .set_stdin(driver.clone())
+///     .set_stdout(driver.clone())
+///     .build();
+/// interpreter.steps = 1000; // e.g. restored from snapshot
+/// driver.run(&mut interpreter, &InterpCode::from(bf!(+++++++[>+++++++++<-]>.))).unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ExpectDriver {
+    state: Rc<RefCell<DriverState>>,
+    /// timeout for next added expectations
+    timeout: Option<u64>,
+}
+
+impl ExpectDriver {
+    /// Create driver with empty script
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Set timeout (in interpreter steps) for all expectations added after this call
+    #[inline]
+    #[must_use]
+    pub const fn timeout(mut self, steps: u64) -> Self {
+        self.timeout = Some(steps);
+        self
+    }
+    /// Add script step
+    #[must_use]
+    pub fn step(self, step: ScriptStep) -> Self {
+        {
+            let mut state = self.state.borrow_mut();
+            state.script.push(step);
+            state.advance();
+        }
+        self
+    }
+    /// Wait for `text` in program output
+    #[must_use]
+    pub fn expect(self, text: impl AsRef<[u8]>) -> Self {
+        let timeout = self.timeout;
+        self.step(ScriptStep::Expect {
+            text: text.as_ref().to_vec(),
+            timeout,
+        })
+    }
+    /// Next program output must be exactly `text`
+    #[must_use]
+    pub fn expect_exact(self, text: impl AsRef<[u8]>) -> Self {
+        let timeout = self.timeout;
+        self.step(ScriptStep::ExpectExact {
+            text: text.as_ref().to_vec(),
+            timeout,
+        })
+    }
+    /// Send `text` to program input
+    #[must_use]
+    pub fn send(self, text: impl AsRef<[u8]>) -> Self {
+        self.step(ScriptStep::Send(text.as_ref().to_vec()))
+    }
+    /// Check if all script steps passed
+    pub fn is_finished(&self) -> bool {
+        let state = self.state.borrow();
+        state.pos == state.script.len()
+    }
+    /// Take failure reported by driver during execution
+    pub fn take_failure(&self) -> Option<ExpectFailure> {
+        self.state.borrow_mut().failure.take()
+    }
+    /// Run `code` from current interpreter state until end, checking script
+    ///
+    /// `interpreter` must use this driver as input and output
+    /// # Errors
+    /// return `Err` if program diverged from script or interpreter failed
+    pub fn run<T: Tape>(
+        &self,
+        interpreter: &mut Interpreter<'_, T>,
+        code: &InterpCode,
+    ) -> Result<(), ExpectFailure> {
+        // steps executed before this call aren't counted into timeout
+        self.state.borrow_mut().since.get_or_insert(interpreter.steps);
+        loop {
+            let deadline = self.state.borrow().deadline();
+            let budget = deadline.map_or(u64::MAX, |d| {
+                d.saturating_sub(interpreter.steps).saturating_add(1)
+            });
+            match interpreter.run_for(code, budget) {
+                Ok(RunStatus::Finished) => break,
+                Ok(RunStatus::StepLimit) => {
+                    if let Some(failure) = self.state.borrow().timeout_failure(interpreter.steps) {
+                        return Err(failure);
+                    }
+                }
+                Err(err) => {
+                    return Err(self
+                        .take_failure()
+                        .unwrap_or(ExpectFailure::Interpreter(err)))
+                }
+            }
+        }
+        let state = self.state.borrow();
+        if state.pos < state.script.len() {
+            return Err(ExpectFailure::Unfinished {
+                index: state.pos,
+                output: state.output_str(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl InterprIOIn for ExpectDriver {
+    fn getchar(&mut self) -> std::io::Result<u8> {
+        let step = self.state.borrow().since.unwrap_or(0);
+        self.getchar_at(step)
+    }
+    fn getchar_at(&mut self, step: u64) -> std::io::Result<u8> {
+        let mut state = self.state.borrow_mut();
+        state.check_timeout(step)?;
+        if let Some(byte) = state.input.pop_front() {
+            return Ok(byte);
+        }
+        let failure = match state.expected() {
+            Some((text, _, _)) => ExpectFailure::InputWhileWaiting {
+                index: state.pos,
+                expected: String::from_utf8_lossy(text).into_owned(),
+                output: state.output_str(),
+                step,
+            },
+            None => ExpectFailure::NoMoreInput { step },
+        };
+        Err(state.fail(failure))
+    }
+}
+
+impl InterprIOOut for ExpectDriver {
+    fn putchar(&mut self, ch: u8) -> std::io::Result<()> {
+        let step = self.state.borrow().since.unwrap_or(0);
+        self.putchar_at(ch, step)
+    }
+    fn putchar_at(&mut self, ch: u8, step: u64) -> std::io::Result<()> {
+        let mut state = self.state.borrow_mut();
+        state.check_timeout(step)?;
+        state.output.push(ch);
+        let Some((text, _, exact)) = state.expected() else {
+            // output after end of script or before next send is ignored
+            return Ok(());
+        };
+        let matched = if exact {
+            if !text.starts_with(&state.output) {
+                let failure = ExpectFailure::Mismatch {
+                    index: state.pos,
+                    expected: String::from_utf8_lossy(text).into_owned(),
+                    output: state.output_str(),
+                    step,
+                };
+                return Err(state.fail(failure));
+            }
+            state.output.len() == text.len()
+        } else {
+            state.output.ends_with(text)
+        };
+        if matched {
+            state.output.clear();
+            state.pos += 1;
+            state.advance();
+            state.since = Some(step);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,434 @@
+use super::{debug_info::DebugInfo, InterpCode, InterpIns};
+
+/*
+    peephole matches:
+    `set_input_offset a` when input_offset is already `a` on every path
+    `ptr_add a` `ptr_add b` as `ptr_add a + b` (same for `ptr_sub`)
+    `ptr_add a` `ptr_sub b` as `ptr_add a - b` (or nothing if a == b) inside `unchecked` region
+    `set a, [o]` `add b, [o]` as `set a + b, [o]`
+    `add a, [o]` `sub b, [o]` as `add a - b, [o]`
+    `jmp_f 'x` where `x: jmp_f 'y` as `jmp_f 'y`
+*/
+
+/// Simplify lowered code
+///
+/// Runs after [`super::bf2interp::bf_to_interp`].
+/// Instructions are merged only if there is no jump target between them
+/// and both are in same `unchecked` region, all jump destinations are updated.
+/// Pointer moves in opposite directions are merged only inside `unchecked` region,
+/// otherwise intermediate pointer could underflow or exceed memory limit
+/// and merged move would hide that error.
+/// ```
+/// # use bf_tools::{ bf, interpreter::{ bf2interp::bf_to_interp_with_debug_info, peephole } };
+/// let (mut code, _) = bf_to_interp_with_debug_info(bf!(,[-]+++[>.<-]));
+/// peephole::optimize(&mut code);
+/// let expected = [
+///     "unchecked 9, [1]",
+///     "getchar [0]",
+///     "set 3, [0]",
+///     "set_input_offset 0",
+///     "jmp_f [input_offset], '10",
+///     "ptr_add 1",
+///     "putchar [0]",
+///     "ptr_sub 1",
+///     "add 255, [0]",
+///     "jmp_t [input_offset], '5",
+/// ];
+/// assert_eq!(code.to_string().lines().collect::<Vec<_>>(), expected);
+/// ```
+/// Optimized code gives same output, errors and final state as original code
+/// (only step counts and error locations differ):
+/// ```
+/// # use bf_tools::{ interpreter::{ *, InterpIns::*, bf2interp::bf_to_interp_with_debug_info, peephole, tape::* } };
+/// fn run<T: Tape>(code: &InterpCode, tape: T) -> impl PartialEq + std::fmt::Debug {
+///     let mut out = Vec::new();
+///     let mut it = Interpreter::builder().set_stdin(&b"hi\x01"[..]).set_stdout(&mut out).set_tape(tape).build();
+///     let res = it.run(code.clone()).map_err(|err| std::mem::discriminant(&err));
+///     let state = (res, it.data_pointer, it.tape.image());
+///     drop(it);
+///     (state, out)
+/// }
+/// let bf = |src: &str| bf_to_interp_with_debug_info(src.parse::<bf_tools::ins::BfCode>().unwrap()).0;
+/// let programs = [
+///     bf(",[-]+++[>.<-]>>+[>+>++<<-]>>[-<+>]<."),
+///     bf(">>,[>+<-]>[<<+>>-]<<[->+<]>.<<<"), // underflow after loops
+///     bf(">+[<+]"),                          // underflow inside loop
+///     bf(">>>[-]<<<[>]+++[>+++<-]>.,,,,"),   // io error at end of input
+///     // underflow in the middle of pointer moves outside of `unchecked` region
+///     InterpCode(vec![PtrSub { offset: 1 }, PtrAdd { offset: 1 }, Putchar { offset: 0 }]),
+///     InterpCode(vec![
+///         PtrAdd { offset: 2 }, PtrSub { offset: 3 }, PtrAdd { offset: 1 },
+///         Add { val: 1, offset: 0 }, Set { val: 7, offset: 0 },
+///     ]),
+///     InterpCode(vec![
+///         PtrAdd { offset: 2 }, PtrSub { offset: 1 }, PtrSub { offset: 1 },
+///         Sub { val: 1, offset: 0 }, Add { val: 1, offset: 0 },
+///     ]),
+/// ];
+/// for code in &programs {
+///     let mut optimized = code.clone();
+///     peephole::optimize(&mut optimized);
+///     assert_eq!(run(code, Vec::new()), run(&optimized, Vec::new()), "{code}");
+/// }
+/// // pointer moves beyond memory limit and back
+/// let far = (PAGE_SIZE * 3) as u32;
+/// let code = InterpCode(vec![PtrAdd { offset: far }, PtrSub { offset: far }, Add { val: 1, offset: 0 }]);
+/// let mut optimized = code.clone();
+/// peephole::optimize(&mut optimized);
+/// let tape = || PagedTape::new().with_memory_limit(PAGE_SIZE * 2);
+/// assert_eq!(run(&code, tape()), run(&optimized, tape()));
+/// ```
+pub fn optimize(code: &mut InterpCode) {
+    optimize_impl(&mut code.0, None);
+}
+
+/// Same as [`optimize`] but also keep [`DebugInfo::ins_blocks`] in sync
+///
+/// Merged instruction belongs to block of first instruction.
+/// Profile counters of optimized code may not match blocks exactly
+/// (e.g. threaded jump skips condition check of next loop)
+pub fn optimize_with_debug_info(code: &mut InterpCode, debug: &mut DebugInfo) {
+    optimize_impl(&mut code.0, Some(&mut debug.ins_blocks));
+}
+
+fn optimize_impl(code: &mut Vec<InterpIns>, mut ins_blocks: Option<&mut Vec<usize>>) {
+    loop {
+        let mut changed = thread_jumps(code);
+        let mut keep: Vec<Option<InterpIns>> = code.iter().copied().map(Some).collect();
+        changed |= remove_input_offsets(code, &mut keep);
+        changed |= merge_adjacent(code, &mut keep);
+        if !changed {
+            break;
+        }
+        compact(code, &keep, ins_blocks.as_deref_mut());
+    }
+}
+
+/// Region (index of [`InterpIns::Unchecked`] marker) of each instruction
+fn regions(code: &[InterpIns]) -> Vec<Option<usize>> {
+    let mut region = vec![None; code.len()];
+    let mut i = 0;
+    while i < code.len() {
+        if let InterpIns::Unchecked { len, .. } = code[i] {
+            let end = (i + 1 + len as usize).min(code.len());
+            region[i + 1..end].fill(Some(i));
+            i = end;
+        } else {
+            i += 1;
+        }
+    }
+    region
+}
+
+const fn jump_dest(ins: &InterpIns) -> Option<u32> {
+    match ins {
+        InterpIns::Jmp { dest } | InterpIns::JmpT { dest } | InterpIns::JmpF { dest } => {
+            Some(*dest)
+        }
+        _ => None,
+    }
+}
+
+/// Redirect jumps which land on another jump with known outcome
+fn thread_jumps(code: &mut [InterpIns]) -> bool {
+    let region = regions(code);
+    // final destination of jump of each kind (`Jmp`, `JmpT`, `JmpF`) which lands on instruction,
+    // so chains through many nested loop ends are followed only once
+    let mut resolved = vec![[None; 3]; code.len()];
+    let kind = |ins: &InterpIns| match ins {
+        InterpIns::Jmp { .. } => 0,
+        InterpIns::JmpT { .. } => 1,
+        _ => 2,
+    };
+    let mut path = Vec::new();
+    let mut changed = false;
+    for ip in 0..code.len() {
+        let Some(mut dest) = jump_dest(&code[ip]) else {
+            continue;
+        };
+        let k = kind(&code[ip]);
+        path.clear();
+        // chain length limit protects from jump cycles
+        let mut finished = false;
+        for _ in 0..code.len() {
+            let d = dest as usize;
+            if d >= code.len() || d == ip || region[d] != region[ip] {
+                finished = d != ip;
+                break;
+            }
+            if let Some(res) = resolved[d][k] {
+                dest = res;
+                finished = true;
+                break;
+            }
+            // condition cell is same at both jumps (pointer and input offset aren't changed)
+            let next = match (code[ip], code[d]) {
+                (_, InterpIns::Jmp { dest }) => dest,
+                (InterpIns::JmpF { .. }, InterpIns::JmpF { dest })
+                | (InterpIns::JmpT { .. }, InterpIns::JmpT { dest }) => dest,
+                (InterpIns::JmpF { .. }, InterpIns::JmpT { .. })
+                | (InterpIns::JmpT { .. }, InterpIns::JmpF { .. }) => dest + 1,
+                _ => {
+                    finished = true;
+                    break;
+                }
+            };
+            path.push(d);
+            dest = next;
+        }
+        if finished {
+            for &d in &path {
+                resolved[d][k] = Some(dest);
+            }
+        }
+        if Some(dest) != jump_dest(&code[ip]) {
+            if let InterpIns::Jmp { dest: d }
+            | InterpIns::JmpT { dest: d }
+            | InterpIns::JmpF { dest: d } = &mut code[ip]
+            {
+                *d = dest;
+            }
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Input offset before instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Known {
+    /// Instruction not reached yet
+    Unreached,
+    Value(u32),
+    /// Different on different paths
+    Any,
+}
+
+impl Known {
+    const fn join(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Unreached, x) | (x, Self::Unreached) => x,
+            (Self::Value(a), Self::Value(b)) if a == b => Self::Value(a),
+            _ => Self::Any,
+        }
+    }
+}
+
+/// Remove `SetInputOffset` which doesn't change input offset
+fn remove_input_offsets(code: &[InterpIns], keep: &mut [Option<InterpIns>]) -> bool {
+    let mut known = vec![Known::Unreached; code.len() + 1];
+    // input offset must be set before first use (see `bytecode::verify`)
+    known[0] = Known::Any;
+    let mut work = vec![0];
+    while let Some(ip) = work.pop() {
+        let Some(ins) = code.get(ip) else {
+            continue;
+        };
+        let after = match ins {
+            InterpIns::SetInputOffset { new_input_offset } => Known::Value(*new_input_offset),
+            _ => known[ip],
+        };
+        let fallthrough = !matches!(ins, InterpIns::Jmp { .. });
+        let jump = jump_dest(ins).map(|d| (d as usize).min(code.len()));
+        for next in fallthrough.then_some(ip + 1).into_iter().chain(jump) {
+            let joined = known[next].join(after);
+            if joined != known[next] {
+                known[next] = joined;
+                work.push(next);
+            }
+        }
+    }
+    let mut changed = false;
+    for (ip, ins) in code.iter().enumerate() {
+        if let InterpIns::SetInputOffset { new_input_offset } = ins {
+            if known[ip] == Known::Value(*new_input_offset) {
+                keep[ip] = None;
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+/// Pointer move by `delta` cells
+fn ptr_move(delta: i64) -> Option<Option<InterpIns>> {
+    Some(match delta {
+        0 => None,
+        1.. => Some(InterpIns::PtrAdd {
+            offset: u32::try_from(delta).ok()?,
+        }),
+        _ => Some(InterpIns::PtrSub {
+            offset: u32::try_from(-delta).ok()?,
+        }),
+    })
+}
+
+/// Result of `a` followed by `b` (`Some(None)` - both instructions do nothing)
+///
+/// `checked` - instructions are outside of `unchecked` region
+fn merge(a: InterpIns, b: InterpIns, checked: bool) -> Option<Option<InterpIns>> {
+    use InterpIns as I;
+    let sign = |ins| {
+        if matches!(ins, I::PtrAdd { .. }) {
+            1
+        } else {
+            -1
+        }
+    };
+    match (a, b) {
+        (
+            I::PtrAdd { offset: x } | I::PtrSub { offset: x },
+            I::PtrAdd { offset: y } | I::PtrSub { offset: y },
+        ) if !checked || sign(a) == sign(b) => {
+            ptr_move(sign(a) * i64::from(x) + sign(b) * i64::from(y))
+        }
+        (I::Set { val: x, offset: o }, I::Add { val: y, offset: p }) if o == p => {
+            Some(Some(I::Set {
+                val: x.wrapping_add(y),
+                offset: o,
+            }))
+        }
+        (I::Set { val: x, offset: o }, I::Sub { val: y, offset: p }) if o == p => {
+            Some(Some(I::Set {
+                val: x.wrapping_sub(y),
+                offset: o,
+            }))
+        }
+        (I::Set { val: x, offset: o }, I::Mul { val: y, offset: p }) if o == p => {
+            Some(Some(I::Set {
+                val: x.wrapping_mul(y),
+                offset: o,
+            }))
+        }
+        (
+            I::Add { val: x, offset: o } | I::Sub { val: x, offset: o },
+            I::Add { val: y, offset: p } | I::Sub { val: y, offset: p },
+        ) if o == p => {
+            let signed = |ins, v: u8| {
+                if matches!(ins, I::Add { .. }) {
+                    v
+                } else {
+                    v.wrapping_neg()
+                }
+            };
+            let val = signed(a, x).wrapping_add(signed(b, y));
+            Some((val != 0).then_some(I::Add { val, offset: o }))
+        }
+        (
+            I::Set { offset: o, .. } | I::Add { offset: o, .. } | I::Sub { offset: o, .. },
+            I::Set { offset: p, .. },
+        ) if o == p => Some(Some(b)),
+        (I::SetInputOffset { .. }, I::SetInputOffset { .. }) => Some(Some(b)),
+        _ => None,
+    }
+}
+
+/// Instruction which does nothing
+const fn is_nop(ins: &InterpIns) -> bool {
+    matches!(
+        ins,
+        InterpIns::PtrAdd { offset: 0 }
+            | InterpIns::PtrSub { offset: 0 }
+            | InterpIns::Add { val: 0, .. }
+            | InterpIns::Sub { val: 0, .. }
+    )
+}
+
+/// Merge neighbouring instructions which aren't separated by jump target or region bound
+fn merge_adjacent(code: &[InterpIns], keep: &mut [Option<InterpIns>]) -> bool {
+    let region = regions(code);
+    // `targets[i]` - count of jump targets before `i`
+    let mut targets = vec![0; code.len() + 2];
+    for ins in code {
+        if let Some(d) = jump_dest(ins) {
+            if let Some(t) = targets.get_mut(d as usize + 1) {
+                *t += 1;
+            }
+        }
+    }
+    for i in 1..targets.len() {
+        targets[i] += targets[i - 1];
+    }
+    let mut changed = false;
+    // indices of kept instructions
+    let mut stack: Vec<usize> = Vec::new();
+    for ip in 0..code.len() {
+        let Some(ins) = keep[ip] else {
+            continue;
+        };
+        if is_nop(&ins) {
+            keep[ip] = None;
+            changed = true;
+            continue;
+        }
+        let Some(&prev) = stack.last() else {
+            stack.push(ip);
+            continue;
+        };
+        let adjacent = targets[ip + 1] == targets[prev + 1] && region[prev] == region[ip];
+        let merged = keep[prev].filter(|_| adjacent).and_then(|a| merge(a, ins, region[ip].is_none()));
+        match merged {
+            Some(merged) => {
+                changed = true;
+                keep[ip] = None;
+                keep[prev] = merged;
+                if merged.is_none() {
+                    stack.pop();
+                }
+            }
+            None => stack.push(ip),
+        }
+    }
+    changed
+}
+
+/// Remove instructions without `keep` value and fix up jumps and `unchecked` regions
+fn compact(
+    code: &mut Vec<InterpIns>,
+    keep: &[Option<InterpIns>],
+    ins_blocks: Option<&mut Vec<usize>>,
+) {
+    // new index of each instruction (or of next kept instruction)
+    let mut new_ip = vec![0; code.len() + 1];
+    let mut count = keep.iter().flatten().count();
+    new_ip[code.len()] = count;
+    for ip in (0..code.len()).rev() {
+        if keep[ip].is_some() {
+            count -= 1;
+        }
+        new_ip[ip] = count;
+    }
+    let remap = |dest: u32| {
+        let dest = dest as usize;
+        if dest <= code.len() {
+            new_ip[dest] as u32
+        } else {
+            (dest - code.len() + new_ip[code.len()]) as u32
+        }
+    };
+    let mut res = Vec::with_capacity(new_ip[code.len()]);
+    for (ip, ins) in keep.iter().enumerate() {
+        let Some(mut ins) = *ins else {
+            continue;
+        };
+        match &mut ins {
+            InterpIns::Jmp { dest } | InterpIns::JmpT { dest } | InterpIns::JmpF { dest } => {
+                *dest = remap(*dest);
+            }
+            InterpIns::Unchecked { len, .. } => {
+                let end = (ip + 1 + *len as usize).min(code.len());
+                *len = (new_ip[end] - new_ip[ip] - 1) as u32;
+            }
+            _ => {}
+        }
+        res.push(ins);
+    }
+    if let Some(ins_blocks) = ins_blocks {
+        let mut ip = 0;
+        ins_blocks.retain(|_| {
+            ip += 1;
+            keep.get(ip - 1).is_some_and(Option::is_some)
+        });
+    }
+    *code = res;
+}
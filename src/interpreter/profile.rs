@@ -0,0 +1,231 @@
+use super::{
+    debug_info::{BlockKind, DebugInfo},
+    InterpCode, InterpIns,
+};
+use crate::ins_parser::Span;
+
+/// Execution counters collected by [`super::Interpreter::run`] in profiling mode
+///
+/// Enabled with [`super::InterpreterBuilder::enable_profiling`].
+/// Counters are accumulated over all runs until [`Profile::clear`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Execution count for each instruction index
+    pub ins_counts: Vec<u64>,
+    /// Count of executions where instruction's condition cell was non-zero
+    ///
+    /// Condition cell is the cell checked by `jmp_t`/`jmp_f`,
+    /// moved by `*_move` / `copy` or cleared by `set`
+    /// (so for loops lowered into single instruction it's count of loop entries)
+    pub nonzero_counts: Vec<u64>,
+    /// Total count of executed instructions
+    pub steps: u64,
+    /// Maximum data pointer value reached during execution
+    pub max_data_pointer: usize,
+}
+
+impl Profile {
+    /// Create empty profile
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            ins_counts: Vec::new(),
+            nonzero_counts: Vec::new(),
+            steps: 0,
+            max_data_pointer: 0,
+        }
+    }
+    /// Reset all counters
+    #[inline]
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+    #[inline]
+    pub(super) fn prepare(&mut self, code_len: usize) {
+        if self.ins_counts.len() < code_len {
+            self.ins_counts.resize(code_len, 0);
+            self.nonzero_counts.resize(code_len, 0);
+        }
+    }
+    #[inline(always)]
+    pub(super) fn record(&mut self, ip: usize, data_pointer: usize, nonzero: bool) {
+        self.ins_counts[ip] += 1;
+        self.nonzero_counts[ip] += nonzero as u64;
+        self.steps += 1;
+        self.max_data_pointer = self.max_data_pointer.max(data_pointer);
+    }
+    /// Build report using debug info of profiled code
+    #[inline]
+    pub fn report(&self, code: &InterpCode, debug: &DebugInfo) -> ProfileReport {
+        ProfileReport::new(self, code, debug)
+    }
+}
+
+/// Execution statistic of single loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopStats {
+    /// Loop block index (see [`DebugInfo`])
+    pub block: usize,
+    /// Loop location in source code
+    pub span: Option<Span>,
+    /// How many times loop was reached
+    pub entries: u64,
+    /// Total count of loop body executions
+    ///
+    /// `None` for loops lowered into single instruction (like `[-]`)
+    pub iterations: Option<u64>,
+    /// Count of instructions executed inside loop (including nested loops)
+    pub steps: u64,
+}
+
+/// Profile mapped back to source blocks
+/// ```
+/// # use bf_tools::interpreter::{ Interpreter, bf2interp::bf_to_interp_with_debug_info };
+/// # use bf_tools::bf;
+/// let (code, debug) = bf_to_interp_with_debug_info(bf!(+++[>++[>+<-]<-]));
+/// let mut interpreter = Interpreter::builder().enable_profiling().build();
+/// interpreter.run(code.clone()).unwrap();
+/// let report = interpreter.profile.as_ref().unwrap().report(&code, &debug);
+/// let hottest = report.loops[0];
+/// assert_eq!((hottest.block, hottest.entries, hottest.iterations), (1, 1, Some(3)));
+///
+/// // loop bodies start with `unchecked` markers, which aren't counted
+/// let (code, debug) = bf_to_interp_with_debug_info(bf!(+>+>+<<[[-]>]));
+/// let mut interpreter = Interpreter::builder().enable_profiling().build();
+/// interpreter.run(code.clone()).unwrap();
+/// let report = interpreter.profile.as_ref().unwrap().report(&code, &debug);
+/// let stats: Vec<_> = report.loops.iter().map(|l| (l.block, l.entries, l.iterations)).collect();
+/// assert_eq!(stats, [(1, 1, Some(3)), (2, 3, None)]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileReport {
+    /// Total count of executed instructions
+    pub steps: u64,
+    /// Maximum data pointer value reached during execution
+    pub max_data_pointer: usize,
+    /// Executed instructions per block (excluding nested blocks)
+    pub block_steps: Vec<u64>,
+    /// Stats for all loops, hottest first
+    pub loops: Vec<LoopStats>,
+    debug: DebugInfo,
+}
+
+impl ProfileReport {
+    /// Map profile counters to blocks of `debug`
+    pub fn new(profile: &Profile, code: &InterpCode, debug: &DebugInfo) -> Self {
+        let count = |ip: usize| profile.ins_counts.get(ip).copied().unwrap_or_default();
+        let mut block_steps = vec![0u64; debug.blocks.len()];
+        let mut entries: Vec<Option<u64>> = vec![None; debug.blocks.len()];
+        let mut iterations = vec![None; debug.blocks.len()];
+        for (ip, &block) in debug.ins_blocks.iter().enumerate() {
+            // markers aren't counted, so first counted instruction of region is used
+            if let Some(InterpIns::Unchecked { .. }) = code.0.get(ip) {
+                continue;
+            }
+            block_steps[block] += count(ip);
+            entries[block].get_or_insert(count(ip));
+            // generic loop lowered as `set_input_offset; jmp_f; body...; set_input_offset; jmp_t`
+            // body entry is reached once per iteration
+            if let Some(InterpIns::JmpF { .. }) = code.0.get(ip) {
+                if debug.blocks[block].kind == BlockKind::Loop && iterations[block].is_none() {
+                    entries[block] = Some(count(ip));
+                    let body = (ip + 1..code.0.len())
+                        .find(|i| !matches!(code.0[*i], InterpIns::Unchecked { .. }))
+                        .unwrap_or(code.0.len());
+                    iterations[block] = Some(count(body));
+                }
+            }
+        }
+        let mut loop_steps = vec![0u64; debug.blocks.len()];
+        for (block, steps) in block_steps.iter().enumerate() {
+            for b in debug.ancestors(block) {
+                loop_steps[b] += steps;
+            }
+        }
+        let mut loops: Vec<_> = debug
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.kind == BlockKind::Loop)
+            .map(|(block, info)| LoopStats {
+                block,
+                span: info.span,
+                entries: entries[block].unwrap_or_default(),
+                iterations: iterations[block],
+                steps: loop_steps[block],
+            })
+            .collect();
+        loops.sort_by(|a, b| b.steps.cmp(&a.steps).then(a.block.cmp(&b.block)));
+        Self {
+            steps: profile.steps,
+            max_data_pointer: profile.max_data_pointer,
+            block_steps,
+            loops,
+            debug: debug.clone(),
+        }
+    }
+
+    fn frame_name(&self, block: usize) -> String {
+        let info = &self.debug.blocks[block];
+        let kind = match info.kind {
+            BlockKind::Block => "block",
+            BlockKind::IO => "io",
+            BlockKind::Loop => "loop",
+        };
+        match info.span {
+            Some(span) => format!("{kind}@{span}"),
+            None => format!("{kind}#{block}"),
+        }
+    }
+
+    /// Write profile in folded stacks format (one `main;loop@1..7;block@2..6 42` line per block)
+    ///
+    /// Output can be passed directly to `flamegraph.pl` or `inferno-flamegraph`
+    /// # Errors
+    /// return `Err` if writing to `out` fails
+    pub fn write_folded(&self, mut out: impl std::io::Write) -> std::io::Result<()> {
+        for (block, &steps) in self.block_steps.iter().enumerate() {
+            if steps == 0 {
+                continue;
+            }
+            let mut frames: Vec<_> = self
+                .debug
+                .ancestors(block)
+                .map(|b| self.frame_name(b))
+                .collect();
+            frames.push("main".to_string());
+            frames.reverse();
+            writeln!(out, "{} {steps}", frames.join(";"))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for ProfileReport {
+    /// Text report with 10 hottest loops (use `{:#}` to print all loops)
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "total steps: {}", self.steps)?;
+        writeln!(f, "max data pointer: {}", self.max_data_pointer)?;
+        writeln!(f, "hottest loops:")?;
+        let shown = if f.alternate() { self.loops.len() } else { 10 };
+        for stats in self.loops.iter().take(shown) {
+            let percent = if self.steps == 0 {
+                0.0
+            } else {
+                stats.steps as f64 * 100.0 / self.steps as f64
+            };
+            let iterations = stats
+                .iterations
+                .map_or_else(|| "-".to_string(), |i| i.to_string());
+            writeln!(
+                f,
+                "  {:<16} steps: {:>12} ({percent:5.1}%)  entries: {:>10}  iterations: {:>12}",
+                self.frame_name(stats.block),
+                stats.steps,
+                stats.entries,
+                iterations,
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,370 @@
+use super::{InterprIOIn, InterprIOOut};
+use std::{cell::RefCell, rc::Rc};
+
+/// Single IO operation of interpreter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IoEvent {
+    /// Getchar returned `byte`
+    Input {
+        /// Count of instructions executed before Getchar
+        step: u64,
+        /// Read byte
+        byte: u8,
+    },
+    /// Getchar failed because input ended
+    InputEof {
+        /// Count of instructions executed before Getchar
+        step: u64,
+    },
+    /// Putchar printed `byte`
+    Output {
+        /// Count of instructions executed before Putchar
+        step: u64,
+        /// Printed byte
+        byte: u8,
+    },
+}
+
+impl IoEvent {
+    /// Step of event
+    #[inline]
+    pub const fn step(&self) -> u64 {
+        match self {
+            Self::Input { step, .. } | Self::InputEof { step } | Self::Output { step, .. } => *step,
+        }
+    }
+}
+
+impl std::fmt::Display for IoEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Input { step, byte } => write!(f, "in {step} {byte}"),
+            Self::InputEof { step } => write!(f, "eof {step}"),
+            Self::Output { step, byte } => write!(f, "out {step} {byte}"),
+        }
+    }
+}
+
+/// Recorded IO session
+///
+/// Text format: `bf-io-log 1` header, then one event per line
+/// (`in <step> <byte>`, `out <step> <byte>` or `eof <step>`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct IoLog {
+    /// All events in execution order
+    pub events: Vec<IoEvent>,
+}
+
+const LOG_HEADER: &str = "bf-io-log 1";
+
+fn invalid_data(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+impl IoLog {
+    /// Write log in text format
+    /// # Errors
+    /// return `Err` if writing to `out` fails
+    pub fn write_to(&self, mut out: impl std::io::Write) -> std::io::Result<()> {
+        writeln!(out, "{LOG_HEADER}")?;
+        for event in &self.events {
+            writeln!(out, "{event}")?;
+        }
+        Ok(())
+    }
+    /// Read log written by [`IoLog::write_to`]
+    /// # Errors
+    /// return `Err` if reading fails or data is not valid log
+    pub fn read_from(input: impl std::io::BufRead) -> std::io::Result<Self> {
+        let mut lines = input.lines();
+        match lines.next().transpose()? {
+            Some(header) if header.trim_end() == LOG_HEADER => {}
+            _ => return Err(invalid_data("missing io log header".to_string())),
+        }
+        let mut events = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            let parts: Vec<_> = line.split_whitespace().collect();
+            let invalid = || invalid_data(format!("invalid io log line {}: `{line}`", i + 2));
+            let num = |s: &str| s.parse::<u64>().map_err(|_| invalid());
+            let byte = |s: &str| s.parse::<u8>().map_err(|_| invalid());
+            events.push(match parts.as_slice() {
+                [] => continue,
+                ["in", step, b] => IoEvent::Input {
+                    step: num(step)?,
+                    byte: byte(b)?,
+                },
+                ["out", step, b] => IoEvent::Output {
+                    step: num(step)?,
+                    byte: byte(b)?,
+                },
+                ["eof", step] => IoEvent::InputEof { step: num(step)? },
+                _ => return Err(invalid()),
+            });
+        }
+        Ok(Self { events })
+    }
+    /// All bytes read by program
+    pub fn input(&self) -> Vec<u8> {
+        self.events
+            .iter()
+            .filter_map(|e| match e {
+                IoEvent::Input { byte, .. } => Some(*byte),
+                _ => None,
+            })
+            .collect()
+    }
+    /// All bytes printed by program
+    pub fn output(&self) -> Vec<u8> {
+        self.events
+            .iter()
+            .filter_map(|e| match e {
+                IoEvent::Output { byte, .. } => Some(*byte),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Recorder of interpreter IO
+///
+/// Wrap interpreter streams with [`IoRecorder::input`] and [`IoRecorder::output`],
+/// all operations of both streams are recorded into single [`IoLog`]
+/// ```
+/// # use bf_tools::{ bf, interpreter::{ Interpreter, record::* } };
+/// let recorder = IoRecorder::new();
+/// let mut interpreter = Interpreter::builder()
+///     .set_stdin(recorder.input(&b"hi"[..]))
+///     .set_stdout(recorder.output(Vec::new()))
+///     .build();
+/// interpreter.run(bf!(.,.,.)).unwrap();
+/// # drop(interpreter);
+///
+/// let log = recorder.log();
+/// assert_eq!(log.input(), b"hi");
+/// assert_eq!(log.events[0], IoEvent::Output { step: 0, byte: 0 });
+/// assert_eq!(log.events[1], IoEvent::Input { step: 1, byte: b'h' });
+///
+/// // replay recorded session without real input
+/// let replay = IoReplay::new(log);
+/// let mut interpreter = Interpreter::builder()
+///     .set_stdin(replay.input())
+///     .set_stdout(replay.output(Vec::new()))
+///     .build();
+/// interpreter.run(bf!(.,.,.)).unwrap();
+/// # drop(interpreter);
+/// assert!(replay.is_finished());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct IoRecorder {
+    log: Rc<RefCell<IoLog>>,
+}
+
+impl IoRecorder {
+    /// Create new recorder with empty log
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Wrap input stream
+    #[inline]
+    pub fn input<T: InterprIOIn>(&self, inner: T) -> RecordingInput<T> {
+        RecordingInput {
+            inner,
+            log: self.log.clone(),
+        }
+    }
+    /// Wrap output stream
+    #[inline]
+    pub fn output<T: InterprIOOut>(&self, inner: T) -> RecordingOutput<T> {
+        RecordingOutput {
+            inner,
+            log: self.log.clone(),
+        }
+    }
+    /// Copy of recorded log
+    #[inline]
+    pub fn log(&self) -> IoLog {
+        self.log.borrow().clone()
+    }
+}
+
+/// Input stream which records all read bytes (see [`IoRecorder`])
+#[derive(Debug)]
+pub struct RecordingInput<T: InterprIOIn> {
+    inner: T,
+    log: Rc<RefCell<IoLog>>,
+}
+
+impl<T: InterprIOIn> InterprIOIn for RecordingInput<T> {
+    fn getchar(&mut self) -> std::io::Result<u8> {
+        self.getchar_at(0)
+    }
+    fn getchar_at(&mut self, step: u64) -> std::io::Result<u8> {
+        let res = self.inner.getchar_at(step);
+        let event = match &res {
+            Ok(byte) => IoEvent::Input { step, byte: *byte },
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                IoEvent::InputEof { step }
+            }
+            Err(_) => return res,
+        };
+        self.log.borrow_mut().events.push(event);
+        res
+    }
+    #[inline]
+    fn has_buffered_input(&self) -> bool {
+        self.inner.has_buffered_input()
+    }
+}
+
+/// Output stream which records all printed bytes (see [`IoRecorder`])
+#[derive(Debug)]
+pub struct RecordingOutput<T: InterprIOOut> {
+    inner: T,
+    log: Rc<RefCell<IoLog>>,
+}
+
+impl<T: InterprIOOut> InterprIOOut for RecordingOutput<T> {
+    fn putchar(&mut self, ch: u8) -> std::io::Result<()> {
+        self.putchar_at(ch, 0)
+    }
+    fn putchar_at(&mut self, ch: u8, step: u64) -> std::io::Result<()> {
+        self.inner.putchar_at(ch, step)?;
+        self.log
+            .borrow_mut()
+            .events
+            .push(IoEvent::Output { step, byte: ch });
+        Ok(())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.inner.finish()
+    }
+}
+
+#[derive(Debug)]
+struct ReplayState {
+    log: IoLog,
+    /// index of next expected event
+    pos: usize,
+}
+
+impl ReplayState {
+    fn next(
+        &mut self,
+        matches: impl FnOnce(&IoEvent) -> bool,
+        actual: &str,
+    ) -> std::io::Result<IoEvent> {
+        let Some(event) = self.log.events.get(self.pos).copied() else {
+            return Err(invalid_data(format!(
+                "replay diverged: {actual} after end of recorded session"
+            )));
+        };
+        if !matches(&event) {
+            return Err(invalid_data(format!(
+                "replay diverged at event {}: expected `{event}`, got `{actual}`",
+                self.pos
+            )));
+        }
+        self.pos += 1;
+        Ok(event)
+    }
+}
+
+/// Player of recorded [`IoLog`]
+///
+/// [`IoReplay::input`] feeds recorded input and [`IoReplay::output`] checks printed bytes.
+/// Any difference from recorded session (other byte, step or order of operations)
+/// is reported as [`std::io::ErrorKind::InvalidData`] error
+#[derive(Debug, Clone)]
+pub struct IoReplay {
+    state: Rc<RefCell<ReplayState>>,
+}
+
+impl IoReplay {
+    /// Create player for recorded session
+    pub fn new(log: IoLog) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(ReplayState { log, pos: 0 })),
+        }
+    }
+    /// Input stream with recorded bytes
+    #[inline]
+    pub fn input(&self) -> ReplayInput {
+        ReplayInput {
+            state: self.state.clone(),
+        }
+    }
+    /// Output stream which checks printed bytes and passes them to `inner`
+    #[inline]
+    pub fn output<T: InterprIOOut>(&self, inner: T) -> ReplayOutput<T> {
+        ReplayOutput {
+            inner,
+            state: self.state.clone(),
+        }
+    }
+    /// Check if all recorded events were replayed
+    pub fn is_finished(&self) -> bool {
+        let state = self.state.borrow();
+        state.pos == state.log.events.len()
+    }
+}
+
+/// Input stream of [`IoReplay`]
+#[derive(Debug)]
+pub struct ReplayInput {
+    state: Rc<RefCell<ReplayState>>,
+}
+
+impl InterprIOIn for ReplayInput {
+    fn getchar(&mut self) -> std::io::Result<u8> {
+        let step = {
+            let state = self.state.borrow();
+            state.log.events.get(state.pos).map_or(0, IoEvent::step)
+        };
+        self.getchar_at(step)
+    }
+    fn getchar_at(&mut self, step: u64) -> std::io::Result<u8> {
+        let event = self.state.borrow_mut().next(
+            |e| matches!(e, IoEvent::Input { step: s, .. } | IoEvent::InputEof { step: s } if *s == step),
+            &format!("in {step}"),
+        )?;
+        match event {
+            IoEvent::Input { byte, .. } => Ok(byte),
+            _ => Err(std::io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
+
+/// Output stream of [`IoReplay`]
+#[derive(Debug)]
+pub struct ReplayOutput<T: InterprIOOut> {
+    inner: T,
+    state: Rc<RefCell<ReplayState>>,
+}
+
+impl<T: InterprIOOut> InterprIOOut for ReplayOutput<T> {
+    fn putchar(&mut self, ch: u8) -> std::io::Result<()> {
+        let step = {
+            let state = self.state.borrow();
+            state.log.events.get(state.pos).map_or(0, IoEvent::step)
+        };
+        self.putchar_at(ch, step)
+    }
+    fn putchar_at(&mut self, ch: u8, step: u64) -> std::io::Result<()> {
+        self.state.borrow_mut().next(
+            |e| *e == IoEvent::Output { step, byte: ch },
+            &format!("out {step} {ch}"),
+        )?;
+        self.inner.putchar_at(ch, step)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.inner.finish()
+    }
+}
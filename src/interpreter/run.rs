@@ -1,164 +1,366 @@
-use super::{InterpCode, InterpIns, InterpreteError, Interpreter};
+use super::{tape::Tape, threaded::{self, Backend}, ErrorLocation, InterpCode, InterpIns, InterpreteError, Interpreter};
 
-impl Interpreter<'_> {
+/// Result of [`Interpreter::run_for`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RunStatus {
+    /// All instructions executed
+    Finished,
+    /// Step limit reached before end of code
+    StepLimit,
+}
+
+impl<T: Tape> Interpreter<'_, T> {
     /// Execute all instructions from [`InterpCode`]
+    ///
+    /// Execution starts from first instruction, tape is not cleared
+    /// # Errors
+    /// return `Err` if data pointer goes out of tape or io fails
     pub fn run<C: Into<InterpCode>>(&mut self, code: C) -> Result<(), InterpreteError> {
-        let mut ip = 0usize;
-        let mut input_offset = 0u32;
-        let code = code.into().0;
-        self.reserve_storage()?;
-        //TODO target feature to disable offset checks?
-        while ip < code.len() {
-            //println!("ip: {ip}, ins: {:?}, tape: {:?}, ptr: {:?}", &code[ip], &self.tape, self.data_pointer);
-            match &code[ip] {
+        self.ip = 0;
+        self.input_offset = 0;
+        let res = self.resume(&code.into());
+        // code is dropped here, so its address can be reused by other code
+        self.compiled = None;
+        res
+    }
+    /// Continue execution from current instruction pointer until end of code
+    /// # Errors
+    /// return `Err` if data pointer goes out of tape or io fails
+    pub fn resume(&mut self, code: &InterpCode) -> Result<(), InterpreteError> {
+        self.run_for(code, u64::MAX).map(|_| ())
+    }
+    /// Continue execution from current instruction pointer and stop after `max_steps` instructions
+    ///
+    /// Can be used to checkpoint long computations with [`Interpreter::snapshot`]:
+    /// ```
+    /// # use bf_tools::{ bf, interpreter::{ Interpreter, InterpCode, run::RunStatus } };
+    /// let code = InterpCode::from(bf!(++++[>+++<-]>[<+>-]));
+    /// let mut interpreter = Interpreter::default();
+    /// while interpreter.run_for(&code, 3).unwrap() == RunStatus::StepLimit {
+    ///     let state = interpreter.snapshot();
+    ///     interpreter = Interpreter::default();
+    ///     interpreter.restore(state);
+    /// }
+    /// assert_eq!(interpreter.tape[..2], [12, 0]);
+    /// ```
+    /// Output is flushed after each call, but incomplete data (like partial UTF-8 char)
+    /// is written out only after program stops, so chunked run prints same bytes:
+    /// ```
+    /// # use bf_tools::interpreter::{ Interpreter, InterpCode, InterpIns::*, DefaultWriter, run::RunStatus };
+    /// let code = InterpCode(vec![Set { val: 195, offset: 0 }, Putchar { offset: 0 }, Set { val: 169, offset: 0 }, Putchar { offset: 0 }]);
+    /// let mut whole = Vec::new();
+    /// Interpreter::builder().set_stdout(DefaultWriter::new(&mut whole)).build().run(code.clone()).unwrap();
+    /// let mut chunked = Vec::new();
+    /// let mut interpreter = Interpreter::builder().set_stdout(DefaultWriter::new(&mut chunked)).build();
+    /// while interpreter.run_for(&code, 1).unwrap() == RunStatus::StepLimit {}
+    /// # drop(interpreter);
+    /// assert_eq!(whole, "é".as_bytes());
+    /// assert_eq!(chunked, whole);
+    /// ```
+    /// # Errors
+    /// return `Err` if data pointer goes out of tape or io fails
+    pub fn run_for(&mut self, code: &InterpCode, max_steps: u64) -> Result<RunStatus, InterpreteError> {
+        let res = self.exec_steps(code, max_steps);
+        // execution can be resumed after step limit, so incomplete output is kept
+        let flushed = if matches!(res, Ok(RunStatus::StepLimit)) {
+            self.io_out.flush()
+        } else {
+            self.io_out.finish()
+        };
+        let status = res?;
+        flushed?;
+        Ok(status)
+    }
+    /// Execute single instruction at current instruction pointer
+    ///
+    /// [`InterpIns::Unchecked`] markers are not counted as steps, so they're executed with next instruction.
+    /// Unlike [`Interpreter::run_for`] output is not flushed after execution
+    /// # Errors
+    /// return `Err` if data pointer goes out of tape or io fails
+    #[inline]
+    pub fn step(&mut self, code: &InterpCode) -> Result<RunStatus, InterpreteError> {
+        self.exec_steps(code, 1)
+    }
+    fn exec_steps(&mut self, code: &InterpCode, max_steps: u64) -> Result<RunStatus, InterpreteError> {
+        let (mut ip, mut input_offset, mut steps) = (self.ip, self.input_offset, 0);
+        // compiling whole code for single step isn't worth it
+        let res = if self.backend == Backend::Threaded && self.profile.is_none() && max_steps > 1 {
+            threaded::exec(self, &code.0, &mut ip, &mut input_offset, &mut steps, max_steps)
+        } else {
+            self.exec::<true>(&code.0, &mut ip, &mut input_offset, &mut steps, max_steps, code.0.len())
+        };
+        self.ip = ip;
+        self.input_offset = input_offset;
+        self.steps += steps;
+        res
+    }
+    /// Execute instructions until `ip` reaches `end`
+    ///
+    /// Without `CHECKED` offset checks are skipped and tape isn't grown
+    /// (used for regions marked with [`InterpIns::Unchecked`])
+    pub(super) fn exec<const CHECKED: bool>(
+        &mut self,
+        code: &[InterpIns],
+        ip: &mut usize,
+        input_offset: &mut u32,
+        steps: &mut u64,
+        max_steps: u64,
+        end: usize,
+    ) -> Result<RunStatus, InterpreteError> {
+        if CHECKED {
+            self.tape.grow_to(self.data_pointer).map_err(|err| InterpreteError::MemoryLimit {
+                at: None,
+                limit: err.limit,
+            })?;
+            if let Some(profile) = self.profile.as_mut() {
+                profile.prepare(code.len());
+            }
+        }
+        while *ip < end {
+            // markers aren't counted as steps, so bounds analysis doesn't change step counts
+            if let InterpIns::Unchecked { len, max_offset } = code[*ip] {
+                // profiler needs every instruction recorded, so region runs in checked mode
+                if CHECKED && self.profile.is_none() {
+                    let max = self.data_pointer + max_offset as usize;
+                    self.reserve_storage(code, *ip, max)?;
+                    let end = *ip + 1 + len as usize;
+                    *ip += 1;
+                    self.exec::<false>(code, ip, input_offset, steps, max_steps, end)?;
+                } else {
+                    *ip += 1;
+                }
+                continue;
+            }
+            if *steps >= max_steps {
+                return Ok(RunStatus::StepLimit);
+            }
+            *steps += 1;
+            if let Some(profile) = self.profile.as_mut().filter(|_| CHECKED) {
+                let cond_offset = match code[*ip] {
+                    InterpIns::Set { offset, .. } => Some(offset),
+                    InterpIns::AddMove { .. }
+                    | InterpIns::SubMove { .. }
+                    | InterpIns::MulMove { .. }
+                    | InterpIns::Move { .. }
+                    | InterpIns::Copy { .. }
+                    | InterpIns::JmpT { .. }
+                    | InterpIns::JmpF { .. } => Some(*input_offset),
+                    _ => None,
+                };
+                let nonzero = cond_offset
+                    .and_then(|offset| self.data_pointer.checked_sub(offset as usize))
+                    .is_some_and(|i| self.tape.cell(i) != 0);
+                profile.record(*ip, self.data_pointer, nonzero);
+            }
+            match &code[*ip] {
                 InterpIns::Set { val, offset } => {
-                    if self.data_pointer < *offset as usize {
-                        println!("ip: {ip}, ins: {:?}, tape: {:?}", &code[ip], &self.tape);
-                        return Err(InterpreteError::InvalidOffset);
+                    if CHECKED && self.data_pointer < *offset as usize {
+                        return Err(self.invalid_offset(code, *ip, *offset));
                     }
-                    self.tape[self.data_pointer - *offset as usize] = *val;
+                    *self.cell_mut(code, *ip, self.data_pointer - *offset as usize)? = *val;
                 }
                 InterpIns::Add { val, offset } => {
-                    if self.data_pointer < *offset as usize {
-                        println!("ip: {ip}, ins: {:?}, tape: {:?}", &code[ip], &self.tape);
-                        return Err(InterpreteError::InvalidOffset);
+                    if CHECKED && self.data_pointer < *offset as usize {
+                        return Err(self.invalid_offset(code, *ip, *offset));
                     }
-                    self.tape[self.data_pointer - *offset as usize] =
-                        self.tape[self.data_pointer - *offset as usize].wrapping_add(*val);
+                    let cell = self.cell_mut(code, *ip, self.data_pointer - *offset as usize)?;
+                    *cell = cell.wrapping_add(*val);
                 }
                 InterpIns::Sub { val, offset } => {
-                    if self.data_pointer < *offset as usize {
-                        println!("ip: {ip}, ins: {:?}, tape: {:?}", &code[ip], &self.tape);
-                        return Err(InterpreteError::InvalidOffset);
+                    if CHECKED && self.data_pointer < *offset as usize {
+                        return Err(self.invalid_offset(code, *ip, *offset));
                     }
-                    self.tape[self.data_pointer - *offset as usize] =
-                        self.tape[self.data_pointer - *offset as usize].wrapping_sub(*val);
+                    let cell = self.cell_mut(code, *ip, self.data_pointer - *offset as usize)?;
+                    *cell = cell.wrapping_sub(*val);
                 }
                 InterpIns::Mul { val, offset } => {
-                    if self.data_pointer < *offset as usize {
-                        println!("ip: {ip}, ins: {:?}, tape: {:?}", &code[ip], &self.tape);
-                        return Err(InterpreteError::InvalidOffset);
+                    if CHECKED && self.data_pointer < *offset as usize {
+                        return Err(self.invalid_offset(code, *ip, *offset));
                     }
-                    self.tape[self.data_pointer - *offset as usize] =
-                        self.tape[self.data_pointer - *offset as usize].wrapping_mul(*val);
+                    let cell = self.cell_mut(code, *ip, self.data_pointer - *offset as usize)?;
+                    *cell = cell.wrapping_mul(*val);
                 }
 
                 InterpIns::PtrAdd { offset } => {
                     self.data_pointer += *offset as usize;
-                    self.reserve_storage()?;
+                    if CHECKED {
+                        self.reserve_storage(code, *ip, self.data_pointer)?;
+                    }
                 }
                 InterpIns::PtrSub { offset } => {
-                    self.data_pointer = self
-                        .data_pointer
-                        .checked_sub(*offset as usize)
-                        .ok_or(InterpreteError::DataPointerUnderflow)?;
+                    self.data_pointer = if CHECKED {
+                        self.data_pointer
+                            .checked_sub(*offset as usize)
+                            .ok_or_else(|| InterpreteError::DataPointerUnderflow {
+                                at: self.location(code, *ip),
+                                offset: *offset,
+                            })?
+                    } else {
+                        self.data_pointer - *offset as usize
+                    };
                 }
 
                 InterpIns::SetInputOffset { new_input_offset } => {
-                    input_offset = *new_input_offset;
+                    *input_offset = *new_input_offset;
                 }
 
                 InterpIns::AddMove { mul, to } => {
-                    if self.data_pointer < *to as usize || self.data_pointer < input_offset as usize {
-                        println!("ip: {ip}, ins: {:?}, tape: {:?}", &code[ip], &self.tape);
-                        return Err(InterpreteError::InvalidOffset);
+                    if CHECKED
+                        && (self.data_pointer < *to as usize
+                            || self.data_pointer < *input_offset as usize)
+                    {
+                        return Err(self.invalid_offset(code, *ip, (*to).max(*input_offset)));
                     }
-                    self.tape[self.data_pointer - *to as usize] =
-                        self.tape[self.data_pointer - *to as usize].wrapping_add(
-                            self.tape[self.data_pointer - input_offset as usize].wrapping_mul(*mul),
-                        );
-                    self.tape[self.data_pointer - input_offset as usize] = 0;
+                    let from = self.data_pointer - *input_offset as usize;
+                    let val = self.tape.cell(from).wrapping_mul(*mul);
+                    let cell = self.cell_mut(code, *ip, self.data_pointer - *to as usize)?;
+                    *cell = cell.wrapping_add(val);
+                    *self.cell_mut(code, *ip, from)? = 0;
                 }
                 InterpIns::SubMove { mul, to } => {
-                    if self.data_pointer < *to as usize || self.data_pointer < input_offset as usize
+                    if CHECKED
+                        && (self.data_pointer < *to as usize
+                            || self.data_pointer < *input_offset as usize)
                     {
-                        return Err(InterpreteError::InvalidOffset);
+                        return Err(self.invalid_offset(code, *ip, (*to).max(*input_offset)));
                     }
-                    self.tape[self.data_pointer - *to as usize] =
-                        self.tape[self.data_pointer - *to as usize].wrapping_sub(
-                            self.tape[self.data_pointer - input_offset as usize].wrapping_mul(*mul),
-                        );
-                    self.tape[self.data_pointer - input_offset as usize] = 0;
+                    let from = self.data_pointer - *input_offset as usize;
+                    let val = self.tape.cell(from).wrapping_mul(*mul);
+                    let cell = self.cell_mut(code, *ip, self.data_pointer - *to as usize)?;
+                    *cell = cell.wrapping_sub(val);
+                    *self.cell_mut(code, *ip, from)? = 0;
                 }
                 InterpIns::MulMove { mul, to } => {
-                    if self.data_pointer < *to as usize || self.data_pointer < input_offset as usize
+                    if CHECKED
+                        && (self.data_pointer < *to as usize
+                            || self.data_pointer < *input_offset as usize)
                     {
-                        return Err(InterpreteError::InvalidOffset);
+                        return Err(self.invalid_offset(code, *ip, (*to).max(*input_offset)));
                     }
-                    self.tape[self.data_pointer - *to as usize] =
-                        self.tape[self.data_pointer - *to as usize].wrapping_mul(
-                            self.tape[self.data_pointer - input_offset as usize].wrapping_mul(*mul),
-                        );
-                    self.tape[self.data_pointer - input_offset as usize] = 0;
+                    let from = self.data_pointer - *input_offset as usize;
+                    let val = self.tape.cell(from).wrapping_mul(*mul);
+                    let cell = self.cell_mut(code, *ip, self.data_pointer - *to as usize)?;
+                    *cell = cell.wrapping_mul(val);
+                    *self.cell_mut(code, *ip, from)? = 0;
                 }
                 InterpIns::Move { to } => {
-                    if self.data_pointer < *to as usize || self.data_pointer < input_offset as usize
+                    if CHECKED
+                        && (self.data_pointer < *to as usize
+                            || self.data_pointer < *input_offset as usize)
                     {
-                        return Err(InterpreteError::InvalidOffset);
+                        return Err(self.invalid_offset(code, *ip, (*to).max(*input_offset)));
                     }
-                    self.tape[self.data_pointer - *to as usize] =
-                        self.tape[self.data_pointer - input_offset as usize];
-                    self.tape[self.data_pointer - input_offset as usize] = 0;
+                    let from = self.data_pointer - *input_offset as usize;
+                    let val = self.tape.cell(from);
+                    *self.cell_mut(code, *ip, self.data_pointer - *to as usize)? = val;
+                    *self.cell_mut(code, *ip, from)? = 0;
                 }
                 InterpIns::Copy { to } => {
-                    if self.data_pointer < *to as usize || self.data_pointer < input_offset as usize
+                    if CHECKED
+                        && (self.data_pointer < *to as usize
+                            || self.data_pointer < *input_offset as usize)
                     {
-                        return Err(InterpreteError::InvalidOffset);
+                        return Err(self.invalid_offset(code, *ip, (*to).max(*input_offset)));
                     }
-                    self.tape[self.data_pointer - *to as usize] +=
-                        self.tape[self.data_pointer - input_offset as usize];
+                    let val = self.tape.cell(self.data_pointer - *input_offset as usize);
+                    let cell = self.cell_mut(code, *ip, self.data_pointer - *to as usize)?;
+                    *cell = cell.wrapping_add(val);
                 }
                 InterpIns::Putchar { offset } => {
-                    if self.data_pointer < *offset as usize {
-                        return Err(InterpreteError::InvalidOffset);
+                    if CHECKED && self.data_pointer < *offset as usize {
+                        return Err(self.invalid_offset(code, *ip, *offset));
                     }
-                    let ch = self.tape[self.data_pointer - *offset as usize];
-                    self.io_out.putchar(ch).map_err(InterpreteError::IOError)?;
+                    let ch = self.tape.cell(self.data_pointer - *offset as usize);
+                    self.io_out
+                        .putchar_at(ch, self.steps + *steps - 1)
+                        .map_err(|err| self.io_error(code, *ip, err))?;
+                    self.output_pos += 1;
                 }
                 InterpIns::Getchar { offset } => {
-                    if self.data_pointer < *offset as usize {
-                        return Err(InterpreteError::InvalidOffset);
+                    if CHECKED && self.data_pointer < *offset as usize {
+                        return Err(self.invalid_offset(code, *ip, *offset));
+                    }
+                    if !self.io_in.has_buffered_input() {
+                        // show prompt before waiting for input
+                        self.io_out
+                            .flush()
+                            .map_err(|err| self.io_error(code, *ip, err))?;
                     }
                     let ch = self.io_in
-                        .getchar()
-                        .map_err(InterpreteError::IOError)?;
-                    self.tape[self.data_pointer - *offset as usize] = ch;
+                        .getchar_at(self.steps + *steps - 1)
+                        .map_err(|err| self.io_error(code, *ip, err))?;
+                    *self.cell_mut(code, *ip, self.data_pointer - *offset as usize)? = ch;
+                    self.input_pos += 1;
                 }
                 InterpIns::JmpT { dest } => {
-                    if self.data_pointer < input_offset as usize {
-                        return Err(InterpreteError::InvalidOffset);
+                    if CHECKED && self.data_pointer < *input_offset as usize {
+                        return Err(self.invalid_offset(code, *ip, *input_offset));
                     }
-                    if self.tape[self.data_pointer - input_offset as usize] != 0 {
-                        ip = *dest as usize;
+                    if self.tape.cell(self.data_pointer - *input_offset as usize) != 0 {
+                        *ip = *dest as usize;
                         continue;
                     }
                 }
                 InterpIns::JmpF { dest } => {
-                    if self.data_pointer < input_offset as usize {
-                        return Err(InterpreteError::InvalidOffset);
+                    if CHECKED && self.data_pointer < *input_offset as usize {
+                        return Err(self.invalid_offset(code, *ip, *input_offset));
                     }
-                    if self.tape[self.data_pointer - input_offset as usize] == 0 {
-                        ip = *dest as usize;
+                    if self.tape.cell(self.data_pointer - *input_offset as usize) == 0 {
+                        *ip = *dest as usize;
                         continue;
                     }
                 }
                 InterpIns::Jmp { dest } => {
-                    ip = *dest as usize;
+                    *ip = *dest as usize;
                     continue;
                 }
+                // handled before step accounting
+                InterpIns::Unchecked { .. } => {}
             }
-            ip += 1;
+            *ip += 1;
         }
-        self.io_out.flush().map_err(InterpreteError::IOError)?;
-        Ok(())
+        Ok(RunStatus::Finished)
     }
-    #[inline(always)]
-    fn reserve_storage(&mut self) -> Result<(), InterpreteError> {
-        let l = self.tape.len();
-        let ptr = self.data_pointer;
-        if ptr >= l {
-            self.tape.resize((1 + ptr as usize).next_power_of_two(), 0)
+    pub(super) fn location(&self, code: &[InterpIns], ip: usize) -> ErrorLocation {
+        ErrorLocation {
+            ip,
+            ins: code[ip],
+            data_pointer: self.data_pointer,
+            span: None,
+        }
+    }
+    #[cold]
+    pub(super) fn invalid_offset(&self, code: &[InterpIns], ip: usize, offset: u32) -> InterpreteError {
+        InterpreteError::InvalidOffset {
+            at: self.location(code, ip),
+            offset,
         }
-        Ok(())
+    }
+    #[cold]
+    pub(super) fn io_error(&self, code: &[InterpIns], ip: usize, err: std::io::Error) -> InterpreteError {
+        InterpreteError::IOError {
+            at: Some(self.location(code, ip)),
+            err,
+        }
+    }
+    #[inline(always)]
+    pub(super) fn cell_mut(&mut self, code: &[InterpIns], ip: usize, index: usize) -> Result<&mut u8, InterpreteError> {
+        let data_pointer = self.data_pointer;
+        self.tape.cell_mut(index).map_err(|err| InterpreteError::MemoryLimit {
+            at: Some(ErrorLocation {
+                ip,
+                ins: code[ip],
+                data_pointer,
+                span: None,
+            }),
+            limit: err.limit,
+        })
+    }
+    #[inline(always)]
+    pub(super) fn reserve_storage(&mut self, code: &[InterpIns], ip: usize, index: usize) -> Result<(), InterpreteError> {
+        self.tape.grow_to(index).map_err(|err| InterpreteError::MemoryLimit {
+            at: Some(self.location(code, ip)),
+            limit: err.limit,
+        })
     }
 }
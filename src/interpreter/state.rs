@@ -0,0 +1,222 @@
+use super::{
+    tape::{Tape, TapeImage},
+    Interpreter,
+};
+use std::io::{Read, Write};
+
+/// Full execution state of [`Interpreter`]
+///
+/// Can be saved with [`ExecState::write_to`] and restored later with [`Interpreter::restore`].
+/// IO streams are not part of state: [`ExecState::input_pos`] bytes of input
+/// must be skipped (and [`ExecState::output_pos`] bytes of output kept) by caller before resuming
+/// ```
+/// # use bf_tools::{ bf, interpreter::{ Interpreter, InterpCode, state::ExecState } };
+/// let code = InterpCode::from(bf!(,[.,]));
+/// let mut out = Vec::new();
+/// let mut interpreter = Interpreter::builder().set_stdin(&b"abc"[..]).set_stdout(&mut out).build();
+/// interpreter.run_for(&code, 5).unwrap();
+///
+/// let mut file = Vec::new();
+/// interpreter.snapshot().write_to(&mut file).unwrap();
+/// let state = ExecState::read_from(&file[..]).unwrap();
+/// assert_eq!((state.input_pos, state.output_pos), (2, 1));
+///
+/// let mut out = Vec::new();
+/// let mut interpreter = Interpreter::builder().set_stdin(&b"c\0"[..]).set_stdout(&mut out).build();
+/// interpreter.restore(state);
+/// interpreter.resume(&code).unwrap();
+/// drop(interpreter);
+/// assert_eq!(out, b"bc");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ExecState {
+    /// Index of next instruction to execute
+    pub ip: usize,
+    /// Offset of cell used by `jmp_t`/`jmp_f` and `*_move` instructions
+    pub input_offset: u32,
+    /// Count of executed instructions
+    pub steps: u64,
+    /// Count of bytes read by Getchar instructions
+    pub input_pos: u64,
+    /// Count of bytes written by Putchar instructions
+    pub output_pos: u64,
+    /// Data tape
+    pub tape: TapeImage,
+    /// Current pointer location on tape
+    pub data_pointer: usize,
+}
+
+const MAGIC: &[u8; 4] = b"BFST";
+const VERSION: u8 = 1;
+
+/// Longest tape accepted by [`ExecState::read_from`] (1 GiB)
+///
+/// Contiguous tape allocates all cells on [`Interpreter::restore`],
+/// so corrupted tape length could abort on allocation failure
+pub const DEFAULT_MAX_TAPE_LEN: usize = 1 << 30;
+
+fn write_varint(out: &mut impl Write, mut v: u64) -> std::io::Result<()> {
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            return out.write_all(&[byte]);
+        }
+        out.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint(input: &mut impl Read) -> std::io::Result<u64> {
+    let mut res = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8];
+        input.read_exact(&mut byte)?;
+        res |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(res);
+        }
+    }
+    Err(invalid_data("varint is too long"))
+}
+
+fn invalid_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+fn to_usize(v: u64) -> std::io::Result<usize> {
+    usize::try_from(v).map_err(|_| invalid_data("value does not fit in usize"))
+}
+
+impl ExecState {
+    /// Write state in compact binary format
+    ///
+    /// Format: `BFST` magic, version byte, LEB128 encoded registers and tape length,
+    /// then tape as `(zero_count, literal_len, literal bytes...)` runs
+    /// # Errors
+    /// return `Err` if writing to `out` fails
+    pub fn write_to(&self, mut out: impl Write) -> std::io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        for v in [
+            self.ip as u64,
+            u64::from(self.input_offset),
+            self.steps,
+            self.input_pos,
+            self.output_pos,
+            self.data_pointer as u64,
+            self.tape.len() as u64,
+        ] {
+            write_varint(&mut out, v)?;
+        }
+        let mut pos = 0;
+        for (start, bytes) in self.tape.runs() {
+            write_varint(&mut out, (start - pos) as u64)?;
+            write_varint(&mut out, bytes.len() as u64)?;
+            out.write_all(bytes)?;
+            pos = start + bytes.len();
+        }
+        if pos < self.tape.len() {
+            write_varint(&mut out, (self.tape.len() - pos) as u64)?;
+            write_varint(&mut out, 0)?;
+        }
+        Ok(())
+    }
+    /// Read state written by [`ExecState::write_to`] with tape of at most [`DEFAULT_MAX_TAPE_LEN`] cells
+    /// # Errors
+    /// return `Err` if reading fails or data is not valid state
+    #[inline]
+    pub fn read_from(input: impl Read) -> std::io::Result<Self> {
+        Self::read_from_with_limit(input, DEFAULT_MAX_TAPE_LEN)
+    }
+    /// Read state written by [`ExecState::write_to`] with tape of at most `max_tape_len` cells
+    ///
+    /// Use it for longer tapes, e.g. [`super::tape::PagedTape`] which doesn't allocate unused cells
+    /// ```
+    /// # use bf_tools::interpreter::state::ExecState;
+    /// let state = ExecState { tape: vec![0; 100].as_slice().into(), ..Default::default() };
+    /// let mut file = Vec::new();
+    /// state.write_to(&mut file).unwrap();
+    /// assert_eq!(ExecState::read_from_with_limit(&file[..], 100).unwrap(), state);
+    ///
+    /// let err = ExecState::read_from_with_limit(&file[..], 99).unwrap_err();
+    /// assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    /// ```
+    /// # Errors
+    /// return `Err` if reading fails, data is not valid state or tape is longer than `max_tape_len`
+    pub fn read_from_with_limit(mut input: impl Read, max_tape_len: usize) -> std::io::Result<Self> {
+        let mut header = [0u8; 5];
+        input.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid_data("invalid state magic"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid_data("unsupported state version"));
+        }
+        let ip = to_usize(read_varint(&mut input)?)?;
+        let input_offset = u32::try_from(read_varint(&mut input)?)
+            .map_err(|_| invalid_data("input offset does not fit in u32"))?;
+        let steps = read_varint(&mut input)?;
+        let input_pos = read_varint(&mut input)?;
+        let output_pos = read_varint(&mut input)?;
+        let data_pointer = to_usize(read_varint(&mut input)?)?;
+        let tape_len = to_usize(read_varint(&mut input)?)?;
+        if tape_len > max_tape_len {
+            return Err(invalid_data("tape is too long"));
+        }
+        let mut tape = TapeImage::new(tape_len);
+        let mut pos = 0;
+        while pos < tape_len {
+            let zeros = to_usize(read_varint(&mut input)?)?;
+            let literal = to_usize(read_varint(&mut input)?)?;
+            if zeros.saturating_add(literal) > tape_len - pos {
+                return Err(invalid_data("tape run out of bounds"));
+            }
+            if zeros == 0 && literal == 0 {
+                return Err(invalid_data("empty tape run"));
+            }
+            pos += zeros;
+            // buffer grows with read data, so invalid length doesn't allocate
+            let mut bytes = Vec::new();
+            (&mut input).take(literal as u64).read_to_end(&mut bytes)?;
+            if bytes.len() != literal {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            tape.push(pos, &bytes);
+            pos += literal;
+        }
+        Ok(Self {
+            ip,
+            input_offset,
+            steps,
+            input_pos,
+            output_pos,
+            tape,
+            data_pointer,
+        })
+    }
+}
+
+impl<T: Tape> Interpreter<'_, T> {
+    /// Capture current execution state
+    pub fn snapshot(&self) -> ExecState {
+        ExecState {
+            ip: self.ip,
+            input_offset: self.input_offset,
+            steps: self.steps,
+            input_pos: self.input_pos,
+            output_pos: self.output_pos,
+            tape: self.tape.image(),
+            data_pointer: self.data_pointer,
+        }
+    }
+    /// Replace execution state (use [`Interpreter::resume`] to continue execution)
+    pub fn restore(&mut self, state: ExecState) {
+        self.ip = state.ip;
+        self.input_offset = state.input_offset;
+        self.steps = state.steps;
+        self.input_pos = state.input_pos;
+        self.output_pos = state.output_pos;
+        self.tape.load(state.tape);
+        self.data_pointer = state.data_pointer;
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+
+/// Tape allocation failed because it would exceed memory limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MemoryLimitExceeded {
+    /// Memory limit in bytes
+    pub limit: usize,
+}
+
+impl std::fmt::Display for MemoryLimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "tape memory limit of {} bytes exceeded", self.limit)
+    }
+}
+
+impl std::error::Error for MemoryLimitExceeded {}
+
+/// Sparse copy of tape contents (see [`Tape::image`])
+///
+/// Stores runs of bytes with their start indices, cells between runs are 0
+/// ```
+/// # use bf_tools::interpreter::tape::TapeImage;
+/// let image = TapeImage::from(&[0, 0, 0, 0, 1, 0, 2, 0, 0, 0, 3, 0][..]);
+/// assert_eq!(image.len(), 12);
+/// assert_eq!(image.runs(), [(4, vec![1, 0, 2]), (10, vec![3])]);
+/// assert_eq!(image.cell(6), 2);
+/// assert_eq!(image.to_vec(), [0, 0, 0, 0, 1, 0, 2, 0, 0, 0, 3, 0]);
+///
+/// let mut image = TapeImage::new(1 << 40);
+/// image.push(1 << 39, &[5]);
+/// assert_eq!(image.cell(1 << 39), 5);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct TapeImage {
+    len: usize,
+    /// `(start, bytes)` sorted by start
+    runs: Vec<(usize, Vec<u8>)>,
+}
+
+impl TapeImage {
+    /// Create image of `len` zero cells
+    #[inline]
+    pub const fn new(len: usize) -> Self {
+        Self {
+            len,
+            runs: Vec::new(),
+        }
+    }
+    /// Count of cells in image
+    #[inline]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+    /// Check if image has no cells
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// `(start, bytes)` runs sorted by start
+    #[inline]
+    pub fn runs(&self) -> &[(usize, Vec<u8>)] {
+        &self.runs
+    }
+    /// Write `bytes` at `start`
+    /// # Panics
+    /// Panics if `bytes` overlap previous run or don't fit in image
+    pub fn push(&mut self, start: usize, bytes: &[u8]) {
+        let end = self.runs.last().map_or(0, |(s, run)| s + run.len());
+        assert!(start >= end, "run at {start} overlaps previous run");
+        assert!(
+            start.checked_add(bytes.len()).is_some_and(|e| e <= self.len),
+            "run at {start} is out of image"
+        );
+        if bytes.is_empty() {
+            return;
+        }
+        match self.runs.last_mut() {
+            Some((_, run)) if start == end => run.extend_from_slice(bytes),
+            _ => self.runs.push((start, bytes.to_vec())),
+        }
+    }
+    /// Write `bytes` at `start` skipping long runs of zeros
+    fn extend(&mut self, start: usize, bytes: &[u8]) {
+        let mut i = 0;
+        while i < bytes.len() {
+            i += bytes[i..].iter().take_while(|v| **v == 0).count();
+            if i == bytes.len() {
+                break;
+            }
+            // split run only on zeros long enough to pay for run header
+            let mut end = i;
+            while end < bytes.len() && !bytes[end..].starts_with(&[0; 3]) {
+                end += 1;
+            }
+            while bytes[end - 1] == 0 {
+                end -= 1;
+            }
+            self.push(start + i, &bytes[i..end]);
+            i = end;
+        }
+    }
+    /// Value of cell at `index` (0 if it's out of image)
+    pub fn cell(&self, index: usize) -> u8 {
+        let run = self.runs.partition_point(|(start, _)| *start <= index);
+        run.checked_sub(1)
+            .and_then(|run| {
+                let (start, bytes) = &self.runs[run];
+                bytes.get(index - start).copied()
+            })
+            .unwrap_or_default()
+    }
+    /// Dense copy of image
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut res = vec![0; self.len];
+        for (start, bytes) in &self.runs {
+            res[*start..start + bytes.len()].copy_from_slice(bytes);
+        }
+        res
+    }
+}
+
+impl From<&[u8]> for TapeImage {
+    fn from(bytes: &[u8]) -> Self {
+        let mut image = Self::new(bytes.len());
+        image.extend(0, bytes);
+        image
+    }
+}
+
+/// Storage for cells of [`super::Interpreter`]
+///
+/// Cells which were never written are 0.
+/// Interpreter calls [`Tape::grow_to`] every time data pointer moves right,
+/// and accesses only cells at or before data pointer
+pub trait Tape: std::fmt::Debug {
+    /// Value of cell at `index`
+    fn cell(&self, index: usize) -> u8;
+    /// Mutable reference to cell at `index`
+    /// # Errors
+    /// return `Err` if cell can't be allocated without exceeding memory limit
+    fn cell_mut(&mut self, index: usize) -> Result<&mut u8, MemoryLimitExceeded>;
+    /// Called when data pointer moves to `index`
+    /// # Errors
+    /// return `Err` if storage can't grow without exceeding memory limit
+    fn grow_to(&mut self, index: usize) -> Result<(), MemoryLimitExceeded>;
+    /// Set all cells to 0
+    fn clear(&mut self);
+    /// Sparse copy of tape contents up to last allocated cell
+    fn image(&self) -> TapeImage;
+    /// Replace tape contents with `image` (all cells after it are 0)
+    fn load(&mut self, image: TapeImage);
+}
+
+/// Contiguous tape, grows to next power of two past data pointer
+impl Tape for Vec<u8> {
+    #[inline(always)]
+    fn cell(&self, index: usize) -> u8 {
+        self[index]
+    }
+    #[inline(always)]
+    fn cell_mut(&mut self, index: usize) -> Result<&mut u8, MemoryLimitExceeded> {
+        Ok(&mut self[index])
+    }
+    #[inline(always)]
+    fn grow_to(&mut self, index: usize) -> Result<(), MemoryLimitExceeded> {
+        if index >= self.len() {
+            self.resize((1 + index).next_power_of_two(), 0);
+        }
+        Ok(())
+    }
+    #[inline]
+    fn clear(&mut self) {
+        Vec::clear(self);
+    }
+    #[inline]
+    fn image(&self) -> TapeImage {
+        TapeImage::from(&self[..])
+    }
+    #[inline]
+    fn load(&mut self, image: TapeImage) {
+        *self = image.to_vec();
+    }
+}
+
+/// Size of [`PagedTape`] page in bytes
+pub const PAGE_SIZE: usize = 4096;
+
+/// Sparse tape made of fixed-size pages allocated on first write
+///
+/// Programs that move far right and touch few cells use only few pages.
+/// Pages are kept in map by page index, so memory use depends only on count of written pages
+/// (including [`Tape::image`], which skips unallocated pages)
+/// ```
+/// # use bf_tools::{ bf, ins::BfCode, interpreter::{ Interpreter, InterpreteError, tape::* } };
+/// let code: BfCode = (">".repeat(1_000_000) + "+").parse().unwrap();
+/// let mut interpreter = Interpreter::builder().set_tape(PagedTape::new()).build();
+/// interpreter.run(code).unwrap();
+/// assert_eq!(interpreter.tape.cell(1_000_000), 1);
+/// assert_eq!(interpreter.tape.allocated(), PAGE_SIZE);
+/// assert_eq!(interpreter.tape.image().runs(), [(1_000_000, vec![1])]);
+///
+/// let mut interpreter = Interpreter::builder()
+///     .set_tape(PagedTape::new().with_memory_limit(10000))
+///     .build();
+/// let err = interpreter.run(bf!(+[>>>>>>>>>>+])).unwrap_err();
+/// assert!(matches!(err, InterpreteError::MemoryLimit { limit: 10000, .. }));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PagedTape {
+    pages: BTreeMap<usize, Box<[u8; PAGE_SIZE]>>,
+    limit: Option<usize>,
+}
+
+impl PagedTape {
+    /// Create empty tape without memory limit
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            pages: BTreeMap::new(),
+            limit: None,
+        }
+    }
+    /// Fail allocations after `bytes` bytes of pages
+    #[inline]
+    #[must_use]
+    pub const fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.limit = Some(bytes);
+        self
+    }
+    /// Memory limit in bytes
+    #[inline]
+    pub const fn memory_limit(&self) -> Option<usize> {
+        self.limit
+    }
+    /// Bytes allocated for pages
+    #[inline]
+    pub fn allocated(&self) -> usize {
+        self.pages.len() * PAGE_SIZE
+    }
+    fn page_mut(&mut self, page: usize) -> &mut [u8; PAGE_SIZE] {
+        self.pages
+            .entry(page)
+            .or_insert_with(|| Box::new([0; PAGE_SIZE]))
+    }
+}
+
+impl Tape for PagedTape {
+    #[inline(always)]
+    fn cell(&self, index: usize) -> u8 {
+        self.pages
+            .get(&(index / PAGE_SIZE))
+            .map_or(0, |page| page[index % PAGE_SIZE])
+    }
+    #[inline]
+    fn cell_mut(&mut self, index: usize) -> Result<&mut u8, MemoryLimitExceeded> {
+        let page = index / PAGE_SIZE;
+        let allocated = self.pages.contains_key(&page);
+        if let Some(limit) = self.limit {
+            if !allocated && self.allocated() + PAGE_SIZE > limit {
+                return Err(MemoryLimitExceeded { limit });
+            }
+        }
+        Ok(&mut self.page_mut(page)[index % PAGE_SIZE])
+    }
+    #[inline(always)]
+    fn grow_to(&mut self, _index: usize) -> Result<(), MemoryLimitExceeded> {
+        // pages are allocated on write
+        Ok(())
+    }
+    fn clear(&mut self) {
+        self.pages.clear();
+    }
+    fn image(&self) -> TapeImage {
+        let len = self.pages.keys().next_back().map_or(0, |p| (p + 1) * PAGE_SIZE);
+        let mut image = TapeImage::new(len);
+        for (page, bytes) in &self.pages {
+            image.extend(page * PAGE_SIZE, &bytes[..]);
+        }
+        image
+    }
+    /// Loaded pages are not checked against memory limit
+    fn load(&mut self, image: TapeImage) {
+        self.clear();
+        for (start, bytes) in image.runs {
+            for (i, v) in bytes.into_iter().enumerate().filter(|(_, v)| *v != 0) {
+                let cell = start + i;
+                self.page_mut(cell / PAGE_SIZE)[cell % PAGE_SIZE] = v;
+            }
+        }
+    }
+}
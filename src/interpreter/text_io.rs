@@ -0,0 +1,370 @@
+use super::{InterprIOIn, InterprIOOut};
+use std::{collections::VecDeque, io::Write};
+
+/// What to do with bytes which are not valid UTF-8
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum InvalidUtf8Policy {
+    /// Pass bytes as is
+    Raw,
+    /// Replace each invalid sequence with `U+FFFD`
+    Replace,
+    /// Replace each byte with `\xNN` escape
+    Escape,
+    /// Replace each byte with space
+    #[default]
+    Spaces,
+}
+
+/// What to do with valid non-ASCII chars
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum NonAsciiPolicy {
+    /// Pass chars as is
+    #[default]
+    Allow,
+    /// Fail with [`std::io::ErrorKind::InvalidData`] error
+    Reject,
+    /// Replace char with `\u{NNNN}` escape
+    Escape,
+}
+
+/// Short byte sequence (up to single UTF-8 char)
+#[derive(Debug, Clone, Copy, Default)]
+struct Seq {
+    buf: [u8; 4],
+    len: usize,
+}
+
+impl Seq {
+    fn bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// Part of byte stream decoded by [`Utf8Decoder`]
+#[derive(Debug, Clone, Copy)]
+enum Piece {
+    Valid(char, Seq),
+    Invalid(Seq),
+}
+
+const fn utf8_len(lead: u8) -> Option<usize> {
+    match lead {
+        0x00..=0x7f => Some(1),
+        0xc2..=0xdf => Some(2),
+        0xe0..=0xef => Some(3),
+        0xf0..=0xf4 => Some(4),
+        _ => None,
+    }
+}
+
+/// Incremental UTF-8 decoder
+#[derive(Debug, Clone, Copy, Default)]
+struct Utf8Decoder {
+    seq: Seq,
+}
+
+impl Utf8Decoder {
+    /// Push next byte, return decoded pieces (up to two when byte interrupts sequence)
+    fn push(&mut self, byte: u8) -> [Option<Piece>; 2] {
+        let mut res = [None, None];
+        if self.seq.len > 0 {
+            if byte & 0xc0 == 0x80 {
+                self.seq.buf[self.seq.len] = byte;
+                self.seq.len += 1;
+                if Some(self.seq.len) == utf8_len(self.seq.buf[0]) {
+                    let seq = std::mem::take(&mut self.seq);
+                    res[0] = Some(match std::str::from_utf8(seq.bytes()) {
+                        Ok(s) => Piece::Valid(s.chars().next().unwrap_or_default(), seq),
+                        Err(_) => Piece::Invalid(seq),
+                    });
+                }
+                return res;
+            }
+            // sequence interrupted by non continuation byte
+            res[0] = self.finish();
+        }
+        let seq = Seq {
+            buf: [byte, 0, 0, 0],
+            len: 1,
+        };
+        res[1] = match utf8_len(byte) {
+            Some(1) => Some(Piece::Valid(byte as char, seq)),
+            Some(_) => {
+                self.seq = seq;
+                None
+            }
+            None => Some(Piece::Invalid(seq)),
+        };
+        res
+    }
+    /// Take incomplete sequence as invalid
+    fn finish(&mut self) -> Option<Piece> {
+        (self.seq.len > 0).then(|| Piece::Invalid(std::mem::take(&mut self.seq)))
+    }
+}
+
+fn write_piece(
+    out: &mut impl Write,
+    piece: Piece,
+    invalid: InvalidUtf8Policy,
+    non_ascii: NonAsciiPolicy,
+) -> std::io::Result<()> {
+    match piece {
+        Piece::Valid(ch, seq) if ch.is_ascii() || non_ascii == NonAsciiPolicy::Allow => {
+            out.write_all(seq.bytes())
+        }
+        Piece::Valid(ch, _) if non_ascii == NonAsciiPolicy::Escape => {
+            write!(out, "\\u{{{:x}}}", ch as u32)
+        }
+        Piece::Valid(ch, _) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("non-ASCII char {ch:?} in ASCII-only stream"),
+        )),
+        Piece::Invalid(seq) => match invalid {
+            InvalidUtf8Policy::Raw => out.write_all(seq.bytes()),
+            InvalidUtf8Policy::Replace => match non_ascii {
+                NonAsciiPolicy::Allow => out.write_all("\u{fffd}".as_bytes()),
+                _ => out.write_all(b"?"),
+            },
+            InvalidUtf8Policy::Escape => {
+                for b in seq.bytes() {
+                    write!(out, "\\x{b:02x}")?;
+                }
+                Ok(())
+            }
+            InvalidUtf8Policy::Spaces => {
+                for _ in seq.bytes() {
+                    out.write_all(b" ")?; // fill with empty symbol instead of invalid utf-8
+                }
+                Ok(())
+            }
+        },
+    }
+}
+
+/// Wrapper for output streams that help group bytes into utf-8 chars
+///
+/// By default invalid UTF-8 sequences are replaced with spaces.
+/// Incomplete char is kept by [`InterprIOOut::flush`] and written out by [`InterprIOOut::finish`]
+/// ```
+/// # use bf_tools::interpreter::{ DefaultWriter, InterprIOOut, InvalidUtf8Policy, NonAsciiPolicy };
+/// let mut out = Vec::new();
+/// let mut writer = DefaultWriter::new(&mut out)
+///     .invalid_utf8(InvalidUtf8Policy::Escape)
+///     .non_ascii(NonAsciiPolicy::Escape);
+/// for b in "я".bytes().chain([b'!', 0xd1]) {
+///     writer.putchar(b).unwrap();
+///     writer.flush().unwrap();
+/// }
+/// writer.finish().unwrap();
+/// # drop(writer);
+/// assert_eq!(out, br"\u{44f}!\xd1");
+/// ```
+#[derive(Debug)]
+pub struct DefaultWriter<T: Write + std::fmt::Debug> {
+    inner: T,
+    decoder: Utf8Decoder,
+    invalid: InvalidUtf8Policy,
+    non_ascii: NonAsciiPolicy,
+}
+
+impl<T: Write + std::fmt::Debug> DefaultWriter<T> {
+    /// Create new writer
+    pub const fn new(inner: T) -> Self {
+        Self {
+            inner,
+            decoder: Utf8Decoder {
+                seq: Seq {
+                    buf: [0; 4],
+                    len: 0,
+                },
+            },
+            invalid: InvalidUtf8Policy::Spaces,
+            non_ascii: NonAsciiPolicy::Allow,
+        }
+    }
+    /// Set policy for invalid UTF-8 sequences
+    #[inline]
+    #[must_use]
+    pub const fn invalid_utf8(mut self, policy: InvalidUtf8Policy) -> Self {
+        self.invalid = policy;
+        self
+    }
+    /// Set policy for non-ASCII chars (ASCII-only mode)
+    #[inline]
+    #[must_use]
+    pub const fn non_ascii(mut self, policy: NonAsciiPolicy) -> Self {
+        self.non_ascii = policy;
+        self
+    }
+    /// Get underlying stream
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Write + std::fmt::Debug> InterprIOOut for DefaultWriter<T> {
+    fn putchar(&mut self, ch: u8) -> std::io::Result<()> {
+        for piece in self.decoder.push(ch).into_iter().flatten() {
+            write_piece(&mut self.inner, piece, self.invalid, self.non_ascii)?;
+        }
+        Ok(())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+    fn finish(&mut self) -> std::io::Result<()> {
+        if let Some(piece) = self.decoder.finish() {
+            write_piece(&mut self.inner, piece, self.invalid, self.non_ascii)?;
+        }
+        self.inner.flush()
+    }
+}
+
+/// Input stream that reads text as UTF-8 chars and sends them to program byte by byte
+///
+/// By default all bytes are passed as is
+/// ```
+/// # use bf_tools::interpreter::{ DefaultReader, InterprIOIn, NonAsciiPolicy };
+/// let mut reader = DefaultReader::new("ы\r\n".as_bytes())
+///     .crlf_to_lf(true)
+///     .non_ascii(NonAsciiPolicy::Escape);
+/// let mut res = Vec::new();
+/// while let Ok(b) = reader.getchar() {
+///     res.push(b);
+/// }
+/// assert_eq!(res, b"\\u{44b}\n");
+/// ```
+/// When bytes are passed as is, program doesn't wait for rest of UTF-8 char:
+/// ```
+/// # use bf_tools::interpreter::{ DefaultReader, InterprIOIn };
+/// # use std::io::Read;
+/// #[derive(Debug)]
+/// struct Blocked;
+/// impl Read for Blocked {
+///     fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+///         Err(std::io::ErrorKind::WouldBlock.into())
+///     }
+/// }
+/// let mut reader = DefaultReader::new((&b"\xd1"[..]).chain(Blocked));
+/// assert_eq!(reader.getchar().unwrap(), 0xd1);
+/// assert!(reader.getchar().is_err());
+/// ```
+#[derive(Debug)]
+pub struct DefaultReader<T: InterprIOIn> {
+    inner: T,
+    decoder: Utf8Decoder,
+    /// decoded bytes not yet read by program
+    pending: VecDeque<u8>,
+    /// `\r` waiting for next char
+    pending_cr: bool,
+    crlf_to_lf: bool,
+    invalid: InvalidUtf8Policy,
+    non_ascii: NonAsciiPolicy,
+}
+
+impl<T: InterprIOIn> DefaultReader<T> {
+    /// Create new reader
+    pub const fn new(inner: T) -> Self {
+        Self {
+            inner,
+            decoder: Utf8Decoder {
+                seq: Seq {
+                    buf: [0; 4],
+                    len: 0,
+                },
+            },
+            pending: VecDeque::new(),
+            pending_cr: false,
+            crlf_to_lf: false,
+            invalid: InvalidUtf8Policy::Raw,
+            non_ascii: NonAsciiPolicy::Allow,
+        }
+    }
+    /// Translate `\r\n` line endings into `\n`
+    #[inline]
+    #[must_use]
+    pub const fn crlf_to_lf(mut self, enable: bool) -> Self {
+        self.crlf_to_lf = enable;
+        self
+    }
+    /// Set policy for invalid UTF-8 sequences
+    #[inline]
+    #[must_use]
+    pub const fn invalid_utf8(mut self, policy: InvalidUtf8Policy) -> Self {
+        self.invalid = policy;
+        self
+    }
+    /// Set policy for non-ASCII chars (ASCII-only mode)
+    #[inline]
+    #[must_use]
+    pub const fn non_ascii(mut self, policy: NonAsciiPolicy) -> Self {
+        self.non_ascii = policy;
+        self
+    }
+    /// Get underlying stream
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn emit(&mut self, piece: Piece) -> std::io::Result<()> {
+        let mut out = Vec::new();
+        let is_lf = matches!(piece, Piece::Valid('\n', _));
+        if std::mem::take(&mut self.pending_cr) && !(self.crlf_to_lf && is_lf) {
+            out.push(b'\r');
+        }
+        if self.crlf_to_lf && matches!(piece, Piece::Valid('\r', _)) {
+            self.pending_cr = true;
+        } else {
+            write_piece(&mut out, piece, self.invalid, self.non_ascii)?;
+        }
+        self.pending.extend(out);
+        Ok(())
+    }
+}
+
+impl<T: InterprIOIn> InterprIOIn for DefaultReader<T> {
+    fn getchar(&mut self) -> std::io::Result<u8> {
+        while self.pending.is_empty() {
+            let byte = match self.inner.getchar() {
+                Ok(byte) => byte,
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    if let Some(piece) = self.decoder.finish() {
+                        self.emit(piece)?;
+                    }
+                    if std::mem::take(&mut self.pending_cr) {
+                        self.pending.push_back(b'\r');
+                    }
+                    break;
+                }
+                Err(err) => return Err(err),
+            };
+            if self.invalid == InvalidUtf8Policy::Raw && self.non_ascii == NonAsciiPolicy::Allow {
+                // every byte is passed as is, so chars aren't assembled
+                let seq = Seq {
+                    buf: [byte, 0, 0, 0],
+                    len: 1,
+                };
+                let piece = if byte.is_ascii() {
+                    Piece::Valid(byte as char, seq)
+                } else {
+                    Piece::Invalid(seq)
+                };
+                self.emit(piece)?;
+                continue;
+            }
+            for piece in self.decoder.push(byte).into_iter().flatten() {
+                self.emit(piece)?;
+            }
+        }
+        self.pending
+            .pop_front()
+            .ok_or_else(|| std::io::ErrorKind::UnexpectedEof.into())
+    }
+    #[inline]
+    fn has_buffered_input(&self) -> bool {
+        !self.pending.is_empty() || self.inner.has_buffered_input()
+    }
+}
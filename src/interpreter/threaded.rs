@@ -0,0 +1,695 @@
+use super::{run::RunStatus, tape::Tape, InterpIns, InterpreteError, Interpreter};
+
+/// Instruction dispatch strategy of [`Interpreter`]
+///
+/// Both backends produce same output, errors and step counts
+/// ```
+/// # use bf_tools::{ bf, interpreter::{ Interpreter, threaded::Backend } };
+/// let code = bf!(++++++++[>++++++++<-]>+.+.+.);
+/// let mut out = Vec::new();
+/// let mut interpreter = Interpreter::builder()
+///     .set_backend(Backend::Threaded)
+///     .set_stdout(&mut out)
+///     .build();
+/// interpreter.run(code).unwrap();
+/// drop(interpreter);
+/// assert_eq!(out, b"ABC");
+/// ```
+/// Same programs give same results on both backends, including errors and step-limited runs:
+/// ```
+/// # use bf_tools::{ ins::BfCode, interpreter::{ *, InterpIns::*, run::RunStatus, tape::*, threaded::Backend } };
+/// fn run<T: Tape>(code: &InterpCode, tape: T, backend: Backend, limit: u64) -> impl PartialEq + std::fmt::Debug {
+///     let mut out = Vec::new();
+///     let mut it = Interpreter::builder()
+///         .set_stdin(&b"hi\x01"[..])
+///         .set_stdout(&mut out)
+///         .set_tape(tape)
+///         .set_backend(backend)
+///         .build();
+///     let mut stops = Vec::new();
+///     let res = loop {
+///         match it.run_for(code, limit) {
+///             Ok(RunStatus::StepLimit) => stops.push((it.ip, it.data_pointer, it.steps)),
+///             res => break res.map_err(|err| (std::mem::discriminant(&err), err.location().map(|at| at.ip))),
+///         }
+///     };
+///     let state = (res, stops, it.data_pointer, it.tape.image());
+///     drop(it);
+///     (state, out)
+/// }
+/// let bf = |src: &str| InterpCode::from(src.parse::<BfCode>().unwrap());
+/// let programs = [
+///     bf("++++++++[>++++++++<-]>+.+.+.>+[>+>++<<-]>>[-<+>]<."),
+///     bf(",[.,]"),
+///     bf(">>,[>+<-]>[<<+>>-]<<[->+<]>.<<<"), // underflow after loops
+///     bf(">+[<+]"),                          // underflow inside loop
+///     bf(">>>[-]<<<[>]+++[>+++<-]>.,,,,"),   // io error at end of input
+///     // superinstructions and invalid offset
+///     InterpCode(vec![
+///         PtrAdd { offset: 2 }, Putchar { offset: 0 }, PtrSub { offset: 2 }, Getchar { offset: 1 },
+///         PtrAdd { offset: 1 }, Getchar { offset: 0 }, PtrSub { offset: 1 },
+///     ]),
+/// ];
+/// for code in &programs {
+///     for limit in [u64::MAX, 1, 2, 3, 7, 20] {
+///         let matched = run(code, Vec::new(), Backend::Match, limit);
+///         assert_eq!(matched, run(code, Vec::new(), Backend::Threaded, limit), "{code} {limit}");
+///     }
+/// }
+/// // memory limit
+/// let code = bf(&(">".repeat(PAGE_SIZE * 3) + "+[<+>>+]"));
+/// for limit in [u64::MAX, 5] {
+///     let tape = || PagedTape::new().with_memory_limit(PAGE_SIZE * 2);
+///     assert_eq!(run(&code, tape(), Backend::Match, limit), run(&code, tape(), Backend::Threaded, limit));
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Backend {
+    /// `match` over every instruction
+    #[default]
+    Match,
+    /// Code is compiled into table of pre-resolved handlers before execution,
+    /// common instruction sequences are fused into superinstructions.
+    ///
+    /// Compiled code is kept for next calls with same code (identified by its address and length),
+    /// so code must not be changed in place between calls; [`Interpreter::run`] and [`Interpreter::reset`] drop it.
+    /// Profiling and [`Interpreter::step`] always use [`Backend::Match`]
+    Threaded,
+}
+
+type Handler<T> =
+    fn(&mut Interpreter<'_, T>, &Op<T>, &mut Ctx<'_>) -> Result<usize, InterpreteError>;
+
+/// Code compiled by [`compile`], cached in [`Interpreter`] between calls of [`exec`]
+pub(super) struct Compiled<T: Tape> {
+    /// Address and length of compiled code
+    key: (usize, usize),
+    ops: Vec<Op<T>>,
+    /// Index of op for every instruction index
+    pcs: Vec<usize>,
+    /// End of `Unchecked` region for every instruction inside region
+    region_ends: Vec<Option<usize>>,
+}
+
+impl<T: Tape> std::fmt::Debug for Compiled<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Compiled")
+            .field("key", &self.key)
+            .field("ops", &self.ops.len())
+            .finish_non_exhaustive()
+    }
+}
+
+fn key(code: &[InterpIns]) -> (usize, usize) {
+    (code.as_ptr() as usize, code.len())
+}
+
+/// Compiled instruction (or superinstruction)
+struct Op<T: Tape> {
+    run: Handler<T>,
+    /// Index of first fused instruction
+    ip: u32,
+    /// Count of fused instructions (0 for `Unchecked` marker)
+    len: u32,
+    /// Offset (or max offset of `Unchecked`)
+    a: u32,
+    /// Value, multiplier or jump destination
+    b: u32,
+}
+
+struct Ctx<'c> {
+    code: &'c [InterpIns],
+    /// Index of executed op
+    pc: usize,
+    input_offset: u32,
+    /// Executed instructions including all instructions of current op
+    steps: u64,
+    /// Index of executed instruction inside superinstruction (set before fallible parts)
+    sub: u32,
+}
+
+impl<T: Tape> Op<T> {
+    #[inline(always)]
+    const fn ip(&self, ctx: &Ctx<'_>) -> usize {
+        (self.ip + ctx.sub) as usize
+    }
+}
+
+/// Execute `code` with threaded dispatch, same contract as `Interpreter::exec::<true>`
+pub(super) fn exec<T: Tape>(
+    it: &mut Interpreter<'_, T>,
+    code: &[InterpIns],
+    ip: &mut usize,
+    input_offset: &mut u32,
+    steps: &mut u64,
+    max_steps: u64,
+) -> Result<RunStatus, InterpreteError> {
+    it.tape
+        .grow_to(it.data_pointer)
+        .map_err(|err| InterpreteError::MemoryLimit {
+            at: None,
+            limit: err.limit,
+        })?;
+    let compiled = match it.compiled.take() {
+        Some(compiled) if compiled.key == key(code) => compiled,
+        _ => compile::<T>(code),
+    };
+    let res = exec_compiled(it, &compiled, code, ip, input_offset, steps, max_steps);
+    it.compiled = Some(compiled);
+    res
+}
+
+fn exec_compiled<T: Tape>(
+    it: &mut Interpreter<'_, T>,
+    compiled: &Compiled<T>,
+    code: &[InterpIns],
+    ip: &mut usize,
+    input_offset: &mut u32,
+    steps: &mut u64,
+    max_steps: u64,
+) -> Result<RunStatus, InterpreteError> {
+    // previous execution stopped by step limit in the middle of superinstruction or region,
+    // ops can't start there, so it's continued with checks until next op
+    while *ip < code.len() && (compiled.pcs[*ip] == usize::MAX || compiled.region_ends[*ip].is_some()) {
+        let (limit, end) = match compiled.region_ends[*ip] {
+            // region is left only through its end
+            Some(end) => (max_steps, end),
+            None => ((*steps + 1).min(max_steps), code.len()),
+        };
+        let status = it.exec::<true>(code, ip, input_offset, steps, limit, end)?;
+        if status == RunStatus::StepLimit && *steps >= max_steps {
+            return Ok(status);
+        }
+    }
+    if *ip >= code.len() {
+        return Ok(RunStatus::Finished);
+    }
+    let ops = &compiled.ops;
+    let mut ctx = Ctx {
+        code,
+        pc: compiled.pcs[*ip],
+        input_offset: *input_offset,
+        steps: *steps,
+        sub: 0,
+    };
+    while let Some(op) = ops.get(ctx.pc) {
+        if ctx.steps + u64::from(op.len) > max_steps {
+            // superinstruction doesn't fit into step limit, finish instruction by instruction
+            *ip = op.ip as usize;
+            *input_offset = ctx.input_offset;
+            *steps = ctx.steps;
+            return it.exec::<true>(code, ip, input_offset, steps, max_steps, code.len());
+        }
+        ctx.steps += u64::from(op.len);
+        ctx.sub = 0;
+        match (op.run)(it, op, &mut ctx) {
+            Ok(pc) => ctx.pc = pc,
+            Err(err) => {
+                *ip = op.ip(&ctx);
+                *input_offset = ctx.input_offset;
+                *steps = ctx.steps - u64::from(op.len.saturating_sub(1 + ctx.sub));
+                return Err(err);
+            }
+        }
+    }
+    *ip = code.len();
+    *input_offset = ctx.input_offset;
+    *steps = ctx.steps;
+    Ok(RunStatus::Finished)
+}
+
+/// Region (index of [`InterpIns::Unchecked`] marker) of each instruction,
+/// `None` for instructions which must be executed with checks
+///
+/// Region is unchecked only if it can be entered through its marker alone
+fn unchecked_regions(code: &[InterpIns]) -> Vec<Option<usize>> {
+    let mut region = vec![None; code.len()];
+    let mut i = 0;
+    while i < code.len() {
+        if let InterpIns::Unchecked { len, .. } = code[i] {
+            let end = (i + 1 + len as usize).min(code.len());
+            region[i + 1..end].fill(Some(i));
+            i = end;
+        } else {
+            i += 1;
+        }
+    }
+    let mut entered_inside = vec![false; code.len()];
+    for (from, ins) in code.iter().enumerate() {
+        if let InterpIns::Jmp { dest } | InterpIns::JmpT { dest } | InterpIns::JmpF { dest } = ins {
+            if let Some(&Some(r)) = region.get(*dest as usize) {
+                entered_inside[r] |= region[from] != Some(r);
+            }
+        }
+    }
+    for r in &mut region {
+        *r = r.filter(|r| !entered_inside[*r]);
+    }
+    region
+}
+
+/// Compile `code` to ops (index of op is `usize::MAX` for instructions fused into middle of superinstruction)
+fn compile<T: Tape>(code: &[InterpIns]) -> Compiled<T> {
+    let mut target = vec![false; code.len() + 1];
+    for ins in code {
+        if let InterpIns::Jmp { dest } | InterpIns::JmpT { dest } | InterpIns::JmpF { dest } = ins {
+            if let Some(t) = target.get_mut(*dest as usize) {
+                *t = true;
+            }
+        }
+    }
+    let region = unchecked_regions(code);
+
+    let mut ops = Vec::with_capacity(code.len());
+    let mut pcs = vec![usize::MAX; code.len() + 1];
+    let mut jumps = Vec::new();
+    let mut i = 0;
+    while i < code.len() {
+        // jump targets can't be inside superinstruction
+        let fusable = |len: usize| (i + 1..i + len).all(|k| !target[k] && region[k] == region[i]);
+        let checked = region[i].is_none();
+        macro_rules! handler {
+            ($f:ident) => {
+                if checked {
+                    $f::<T, true> as Handler<T>
+                } else {
+                    $f::<T, false>
+                }
+            };
+        }
+        use InterpIns as I;
+        let (run, len, a, b): (Handler<T>, usize, u32, u32) = match code[i..] {
+            [I::SetInputOffset { new_input_offset }, I::JmpF { dest }, ..] if fusable(2) => {
+                (handler!(loop_enter), 2, new_input_offset, dest)
+            }
+            [I::SetInputOffset { new_input_offset }, I::JmpT { dest }, ..] if fusable(2) => {
+                (handler!(loop_back), 2, new_input_offset, dest)
+            }
+            [I::PtrAdd { offset }, I::Putchar { offset: 0 }, I::PtrSub { offset: back }, ..]
+                if offset == back && fusable(3) =>
+            {
+                (handler!(putchar_right), 3, offset, 0)
+            }
+            [I::PtrAdd { offset }, I::Getchar { offset: 0 }, I::PtrSub { offset: back }, ..]
+                if offset == back && fusable(3) =>
+            {
+                (handler!(getchar_right), 3, offset, 0)
+            }
+            [I::Set { val, offset }, ..] => (handler!(set), 1, offset, val.into()),
+            [I::Add { val, offset }, ..] => (handler!(add), 1, offset, val.into()),
+            [I::Sub { val, offset }, ..] => (handler!(sub), 1, offset, val.into()),
+            [I::Mul { val, offset }, ..] => (handler!(mul), 1, offset, val.into()),
+            [I::PtrAdd { offset }, ..] => (handler!(ptr_add), 1, offset, 0),
+            [I::PtrSub { offset }, ..] => (handler!(ptr_sub), 1, offset, 0),
+            [I::SetInputOffset { new_input_offset }, ..] => {
+                (set_input_offset::<T>, 1, new_input_offset, 0)
+            }
+            [I::AddMove { mul, to }, ..] => (handler!(add_move), 1, to, mul.into()),
+            [I::SubMove { mul, to }, ..] => (handler!(sub_move), 1, to, mul.into()),
+            [I::MulMove { mul, to }, ..] => (handler!(mul_move), 1, to, mul.into()),
+            [I::Move { to }, ..] => (handler!(move_to), 1, to, 0),
+            [I::Copy { to }, ..] => (handler!(copy), 1, to, 0),
+            [I::Putchar { offset }, ..] => (handler!(putchar), 1, offset, 0),
+            [I::Getchar { offset }, ..] => (handler!(getchar), 1, offset, 0),
+            [I::JmpT { dest }, ..] => (handler!(jmp_t), 1, 0, dest),
+            [I::JmpF { dest }, ..] => (handler!(jmp_f), 1, 0, dest),
+            [I::Jmp { dest }, ..] => (jmp::<T>, 1, 0, dest),
+            [I::Unchecked { max_offset, .. }, ..] => {
+                // instructions of region compiled with checks don't need storage reserved
+                let enters_region = region.get(i + 1).is_some_and(|r| *r == Some(i));
+                let run = if enters_region {
+                    unchecked::<T>
+                } else {
+                    nop::<T>
+                };
+                (run, 1, max_offset, 0)
+            }
+            [] => unreachable!(),
+        };
+        if matches!(
+            code[i + len - 1],
+            I::Jmp { .. } | I::JmpT { .. } | I::JmpF { .. }
+        ) {
+            jumps.push(ops.len());
+        }
+        pcs[i] = ops.len();
+        ops.push(Op {
+            run,
+            ip: i as u32,
+            // markers aren't counted as steps
+            len: if matches!(code[i], I::Unchecked { .. }) { 0 } else { len as u32 },
+            a,
+            b,
+        });
+        i += len;
+    }
+    pcs[code.len()] = ops.len();
+    for op in jumps {
+        let dest = ops[op].b as usize;
+        ops[op].b = pcs.get(dest).copied().unwrap_or(ops.len()) as u32;
+    }
+    let region_ends = (0..=code.len())
+        .map(|i| {
+            let marker = region.get(i).copied().flatten()?;
+            let InterpIns::Unchecked { len, .. } = code[marker] else {
+                return None;
+            };
+            Some((marker + 1 + len as usize).min(code.len()))
+        })
+        .collect();
+    Compiled {
+        key: key(code),
+        ops,
+        pcs,
+        region_ends,
+    }
+}
+
+type Res = Result<usize, InterpreteError>;
+
+/// Index of cell `ptr - offset`
+#[inline(always)]
+fn index<T: Tape, const CHECKED: bool>(
+    it: &Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &Ctx<'_>,
+    offset: u32,
+) -> Result<usize, InterpreteError> {
+    if CHECKED && it.data_pointer < offset as usize {
+        return Err(it.invalid_offset(ctx.code, op.ip(ctx), offset));
+    }
+    Ok(it.data_pointer - offset as usize)
+}
+
+/// Indices of `ptr - input_offset` and `ptr - to` cells of `*_move` instructions
+#[inline(always)]
+fn move_indices<T: Tape, const CHECKED: bool>(
+    it: &Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &Ctx<'_>,
+) -> Result<(usize, usize), InterpreteError> {
+    if CHECKED && it.data_pointer < op.a.max(ctx.input_offset) as usize {
+        return Err(it.invalid_offset(ctx.code, op.ip(ctx), op.a.max(ctx.input_offset)));
+    }
+    Ok((
+        it.data_pointer - ctx.input_offset as usize,
+        it.data_pointer - op.a as usize,
+    ))
+}
+
+fn set<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    let i = index::<T, CHECKED>(it, op, ctx, op.a)?;
+    *it.cell_mut(ctx.code, op.ip(ctx), i)? = op.b as u8;
+    Ok(ctx.pc + 1)
+}
+
+fn add<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    let i = index::<T, CHECKED>(it, op, ctx, op.a)?;
+    let cell = it.cell_mut(ctx.code, op.ip(ctx), i)?;
+    *cell = cell.wrapping_add(op.b as u8);
+    Ok(ctx.pc + 1)
+}
+
+fn sub<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    let i = index::<T, CHECKED>(it, op, ctx, op.a)?;
+    let cell = it.cell_mut(ctx.code, op.ip(ctx), i)?;
+    *cell = cell.wrapping_sub(op.b as u8);
+    Ok(ctx.pc + 1)
+}
+
+fn mul<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    let i = index::<T, CHECKED>(it, op, ctx, op.a)?;
+    let cell = it.cell_mut(ctx.code, op.ip(ctx), i)?;
+    *cell = cell.wrapping_mul(op.b as u8);
+    Ok(ctx.pc + 1)
+}
+
+fn ptr_add<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    it.data_pointer += op.a as usize;
+    if CHECKED {
+        it.reserve_storage(ctx.code, op.ip(ctx), it.data_pointer)?;
+    }
+    Ok(ctx.pc + 1)
+}
+
+fn ptr_sub<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    if CHECKED && it.data_pointer < op.a as usize {
+        return Err(InterpreteError::DataPointerUnderflow {
+            at: it.location(ctx.code, op.ip(ctx)),
+            offset: op.a,
+        });
+    }
+    it.data_pointer -= op.a as usize;
+    Ok(ctx.pc + 1)
+}
+
+const fn set_input_offset<T: Tape>(_: &mut Interpreter<'_, T>, op: &Op<T>, ctx: &mut Ctx<'_>) -> Res {
+    ctx.input_offset = op.a;
+    Ok(ctx.pc + 1)
+}
+
+/// `Unchecked` marker of region executed with checks
+const fn nop<T: Tape>(_: &mut Interpreter<'_, T>, _: &Op<T>, ctx: &mut Ctx<'_>) -> Res {
+    Ok(ctx.pc + 1)
+}
+
+fn unchecked<T: Tape>(it: &mut Interpreter<'_, T>, op: &Op<T>, ctx: &mut Ctx<'_>) -> Res {
+    it.reserve_storage(ctx.code, op.ip(ctx), it.data_pointer + op.a as usize)?;
+    Ok(ctx.pc + 1)
+}
+
+fn add_move<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    let (from, to) = move_indices::<T, CHECKED>(it, op, ctx)?;
+    let val = it.tape.cell(from).wrapping_mul(op.b as u8);
+    let cell = it.cell_mut(ctx.code, op.ip(ctx), to)?;
+    *cell = cell.wrapping_add(val);
+    *it.cell_mut(ctx.code, op.ip(ctx), from)? = 0;
+    Ok(ctx.pc + 1)
+}
+
+fn sub_move<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    let (from, to) = move_indices::<T, CHECKED>(it, op, ctx)?;
+    let val = it.tape.cell(from).wrapping_mul(op.b as u8);
+    let cell = it.cell_mut(ctx.code, op.ip(ctx), to)?;
+    *cell = cell.wrapping_sub(val);
+    *it.cell_mut(ctx.code, op.ip(ctx), from)? = 0;
+    Ok(ctx.pc + 1)
+}
+
+fn mul_move<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    let (from, to) = move_indices::<T, CHECKED>(it, op, ctx)?;
+    let val = it.tape.cell(from).wrapping_mul(op.b as u8);
+    let cell = it.cell_mut(ctx.code, op.ip(ctx), to)?;
+    *cell = cell.wrapping_mul(val);
+    *it.cell_mut(ctx.code, op.ip(ctx), from)? = 0;
+    Ok(ctx.pc + 1)
+}
+
+fn move_to<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    let (from, to) = move_indices::<T, CHECKED>(it, op, ctx)?;
+    let val = it.tape.cell(from);
+    *it.cell_mut(ctx.code, op.ip(ctx), to)? = val;
+    *it.cell_mut(ctx.code, op.ip(ctx), from)? = 0;
+    Ok(ctx.pc + 1)
+}
+
+fn copy<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    let (from, to) = move_indices::<T, CHECKED>(it, op, ctx)?;
+    let val = it.tape.cell(from);
+    let cell = it.cell_mut(ctx.code, op.ip(ctx), to)?;
+    *cell = cell.wrapping_add(val);
+    Ok(ctx.pc + 1)
+}
+
+/// Write cell `ptr - offset`, `ctx.sub` must point to Putchar instruction
+#[inline(always)]
+fn write_cell<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &Ctx<'_>,
+    offset: u32,
+) -> Result<(), InterpreteError> {
+    let ch = it.tape.cell(index::<T, CHECKED>(it, op, ctx, offset)?);
+    let step = it.steps + ctx.steps - u64::from(op.len - ctx.sub);
+    it.io_out
+        .putchar_at(ch, step)
+        .map_err(|err| it.io_error(ctx.code, op.ip(ctx), err))?;
+    it.output_pos += 1;
+    Ok(())
+}
+
+/// Read cell `ptr - offset`, `ctx.sub` must point to Getchar instruction
+#[inline(always)]
+fn read_cell<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &Ctx<'_>,
+    offset: u32,
+) -> Result<(), InterpreteError> {
+    let i = index::<T, CHECKED>(it, op, ctx, offset)?;
+    if !it.io_in.has_buffered_input() {
+        // show prompt before waiting for input
+        it.io_out
+            .flush()
+            .map_err(|err| it.io_error(ctx.code, op.ip(ctx), err))?;
+    }
+    let step = it.steps + ctx.steps - u64::from(op.len - ctx.sub);
+    let ch = it
+        .io_in
+        .getchar_at(step)
+        .map_err(|err| it.io_error(ctx.code, op.ip(ctx), err))?;
+    *it.cell_mut(ctx.code, op.ip(ctx), i)? = ch;
+    it.input_pos += 1;
+    Ok(())
+}
+
+fn putchar<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    write_cell::<T, CHECKED>(it, op, ctx, op.a)?;
+    Ok(ctx.pc + 1)
+}
+
+fn getchar<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    read_cell::<T, CHECKED>(it, op, ctx, op.a)?;
+    Ok(ctx.pc + 1)
+}
+
+/// `ptr_add n; putchar [0]; ptr_sub n`
+fn putchar_right<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    ptr_add::<T, CHECKED>(it, op, ctx)?;
+    ctx.sub = 1;
+    write_cell::<T, CHECKED>(it, op, ctx, 0)?;
+    it.data_pointer -= op.a as usize;
+    Ok(ctx.pc + 1)
+}
+
+/// `ptr_add n; getchar [0]; ptr_sub n`
+fn getchar_right<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    ptr_add::<T, CHECKED>(it, op, ctx)?;
+    ctx.sub = 1;
+    read_cell::<T, CHECKED>(it, op, ctx, 0)?;
+    it.data_pointer -= op.a as usize;
+    Ok(ctx.pc + 1)
+}
+
+/// Value of cell `ptr - input_offset` is not 0
+#[inline(always)]
+fn condition<T: Tape, const CHECKED: bool>(
+    it: &Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &Ctx<'_>,
+) -> Result<bool, InterpreteError> {
+    Ok(it
+        .tape
+        .cell(index::<T, CHECKED>(it, op, ctx, ctx.input_offset)?)
+        != 0)
+}
+
+fn jmp_t<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    Ok(if condition::<T, CHECKED>(it, op, ctx)? {
+        op.b as usize
+    } else {
+        ctx.pc + 1
+    })
+}
+
+fn jmp_f<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    Ok(if condition::<T, CHECKED>(it, op, ctx)? {
+        ctx.pc + 1
+    } else {
+        op.b as usize
+    })
+}
+
+const fn jmp<T: Tape>(_: &mut Interpreter<'_, T>, op: &Op<T>, _: &mut Ctx<'_>) -> Res {
+    Ok(op.b as usize)
+}
+
+/// `set_input_offset n; jmp_f [input_offset], 'dest`
+fn loop_enter<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    ctx.input_offset = op.a;
+    ctx.sub = 1;
+    jmp_f::<T, CHECKED>(it, op, ctx)
+}
+
+/// `set_input_offset n; jmp_t [input_offset], 'dest`
+fn loop_back<T: Tape, const CHECKED: bool>(
+    it: &mut Interpreter<'_, T>,
+    op: &Op<T>,
+    ctx: &mut Ctx<'_>,
+) -> Res {
+    ctx.input_offset = op.a;
+    ctx.sub = 1;
+    jmp_t::<T, CHECKED>(it, op, ctx)
+}
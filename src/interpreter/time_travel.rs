@@ -0,0 +1,424 @@
+use super::{state::ExecState, InterpCode, InterpIns, InterpreteError, Interpreter};
+
+/// Registers of [`Interpreter`] which are changed by single step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Registers {
+    /// Instruction pointer
+    pub ip: usize,
+    /// Offset of condition cell
+    pub input_offset: u32,
+    /// Data pointer
+    pub data_pointer: usize,
+}
+
+impl Registers {
+    const fn of(interpreter: &Interpreter<'_>) -> Self {
+        Self {
+            ip: interpreter.ip,
+            input_offset: interpreter.input_offset,
+            data_pointer: interpreter.data_pointer,
+        }
+    }
+    const fn apply(self, interpreter: &mut Interpreter<'_>) {
+        interpreter.ip = self.ip;
+        interpreter.input_offset = self.input_offset;
+        interpreter.data_pointer = self.data_pointer;
+    }
+}
+
+/// Single cell change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CellWrite {
+    /// Cell index
+    pub cell: usize,
+    /// Value before step
+    pub old: u8,
+    /// Value after step
+    pub new: u8,
+}
+
+/// Changes made by single executed instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StepRecord {
+    /// Registers before step
+    pub before: Registers,
+    /// Registers after step
+    pub after: Registers,
+    /// Cells written by instruction (at most two for `*_move` instructions)
+    pub writes: [Option<CellWrite>; 2],
+    /// Byte printed by Putchar
+    pub output: Option<u8>,
+    /// Byte read by Getchar
+    pub input: Option<u8>,
+}
+
+impl StepRecord {
+    /// Check if step changed value of `cell`
+    #[inline]
+    pub fn writes_cell(&self, cell: usize) -> bool {
+        self.writes
+            .iter()
+            .flatten()
+            .any(|w| w.cell == cell && w.old != w.new)
+    }
+}
+
+/// Cells which can be written by instruction
+fn written_cells(ins: &InterpIns, regs: Registers) -> [Option<usize>; 2] {
+    let at = |offset: u32| regs.data_pointer.checked_sub(offset as usize);
+    match *ins {
+        InterpIns::Set { offset, .. }
+        | InterpIns::Add { offset, .. }
+        | InterpIns::Sub { offset, .. }
+        | InterpIns::Mul { offset, .. }
+        | InterpIns::Getchar { offset } => [at(offset), None],
+        InterpIns::AddMove { to, .. }
+        | InterpIns::SubMove { to, .. }
+        | InterpIns::MulMove { to, .. }
+        | InterpIns::Move { to } => [at(to), at(regs.input_offset)],
+        InterpIns::Copy { to } => [at(to), None],
+        _ => [None, None],
+    }
+}
+
+/// Debugger that can execute code backwards
+///
+/// Every executed step is recorded (registers and changed cells),
+/// and full [`ExecState`] snapshot is taken every [`TimeTravel::snapshot_interval`] steps,
+/// so any point of execution can be reached without re-running program.
+/// Steps re-executed after going back are replayed from history:
+/// input is not read again and output is not written again.
+///
+/// Memory use grows with each step (one [`StepRecord`] per step and copy of tape per snapshot),
+/// use [`TimeTravel::with_history_limit`] to bound it for long runs
+/// ```
+/// # use bf_tools::{ bf, interpreter::{ Interpreter, time_travel::TimeTravel } };
+/// let mut out = Vec::new();
+/// let interpreter = Interpreter::builder().set_stdout(&mut out).build();
+/// let mut debugger = TimeTravel::new(interpreter, bf!(+++[>++<-]>.[-]));
+/// debugger.run_to_end().unwrap();
+/// assert_eq!(debugger.interpreter().tape[1], 0);
+///
+/// // how did cell 1 become 0?
+/// let step = debugger.reverse_to_write(1).unwrap();
+/// assert_eq!(debugger.position(), step);
+/// assert_eq!(debugger.interpreter().tape[1], 6);
+///
+/// // state before first Putchar
+/// debugger.reverse_to_output(0);
+/// assert_eq!(debugger.interpreter().output_pos, 0);
+/// debugger.step().unwrap();
+/// assert_eq!(debugger.interpreter().output_pos, 1);
+/// # drop(debugger);
+/// assert_eq!(out, [6]);
+/// ```
+#[derive(Debug)]
+pub struct TimeTravel<'a> {
+    interpreter: Interpreter<'a>,
+    code: InterpCode,
+    history: Vec<StepRecord>,
+    /// Count of applied steps from history
+    position: usize,
+    /// `(position, state)` pairs sorted by position
+    snapshots: Vec<(usize, ExecState)>,
+    snapshot_interval: usize,
+    history_limit: usize,
+}
+
+impl<'a> TimeTravel<'a> {
+    /// Create debugger for `code`, execution starts from current interpreter state
+    pub fn new(interpreter: Interpreter<'a>, code: impl Into<InterpCode>) -> Self {
+        let snapshots = vec![(0, interpreter.snapshot())];
+        Self {
+            interpreter,
+            code: code.into(),
+            history: Vec::new(),
+            position: 0,
+            snapshots,
+            snapshot_interval: 100_000,
+            history_limit: usize::MAX,
+        }
+    }
+    /// Set count of steps between snapshots (default is 100000)
+    #[inline]
+    #[must_use]
+    pub fn with_snapshot_interval(mut self, interval: usize) -> Self {
+        self.snapshot_interval = interval.max(1);
+        self
+    }
+    /// Count of steps between snapshots
+    #[inline]
+    pub const fn snapshot_interval(&self) -> usize {
+        self.snapshot_interval
+    }
+    /// Set max count of recorded steps (default is unlimited)
+    ///
+    /// Oldest steps are dropped by whole snapshot intervals, so up to `limit + snapshot_interval`
+    /// steps are kept and debugger can't go back before oldest kept step
+    /// (positions are counted from it)
+    /// ```
+    /// # use bf_tools::{ bf, interpreter::{ Interpreter, time_travel::TimeTravel } };
+    /// let mut debugger = TimeTravel::new(Interpreter::default(), bf!(++++++++[>++++++++<-]))
+    ///     .with_snapshot_interval(10)
+    ///     .with_history_limit(20);
+    /// debugger.run_to_end().unwrap();
+    /// assert!(debugger.history().len() <= 30);
+    /// assert_eq!(debugger.position(), debugger.history().len());
+    /// debugger.seek(0);
+    /// assert_ne!(debugger.interpreter().tape[1], 0);
+    /// ```
+    #[inline]
+    #[must_use]
+    pub const fn with_history_limit(mut self, limit: usize) -> Self {
+        self.history_limit = limit;
+        self
+    }
+    /// Drop oldest steps above [`TimeTravel::with_history_limit`]
+    fn trim_history(&mut self) {
+        let excess = self.history.len().saturating_sub(self.history_limit);
+        if excess == 0 {
+            return;
+        }
+        // new start must have snapshot
+        let i = self.snapshots.partition_point(|(p, _)| *p < excess);
+        let Some(&(start, _)) = self.snapshots.get(i) else {
+            return;
+        };
+        self.history.drain(..start);
+        self.snapshots.drain(..i);
+        for (p, _) in &mut self.snapshots {
+            *p -= start;
+        }
+        self.position -= start;
+    }
+    /// Debugged interpreter
+    #[inline]
+    pub const fn interpreter(&self) -> &Interpreter<'a> {
+        &self.interpreter
+    }
+    /// Take back debugged interpreter
+    #[inline]
+    pub fn into_interpreter(self) -> Interpreter<'a> {
+        self.interpreter
+    }
+    /// Debugged code
+    #[inline]
+    pub const fn code(&self) -> &InterpCode {
+        &self.code
+    }
+    /// Count of executed steps from start of debugging
+    #[inline]
+    pub const fn position(&self) -> usize {
+        self.position
+    }
+    /// All recorded steps (including ones undone by reverse execution)
+    #[inline]
+    pub fn history(&self) -> &[StepRecord] {
+        &self.history
+    }
+
+    /// Execute single instruction
+    ///
+    /// Return `false` if end of code reached
+    /// # Errors
+    /// return `Err` if data pointer goes out of tape or io fails
+    pub fn step(&mut self) -> Result<bool, InterpreteError> {
+        if let Some(record) = self.history.get(self.position).copied() {
+            self.redo(&record);
+            return Ok(true);
+        }
+        // markers are executed with next instruction
+        let Some(ins) = self.code.0[self.interpreter.ip.min(self.code.0.len())..]
+            .iter()
+            .find(|ins| !matches!(ins, InterpIns::Unchecked { .. }))
+        else {
+            return Ok(false);
+        };
+        let before = Registers::of(&self.interpreter);
+        let cells = written_cells(ins, before);
+        let value = |interpreter: &Interpreter<'_>, cell: usize| {
+            interpreter.tape.get(cell).copied().unwrap_or_default()
+        };
+        let old = cells.map(|c| c.map(|c| (c, value(&self.interpreter, c))));
+        let (steps, input_pos, output_pos) = (
+            self.interpreter.steps,
+            self.interpreter.input_pos,
+            self.interpreter.output_pos,
+        );
+        let output = match ins {
+            InterpIns::Putchar { offset } => before
+                .data_pointer
+                .checked_sub(*offset as usize)
+                .map(|c| value(&self.interpreter, c)),
+            _ => None,
+        };
+
+        if let Err(err) = self.interpreter.step(&self.code) {
+            // partially executed step can't be recorded
+            before.apply(&mut self.interpreter);
+            self.interpreter.steps = steps;
+            self.interpreter.input_pos = input_pos;
+            self.interpreter.output_pos = output_pos;
+            return Err(err);
+        }
+
+        let writes = old.map(|w| {
+            w.map(|(cell, old)| CellWrite {
+                cell,
+                old,
+                new: value(&self.interpreter, cell),
+            })
+        });
+        let input = match ins {
+            InterpIns::Getchar { .. } => writes[0].map(|w| w.new),
+            _ => None,
+        };
+        self.history.push(StepRecord {
+            before,
+            after: Registers::of(&self.interpreter),
+            writes,
+            output: output.filter(|_| self.interpreter.output_pos != output_pos),
+            input: input.filter(|_| self.interpreter.input_pos != input_pos),
+        });
+        self.position += 1;
+        if self.position.is_multiple_of(self.snapshot_interval) {
+            self.snapshots
+                .push((self.position, self.interpreter.snapshot()));
+        }
+        self.trim_history();
+        Ok(true)
+    }
+    /// Execute code until end
+    /// # Errors
+    /// return `Err` if data pointer goes out of tape or io fails
+    pub fn run_to_end(&mut self) -> Result<(), InterpreteError> {
+        while self.step()? {}
+        self.interpreter
+            .io_out
+            .finish()
+            .map_err(InterpreteError::from)
+    }
+    /// Undo last executed instruction
+    ///
+    /// Return `false` if debugger is already at start
+    pub fn step_back(&mut self) -> bool {
+        if self.position == 0 {
+            return false;
+        }
+        self.position -= 1;
+        let record = self.history[self.position];
+        for w in record.writes.iter().rev().flatten() {
+            self.interpreter.tape[w.cell] = w.old;
+        }
+        record.before.apply(&mut self.interpreter);
+        self.interpreter.steps -= 1;
+        self.interpreter.input_pos -= record.input.is_some() as u64;
+        self.interpreter.output_pos -= record.output.is_some() as u64;
+        true
+    }
+    fn redo(&mut self, record: &StepRecord) {
+        for w in record.writes.iter().flatten() {
+            if self.interpreter.tape.len() <= w.cell {
+                self.interpreter
+                    .tape
+                    .resize((w.cell + 1).next_power_of_two(), 0);
+            }
+            self.interpreter.tape[w.cell] = w.new;
+        }
+        record.after.apply(&mut self.interpreter);
+        self.interpreter.steps += 1;
+        self.interpreter.input_pos += record.input.is_some() as u64;
+        self.interpreter.output_pos += record.output.is_some() as u64;
+        self.position += 1;
+    }
+    /// Move to state after `position` steps
+    ///
+    /// Positions after last executed step are clamped (use [`TimeTravel::step`] to execute new steps).
+    /// Far jumps start from nearest snapshot instead of replaying every step
+    pub fn seek(&mut self, position: usize) {
+        let position = position.min(self.history.len());
+        let snapshot = self
+            .snapshots
+            .partition_point(|(p, _)| *p <= position)
+            .checked_sub(1)
+            .map(|i| &self.snapshots[i]);
+        if let Some((at, state)) = snapshot {
+            let from_snapshot = position - at;
+            let from_current = self.position.abs_diff(position);
+            if from_snapshot < from_current {
+                self.interpreter.restore(state.clone());
+                self.position = *at;
+            }
+        }
+        while self.position > position {
+            self.step_back();
+        }
+        while self.position < position {
+            let record = self.history[self.position];
+            self.redo(&record);
+        }
+    }
+    /// Go backwards until step matching `pred` and stop before it
+    ///
+    /// Return position of found step (and stay at start if nothing found)
+    pub fn reverse_until(&mut self, pred: impl FnMut(&StepRecord) -> bool) -> Option<usize> {
+        let found = self.history[..self.position].iter().rposition(pred);
+        self.seek(found.unwrap_or(0));
+        found
+    }
+    /// Go backwards to last step which changed value of `cell` and stop before it
+    pub fn reverse_to_write(&mut self, cell: usize) -> Option<usize> {
+        self.reverse_until(|r| r.writes_cell(cell))
+    }
+    /// Go backwards to Putchar which printed `index`-th byte of output (from 0) and stop before it
+    pub fn reverse_to_output(&mut self, index: u64) -> Option<usize> {
+        let start = self.snapshots[0].1.output_pos;
+        let mut printed = self.history[..self.position]
+            .iter()
+            .filter(|r| r.output.is_some())
+            .count() as u64
+            + start;
+        self.reverse_until(|r| {
+            if r.output.is_none() {
+                return false;
+            }
+            printed -= 1;
+            printed == index
+        })
+    }
+    /// Go backwards until instruction at any of `breakpoints` and stop before executing it
+    ///
+    /// Current (not executed) instruction is skipped, last executed one can be found
+    /// ```
+    /// # use bf_tools::interpreter::{ Interpreter, InterpCode, InterpIns::*, time_travel::TimeTravel };
+    /// let code = InterpCode(vec![Add { val: 1, offset: 0 }, PtrAdd { offset: 1 }, Add { val: 2, offset: 0 }, PtrSub { offset: 1 }]);
+    /// let mut debugger = TimeTravel::new(Interpreter::default(), code);
+    /// for _ in 0..3 {
+    ///     debugger.step().unwrap();
+    /// }
+    /// assert_eq!(debugger.reverse_continue(&[2]), Some(2));
+    /// assert_eq!(debugger.interpreter().ip, 2);
+    /// assert_eq!(debugger.reverse_continue(&[2]), None);
+    /// assert_eq!(debugger.position(), 0);
+    /// ```
+    pub fn reverse_continue(&mut self, breakpoints: &[usize]) -> Option<usize> {
+        let found = self.history[..self.position]
+            .iter()
+            .rposition(|r| breakpoints.contains(&r.before.ip));
+        self.seek(found.unwrap_or(0));
+        found
+    }
+    /// Continue forward execution until instruction at any of `breakpoints` (or end of code)
+    ///
+    /// Return `true` if stopped at breakpoint
+    /// # Errors
+    /// return `Err` if data pointer goes out of tape or io fails
+    pub fn continue_to(&mut self, breakpoints: &[usize]) -> Result<bool, InterpreteError> {
+        while self.step()? {
+            if breakpoints.contains(&self.interpreter.ip) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
@@ -42,3 +42,21 @@ pub mod optimizer;
 
 /// Interpreter for BF code
 pub mod interpreter;
+
+/// Compiler of small structured language to [`ins::BfCode`]
+pub mod compiler;
+
+/// Graphviz DOT export of [`optimizer::OptCode`] and [`interpreter::InterpCode`]
+pub mod dot;
+
+/// Macro assembler: front-end language lowered to [`ins::BfCode`]
+pub mod masm;
+
+/// Builder of BF programs with cell allocation
+pub mod program;
+
+/// Generator of BF programs which print given text
+pub mod text_gen;
+
+/// Visitor and fold traits for [`ins::BfCode`] and [`optimizer::OptCode`]
+pub mod visit;
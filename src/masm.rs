@@ -0,0 +1,1072 @@
+use crate::ins::{BfCode, BfIns};
+use crate::ins_parser::{SourceMap, Span};
+use crate::optimizer::golf::add_len;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::rc::Rc;
+
+/*
+    statements (one per line, text after `;` is comment):
+    `var a b`                 allocate cells
+    `add a 5` `add a b`       a += 5, a += b (`sub` and `set` are same)
+    `clear a`                 a = 0
+    `move a b c`              b += a, c += a, a = 0
+    `copy a b c`              b += a, c += a
+    `put a` `get a`           output / input
+    `print "text"`            print string with temporary cell
+    `if a { } else { }`       a is not changed
+    `while a { }`
+    `macro name x y { }`      define macro with parameters
+    `name a 5`                invoke macro
+    `include "file"`          assemble file (only once)
+    values are numbers, char literals (`'A'`) or names of cells / macro parameters
+*/
+
+/// Kind of [`MasmError`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MasmErrorKind {
+    /// Statement isn't written in it's format
+    InvalidSyntax,
+    /// Name isn't variable, macro parameter or macro
+    UnknownName(String),
+    /// Variable or macro is already defined
+    DuplicateName(String),
+    /// Statement gets wrong count of arguments
+    WrongArgCount {
+        /// statement name
+        name: String,
+        /// count of passed arguments
+        found: usize,
+    },
+    /// Argument has wrong type
+    InvalidArgument {
+        /// argument index (starts from 0)
+        index: usize,
+        /// expected type (`cell`, `value` or `string`)
+        expected: &'static str,
+    },
+    /// Same cell is passed as source and destination
+    SameCell,
+    /// Macro invokes itself
+    RecursiveMacro(String),
+    /// `}` without block
+    UnmatchedBrace,
+    /// Block without `}`
+    UnclosedBlock,
+    /// Included file can't be read
+    Include {
+        /// file path
+        path: String,
+        /// error message
+        message: String,
+    },
+}
+
+/// Error type of [`MacroAssembler::assemble`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MasmError {
+    /// Name of file with error
+    pub file: String,
+    /// line number (starts from 1)
+    pub line: usize,
+    /// Error kind
+    pub kind: MasmErrorKind,
+}
+
+impl std::fmt::Display for MasmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: ", self.file, self.line)?;
+        match &self.kind {
+            MasmErrorKind::InvalidSyntax => write!(f, "invalid syntax"),
+            MasmErrorKind::UnknownName(name) => write!(f, "unknown name `{name}`"),
+            MasmErrorKind::DuplicateName(name) => write!(f, "`{name}` is already defined"),
+            MasmErrorKind::WrongArgCount { name, found } => {
+                write!(f, "`{name}` can't take {found} arguments")
+            }
+            MasmErrorKind::InvalidArgument { index, expected } => {
+                write!(f, "argument {index} must be {expected}")
+            }
+            MasmErrorKind::SameCell => write!(f, "cell is both source and destination"),
+            MasmErrorKind::RecursiveMacro(name) => write!(f, "macro `{name}` invokes itself"),
+            MasmErrorKind::UnmatchedBrace => write!(f, "unmatched `}}`"),
+            MasmErrorKind::UnclosedBlock => write!(f, "block isn't closed"),
+            MasmErrorKind::Include { path, message } => {
+                write!(f, "can't include `{path}`: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MasmError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Tok {
+    Word(String),
+    Num(i64),
+    Str(Vec<u8>),
+    Open,
+    Close,
+    Newline,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    tok: Tok,
+    line: usize,
+    span: Span,
+}
+
+/// Read escaped char after `\`
+pub(crate) const fn unescape(c: char) -> Option<char> {
+    Some(match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        '\\' | '"' | '\'' => c,
+        _ => return None,
+    })
+}
+
+/// Split text into tokens (`err` - error at line)
+fn tokenize(
+    text: &str,
+    err: impl Fn(usize, MasmErrorKind) -> MasmError,
+) -> Result<Vec<Token>, MasmError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut res = Vec::new();
+    let (mut i, mut line) = (0, 1);
+    while let Some(&c) = chars.get(i) {
+        let start = i;
+        i += 1;
+        let tok = match c {
+            '\n' => Tok::Newline,
+            ';' => {
+                while chars.get(i).is_some_and(|c| *c != '\n') {
+                    i += 1;
+                }
+                continue;
+            }
+            c if c.is_whitespace() => continue,
+            '{' => Tok::Open,
+            '}' => Tok::Close,
+            '"' | '\'' => {
+                let mut s = String::new();
+                loop {
+                    match chars.get(i) {
+                        Some('\\') => {
+                            let c = chars.get(i + 1).copied().and_then(unescape);
+                            s.push(c.ok_or_else(|| err(line, MasmErrorKind::InvalidSyntax))?);
+                            i += 2;
+                        }
+                        Some(&end) if end == c => break,
+                        Some(&c) if c != '\n' => {
+                            s.push(c);
+                            i += 1;
+                        }
+                        _ => return Err(err(line, MasmErrorKind::InvalidSyntax)),
+                    }
+                }
+                i += 1;
+                if c == '"' {
+                    Tok::Str(s.into_bytes())
+                } else {
+                    let mut it = s.chars().map(u8::try_from);
+                    match (it.next(), it.next()) {
+                        (Some(Ok(c)), None) => Tok::Num(c.into()),
+                        _ => return Err(err(line, MasmErrorKind::InvalidSyntax)),
+                    }
+                }
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                while chars
+                    .get(i)
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_')
+                {
+                    i += 1;
+                }
+                Tok::Word(chars[start..i].iter().collect())
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '+' => {
+                while chars.get(i).is_some_and(char::is_ascii_digit) {
+                    i += 1;
+                }
+                let s: String = chars[start..i].iter().collect();
+                let n = s.strip_prefix('+').unwrap_or(&s).parse();
+                Tok::Num(n.map_err(|_| err(line, MasmErrorKind::InvalidSyntax))?)
+            }
+            _ => return Err(err(line, MasmErrorKind::InvalidSyntax)),
+        };
+        res.push(Token {
+            tok,
+            line,
+            span: Span::new(start, i),
+        });
+        if c == '\n' {
+            line += 1;
+        }
+    }
+    Ok(res)
+}
+
+/// Statement: name with arguments and blocks (`if a { } else { }` has two blocks)
+#[derive(Debug)]
+struct Stmt {
+    name: String,
+    args: Vec<Token>,
+    blocks: Vec<Rc<[Stmt]>>,
+    file: usize,
+    line: usize,
+    /// span of name and arguments
+    span: Span,
+}
+
+/// Parse statements of file, nested blocks are parsed without recursion
+fn parse(
+    tokens: Vec<Token>,
+    file: usize,
+    err: impl Fn(usize, MasmErrorKind) -> MasmError,
+) -> Result<Rc<[Stmt]>, MasmError> {
+    let mut tokens = tokens.into_iter().peekable();
+    let mut cur = Vec::new();
+    // enclosing blocks: (statements before block, statement which owns block)
+    let mut stack: Vec<(Vec<Stmt>, Stmt)> = Vec::new();
+    while let Some(token) = tokens.next() {
+        match token.tok {
+            Tok::Newline => {}
+            Tok::Word(name) => {
+                let mut stmt = Stmt {
+                    name,
+                    args: Vec::new(),
+                    blocks: Vec::new(),
+                    file,
+                    line: token.line,
+                    span: token.span,
+                };
+                while let Some(arg) =
+                    tokens.next_if(|t| !matches!(t.tok, Tok::Newline | Tok::Open | Tok::Close))
+                {
+                    stmt.span = stmt.span.join(arg.span);
+                    stmt.args.push(arg);
+                }
+                if tokens.next_if(|t| t.tok == Tok::Open).is_some() {
+                    stack.push((std::mem::take(&mut cur), stmt));
+                } else {
+                    cur.push(stmt);
+                }
+            }
+            Tok::Close => {
+                let (parent, mut stmt) = stack
+                    .pop()
+                    .ok_or_else(|| err(token.line, MasmErrorKind::UnmatchedBrace))?;
+                stmt.blocks
+                    .push(Rc::from(std::mem::replace(&mut cur, parent)));
+                // `} else {`
+                if stmt.blocks.len() == 1
+                    && tokens
+                        .next_if(|t| matches!(&t.tok, Tok::Word(w) if w == "else"))
+                        .is_some()
+                {
+                    if tokens.next_if(|t| t.tok == Tok::Open).is_none() {
+                        return Err(err(stmt.line, MasmErrorKind::InvalidSyntax));
+                    }
+                    stack.push((std::mem::take(&mut cur), stmt));
+                } else {
+                    cur.push(stmt);
+                }
+            }
+            _ => return Err(err(token.line, MasmErrorKind::InvalidSyntax)),
+        }
+    }
+    match stack.pop() {
+        Some((_, stmt)) => Err(err(stmt.line, MasmErrorKind::UnclosedBlock)),
+        None => Ok(Rc::from(cur)),
+    }
+}
+
+/// Source file of [`MacroAssembler`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    /// File name (as it's included)
+    pub name: String,
+    /// File contents
+    pub text: String,
+}
+
+/// Statement which produced instructions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Origin {
+    /// Index of file in [`MacroAssembler::files`]
+    pub file: usize,
+    /// Span of statement in file
+    pub span: Span,
+    /// Origin of macro invocation or include which contains statement
+    pub caller: Option<usize>,
+}
+
+/// Relation between assembled [`BfCode`] and assembler sources
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MasmMap {
+    /// All statements which produced instructions
+    pub origins: Vec<Origin>,
+    /// Index in `origins` for each instruction in pre-order (like [`SourceMap::spans`])
+    pub ins: Vec<usize>,
+}
+
+impl MasmMap {
+    /// Statement of instruction `ins` (pre-order index) and all macro invocations which expanded it,
+    /// innermost first
+    pub fn backtrace(&self, ins: usize) -> impl Iterator<Item = &Origin> + '_ {
+        let first = self.ins.get(ins).and_then(|i| self.origins.get(*i));
+        std::iter::successors(first, |origin| {
+            origin.caller.and_then(|i| self.origins.get(i))
+        })
+    }
+    /// [`SourceMap`] of code in `file`
+    ///
+    /// Instruction gets span of innermost statement from `file` in it's backtrace
+    /// (so instructions of macros from included files are mapped to macro invocations)
+    /// or empty span if there is no such statement.
+    /// Can be used with [`crate::optimizer::opt_ins::bf_to_opt_with_spans`] to get source locations of errors
+    pub fn source_map(&self, file: usize) -> SourceMap {
+        SourceMap {
+            spans: (0..self.ins.len())
+                .map(|ins| {
+                    self.backtrace(ins)
+                        .find(|origin| origin.file == file)
+                        .map_or_else(Span::default, |origin| origin.span)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Result of [`MacroAssembler::assemble`]
+#[derive(Debug, Clone)]
+pub struct Assembled {
+    /// Assembled code
+    pub code: BfCode,
+    /// Source locations of code
+    pub map: MasmMap,
+    /// Cell index of each global variable
+    pub vars: BTreeMap<String, usize>,
+}
+
+/// Assembler of macro language (front-end which is lowered to [`BfCode`])
+///
+/// Cells are allocated by assembler, so code refers to them by names.
+/// All statements address fixed cells, so data pointer position is always known
+/// and every loop returns pointer to it's condition cell.
+/// Temporary cells and variables of blocks are cleared before they're reused.
+///
+/// Statements:
+/// - `var a b` - allocate cells (cells of macro or `if`/`while` body are freed at it's end)
+/// - `add a 5`, `sub a 'A'`, `set a b` - change cell by value or other cell
+/// - `clear a`, `move a b c` (`b += a`, `c += a`, `a = 0`), `copy a b c` (`a` is kept)
+/// - `put a`, `get a`, `print "text\n"`
+/// - `if a { } else { }` (`a` is kept), `while a { }`
+/// - `macro name x y { }` - define macro, parameters are cells, values or strings
+/// - `include "file"` - assemble file in place (each file is included only once)
+///
+/// Text after `;` is comment
+/// ```
+/// # use bf_tools::{ masm::MacroAssembler, optimizer::OptState, interpreter::Interpreter };
+/// let lib = "
+///     ; print digit
+///     macro put_digit d {
+///         add d '0'
+///         put d
+///         sub d '0'
+///     }
+/// ";
+/// let main = "
+///     include \"lib.bfm\"
+///     var n i
+///     set n 3
+///     copy n i
+///     while i {
+///         put_digit i
+///         sub i 1
+///         if i { print \", \" } else { print \"\\n\" }
+///     }
+/// ";
+/// let mut asm = MacroAssembler::default()
+///     .add_file("lib.bfm", lib)
+///     .add_file("main.bfm", main);
+/// let out = asm.assemble("main.bfm").unwrap();
+///
+/// let code = OptState::default().run_passes(out.code.into());
+/// let mut output = Vec::new();
+/// let mut interpreter = Interpreter::builder().set_stdout(&mut output).build();
+/// interpreter.run(code).unwrap();
+/// assert_eq!(interpreter.tape[out.vars["n"]], 3);
+/// drop(interpreter);
+/// assert_eq!(output, b"3, 2, 1\n");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MacroAssembler {
+    files: Vec<SourceFile>,
+}
+
+impl MacroAssembler {
+    /// Add file which can be assembled or included by `name` without reading it from disk
+    #[must_use]
+    pub fn add_file(mut self, name: impl Into<String>, text: impl Into<String>) -> Self {
+        self.files.push(SourceFile {
+            name: name.into(),
+            text: text.into(),
+        });
+        self
+    }
+    /// All added and included files
+    #[inline]
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+    /// Find file added with `name`, or read it from disk (path is relative to directory of file `from`)
+    fn load(&mut self, name: &str, from: Option<usize>) -> Result<usize, MasmErrorKind> {
+        let path = match from.and_then(|from| Path::new(&self.files[from].name).parent()) {
+            Some(dir) => dir.join(name).to_string_lossy().into_owned(),
+            None => name.to_string(),
+        };
+        if let Some(file) = self
+            .files
+            .iter()
+            .position(|f| f.name == name || f.name == path)
+        {
+            return Ok(file);
+        }
+        let text = std::fs::read_to_string(&path).map_err(|err| MasmErrorKind::Include {
+            path: path.clone(),
+            message: err.to_string(),
+        })?;
+        self.files.push(SourceFile { name: path, text });
+        Ok(self.files.len() - 1)
+    }
+    fn parse_file(&self, file: usize) -> Result<Rc<[Stmt]>, MasmError> {
+        let err = |line, kind| MasmError {
+            file: self.files[file].name.clone(),
+            line,
+            kind,
+        };
+        parse(tokenize(&self.files[file].text, err)?, file, err)
+    }
+    /// Assemble file `name` (see [`MacroAssembler::add_file`])
+    ///
+    /// Source map relates instructions to statements of macro bodies:
+    /// ```
+    /// # use bf_tools::{ bf, masm::MacroAssembler };
+    /// let src = "macro inc x {\n    add x 1\n}\nvar a\ninc a\n";
+    /// let out = MacroAssembler::default().add_file("main", src).assemble("main").unwrap();
+    /// assert_eq!(out.code, bf!(+));
+    ///
+    /// let stmts: Vec<_> = out.map.backtrace(0).map(|o| &src[o.span.start..o.span.end]).collect();
+    /// assert_eq!(stmts, ["add x 1", "inc a"]);
+    /// ```
+    /// # Errors
+    /// return `Err` if source isn't valid, or file can't be read
+    pub fn assemble(&mut self, name: &str) -> Result<Assembled, MasmError> {
+        let file = self.load(name, None).map_err(|kind| MasmError {
+            file: name.to_string(),
+            line: 0,
+            kind,
+        })?;
+        let stmts = self.parse_file(file)?;
+        let mut ex = Expander::new(self);
+        ex.included.insert(file);
+        ex.run(stmts)?;
+        Ok(ex.finish())
+    }
+}
+
+/// Value of name or argument
+#[derive(Debug, Clone)]
+enum Value {
+    Cell(usize),
+    Num(u8),
+    Str(Rc<[u8]>),
+}
+
+#[derive(Debug, Default)]
+struct Scope {
+    names: HashMap<String, Value>,
+    /// cells allocated by `var`
+    owned: Vec<usize>,
+    /// macro body (names of enclosing scopes except global one aren't visible)
+    barrier: bool,
+}
+
+#[derive(Debug)]
+struct Macro {
+    params: Vec<String>,
+    body: Rc<[Stmt]>,
+}
+
+/// Code emitted after block
+#[derive(Debug)]
+enum Tail {
+    /// End of block scope
+    Scope { origin: usize },
+    /// End of macro expansion
+    Macro,
+    /// End of `if` body, `flag` is copy of condition
+    IfThen {
+        origin: usize,
+        flag: usize,
+        else_body: Option<(usize, Rc<[Stmt]>)>,
+    },
+    /// End of `else` body
+    IfElse { origin: usize, flag: usize },
+    /// End of `while` body
+    While { origin: usize, cell: usize },
+}
+
+#[derive(Debug)]
+enum Work {
+    /// Execute statements, `caller` - origin of macro invocation or include
+    Stmts {
+        stmts: Rc<[Stmt]>,
+        next: usize,
+        caller: Option<usize>,
+    },
+    Tail(Tail),
+}
+
+/// Builtin statement names (they can't be macro names)
+const BUILTINS: [&str; 14] = [
+    "var", "add", "sub", "set", "clear", "move", "copy", "put", "get", "print", "if", "while",
+    "macro", "include",
+];
+
+/// Expansion state of [`MacroAssembler::assemble`]
+struct Expander<'a> {
+    asm: &'a mut MacroAssembler,
+    included: HashSet<usize>,
+    macros: HashMap<String, Rc<Macro>>,
+    /// names of macros which are expanded now
+    active: Vec<String>,
+    scopes: Vec<Scope>,
+    /// allocated cells
+    used: Vec<bool>,
+    ptr: usize,
+    /// code of all open loops (whole code first)
+    code: Vec<Vec<BfIns>>,
+    map: MasmMap,
+    origin_ids: HashMap<Origin, usize>,
+    /// origin of emitted instructions
+    origin: usize,
+}
+
+impl<'a> Expander<'a> {
+    fn new(asm: &'a mut MacroAssembler) -> Self {
+        Self {
+            asm,
+            included: HashSet::new(),
+            macros: HashMap::new(),
+            active: Vec::new(),
+            scopes: vec![Scope::default()],
+            used: Vec::new(),
+            ptr: 0,
+            code: vec![Vec::new()],
+            map: MasmMap::default(),
+            origin_ids: HashMap::new(),
+            origin: 0,
+        }
+    }
+    fn finish(mut self) -> Assembled {
+        let vars =
+            self.scopes
+                .swap_remove(0)
+                .names
+                .into_iter()
+                .filter_map(|(name, val)| match val {
+                    Value::Cell(cell) => Some((name, cell)),
+                    _ => None,
+                });
+        Assembled {
+            code: BfCode(self.code.swap_remove(0)),
+            map: self.map,
+            vars: vars.collect(),
+        }
+    }
+
+    fn run(&mut self, stmts: Rc<[Stmt]>) -> Result<(), MasmError> {
+        let mut work = vec![Work::Stmts {
+            stmts,
+            next: 0,
+            caller: None,
+        }];
+        while let Some(item) = work.pop() {
+            match item {
+                Work::Stmts {
+                    stmts,
+                    next,
+                    caller,
+                } => {
+                    if next < stmts.len() {
+                        work.push(Work::Stmts {
+                            stmts: Rc::clone(&stmts),
+                            next: next + 1,
+                            caller,
+                        });
+                        self.exec(&stmts[next], caller, &mut work)?;
+                    }
+                }
+                Work::Tail(tail) => self.tail(tail, &mut work),
+            }
+        }
+        Ok(())
+    }
+
+    // emission
+
+    fn push(&mut self, ins: BfIns) {
+        self.map.ins.push(self.origin);
+        if let Some(code) = self.code.last_mut() {
+            code.push(ins);
+        }
+    }
+    fn move_to(&mut self, cell: usize) {
+        if cell > self.ptr {
+            self.push(BfIns::PtrAdd(cell - self.ptr));
+        } else if cell < self.ptr {
+            self.push(BfIns::PtrSub(self.ptr - cell));
+        }
+        self.ptr = cell;
+    }
+    fn add(&mut self, cell: usize, val: u8) {
+        if val != 0 {
+            self.move_to(cell);
+            self.push(if val < 128 {
+                BfIns::Add(val)
+            } else {
+                BfIns::Sub(val.wrapping_neg())
+            });
+        }
+    }
+    fn open_loop(&mut self, cell: usize) {
+        self.move_to(cell);
+        self.map.ins.push(self.origin);
+        self.code.push(Vec::new());
+    }
+    fn close_loop(&mut self, cell: usize) {
+        self.move_to(cell);
+        if let Some(body) = self.code.pop() {
+            if let Some(code) = self.code.last_mut() {
+                code.push(BfIns::Loop(BfCode(body)));
+            }
+        }
+    }
+    fn clear(&mut self, cell: usize) {
+        self.open_loop(cell);
+        self.add(cell, 255);
+        self.close_loop(cell);
+    }
+    /// `dst += src * k` for each `(dst, k)`, then `src = 0`
+    fn transfer(&mut self, src: usize, dsts: &[(usize, u8)]) {
+        self.open_loop(src);
+        self.add(src, 255);
+        for (dst, k) in dsts {
+            self.add(*dst, *k);
+        }
+        self.close_loop(src);
+    }
+    /// Same as [`Expander::transfer`], but `src` is restored with temporary cell
+    fn copy(&mut self, src: usize, dsts: &[(usize, u8)]) {
+        let tmp = self.alloc();
+        let mut all = dsts.to_vec();
+        all.push((tmp, 1));
+        self.transfer(src, &all);
+        self.transfer(tmp, &[(src, 1)]);
+        self.free(tmp);
+    }
+    fn alloc(&mut self) -> usize {
+        match self.used.iter().position(|used| !used) {
+            Some(cell) => {
+                self.used[cell] = true;
+                cell
+            }
+            None => {
+                self.used.push(true);
+                self.used.len() - 1
+            }
+        }
+    }
+    fn free(&mut self, cell: usize) {
+        self.used[cell] = false;
+    }
+
+    // statements
+
+    fn err(&self, stmt: &Stmt, kind: MasmErrorKind) -> MasmError {
+        MasmError {
+            file: self.asm.files[stmt.file].name.clone(),
+            line: stmt.line,
+            kind,
+        }
+    }
+    fn lookup(&self, name: &str) -> Option<&Value> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(val) = scope.names.get(name) {
+                return Some(val);
+            }
+            if scope.barrier {
+                break;
+            }
+        }
+        self.scopes
+            .first()
+            .and_then(|global| global.names.get(name))
+    }
+    fn value(&self, stmt: &Stmt, index: usize) -> Result<Value, MasmError> {
+        match &stmt.args[index].tok {
+            Tok::Num(n) => Ok(Value::Num(n.rem_euclid(256) as u8)),
+            Tok::Str(s) => Ok(Value::Str(s.as_slice().into())),
+            Tok::Word(name) => self
+                .lookup(name)
+                .cloned()
+                .ok_or_else(|| self.err(stmt, MasmErrorKind::UnknownName(name.clone()))),
+            _ => Err(self.err(stmt, MasmErrorKind::InvalidSyntax)),
+        }
+    }
+    fn cell(&self, stmt: &Stmt, index: usize) -> Result<usize, MasmError> {
+        match self.value(stmt, index)? {
+            Value::Cell(cell) => Ok(cell),
+            _ => Err(self.err(
+                stmt,
+                MasmErrorKind::InvalidArgument {
+                    index,
+                    expected: "cell",
+                },
+            )),
+        }
+    }
+    /// Cells of arguments starting from `from`, which must differ from `src`
+    fn dsts(
+        &self,
+        stmt: &Stmt,
+        from: usize,
+        src: usize,
+        k: u8,
+    ) -> Result<Vec<(usize, u8)>, MasmError> {
+        (from..stmt.args.len())
+            .map(|i| match self.cell(stmt, i)? {
+                dst if dst == src => Err(self.err(stmt, MasmErrorKind::SameCell)),
+                dst => Ok((dst, k)),
+            })
+            .collect()
+    }
+    fn check(
+        &self,
+        stmt: &Stmt,
+        args: std::ops::RangeInclusive<usize>,
+        blocks: std::ops::RangeInclusive<usize>,
+    ) -> Result<(), MasmError> {
+        if !args.contains(&stmt.args.len()) {
+            return Err(self.err(
+                stmt,
+                MasmErrorKind::WrongArgCount {
+                    name: stmt.name.clone(),
+                    found: stmt.args.len(),
+                },
+            ));
+        }
+        if !blocks.contains(&stmt.blocks.len()) {
+            return Err(self.err(stmt, MasmErrorKind::InvalidSyntax));
+        }
+        Ok(())
+    }
+    fn word<'s>(&self, stmt: &'s Stmt, index: usize) -> Result<&'s str, MasmError> {
+        match &stmt.args[index].tok {
+            Tok::Word(name) => Ok(name),
+            _ => Err(self.err(stmt, MasmErrorKind::InvalidSyntax)),
+        }
+    }
+    /// Open block scope and schedule `body` with `tail` after it
+    fn block(&mut self, body: Rc<[Stmt]>, caller: Option<usize>, tail: Tail, work: &mut Vec<Work>) {
+        self.scopes.push(Scope::default());
+        work.push(Work::Tail(tail));
+        work.push(Work::Tail(Tail::Scope {
+            origin: self.origin,
+        }));
+        work.push(Work::Stmts {
+            stmts: body,
+            next: 0,
+            caller,
+        });
+    }
+
+    fn exec(
+        &mut self,
+        stmt: &Stmt,
+        caller: Option<usize>,
+        work: &mut Vec<Work>,
+    ) -> Result<(), MasmError> {
+        let origin = Origin {
+            file: stmt.file,
+            span: stmt.span,
+            caller,
+        };
+        let next_id = self.map.origins.len();
+        self.origin = *self.origin_ids.entry(origin).or_insert(next_id);
+        if self.origin == next_id {
+            self.map.origins.push(origin);
+        }
+        let n = stmt.args.len();
+        match stmt.name.as_str() {
+            "var" => {
+                self.check(stmt, 1..=usize::MAX, 0..=0)?;
+                for i in 0..n {
+                    let name = self.word(stmt, i)?;
+                    if self
+                        .scopes
+                        .last()
+                        .is_some_and(|s| s.names.contains_key(name))
+                    {
+                        return Err(self.err(stmt, MasmErrorKind::DuplicateName(name.to_string())));
+                    }
+                    let cell = self.alloc();
+                    if let Some(scope) = self.scopes.last_mut() {
+                        scope.names.insert(name.to_string(), Value::Cell(cell));
+                        scope.owned.push(cell);
+                    }
+                }
+            }
+            name @ ("add" | "sub" | "set") => {
+                self.check(stmt, 2..=2, 0..=0)?;
+                let dst = self.cell(stmt, 0)?;
+                let k = if name == "sub" { 255 } else { 1 };
+                match self.value(stmt, 1)? {
+                    Value::Num(val) => {
+                        if name == "set" {
+                            self.clear(dst);
+                        }
+                        self.add(dst, val.wrapping_mul(k));
+                    }
+                    Value::Cell(src) if src == dst => {
+                        return Err(self.err(stmt, MasmErrorKind::SameCell))
+                    }
+                    Value::Cell(src) => {
+                        if name == "set" {
+                            self.clear(dst);
+                        }
+                        self.copy(src, &[(dst, k)]);
+                    }
+                    Value::Str(_) => {
+                        return Err(self.err(
+                            stmt,
+                            MasmErrorKind::InvalidArgument {
+                                index: 1,
+                                expected: "value",
+                            },
+                        ))
+                    }
+                }
+            }
+            "clear" => {
+                self.check(stmt, 1..=usize::MAX, 0..=0)?;
+                for i in 0..n {
+                    let cell = self.cell(stmt, i)?;
+                    self.clear(cell);
+                }
+            }
+            name @ ("move" | "copy") => {
+                self.check(stmt, 2..=usize::MAX, 0..=0)?;
+                let src = self.cell(stmt, 0)?;
+                let dsts = self.dsts(stmt, 1, src, 1)?;
+                if name == "move" {
+                    self.transfer(src, &dsts);
+                } else {
+                    self.copy(src, &dsts);
+                }
+            }
+            name @ ("put" | "get") => {
+                self.check(stmt, 1..=usize::MAX, 0..=0)?;
+                for i in 0..n {
+                    let cell = self.cell(stmt, i)?;
+                    self.move_to(cell);
+                    self.push(if name == "put" {
+                        BfIns::Putchar
+                    } else {
+                        BfIns::Getchar
+                    });
+                }
+            }
+            "print" => {
+                self.check(stmt, 1..=1, 0..=0)?;
+                let Value::Str(text) = self.value(stmt, 0)? else {
+                    return Err(self.err(
+                        stmt,
+                        MasmErrorKind::InvalidArgument {
+                            index: 0,
+                            expected: "string",
+                        },
+                    ));
+                };
+                let tmp = self.alloc();
+                let mut val = 0u8;
+                for c in text.iter() {
+                    self.add(tmp, c.wrapping_sub(val));
+                    self.move_to(tmp);
+                    self.push(BfIns::Putchar);
+                    val = *c;
+                }
+                // `[-]` is 3 chars
+                if add_len(val) > 3 {
+                    self.clear(tmp);
+                } else {
+                    self.add(tmp, val.wrapping_neg());
+                }
+                self.free(tmp);
+            }
+            "if" => {
+                self.check(stmt, 1..=1, 1..=2)?;
+                let cond = self.cell(stmt, 0)?;
+                let flag = self.alloc();
+                self.copy(cond, &[(flag, 1)]);
+                let else_body = stmt.blocks.get(1).map(|body| {
+                    let else_flag = self.alloc();
+                    self.add(else_flag, 1);
+                    (else_flag, Rc::clone(body))
+                });
+                self.open_loop(flag);
+                let tail = Tail::IfThen {
+                    origin: self.origin,
+                    flag,
+                    else_body,
+                };
+                self.block(Rc::clone(&stmt.blocks[0]), caller, tail, work);
+            }
+            "while" => {
+                self.check(stmt, 1..=1, 1..=1)?;
+                let cell = self.cell(stmt, 0)?;
+                self.open_loop(cell);
+                let tail = Tail::While {
+                    origin: self.origin,
+                    cell,
+                };
+                self.block(Rc::clone(&stmt.blocks[0]), caller, tail, work);
+            }
+            "macro" => {
+                self.check(stmt, 1..=usize::MAX, 1..=1)?;
+                let name = self.word(stmt, 0)?;
+                if BUILTINS.contains(&name) || self.macros.contains_key(name) {
+                    return Err(self.err(stmt, MasmErrorKind::DuplicateName(name.to_string())));
+                }
+                let mut params: Vec<String> = Vec::new();
+                for i in 1..n {
+                    let param = self.word(stmt, i)?;
+                    if params.iter().any(|p| p == param) {
+                        return Err(self.err(stmt, MasmErrorKind::DuplicateName(param.to_string())));
+                    }
+                    params.push(param.to_string());
+                }
+                let body = Rc::clone(&stmt.blocks[0]);
+                self.macros
+                    .insert(name.to_string(), Rc::new(Macro { params, body }));
+            }
+            "include" => {
+                self.check(stmt, 1..=1, 0..=0)?;
+                let Tok::Str(path) = &stmt.args[0].tok else {
+                    return Err(self.err(
+                        stmt,
+                        MasmErrorKind::InvalidArgument {
+                            index: 0,
+                            expected: "string",
+                        },
+                    ));
+                };
+                let path = String::from_utf8_lossy(path);
+                let file = self
+                    .asm
+                    .load(&path, Some(stmt.file))
+                    .map_err(|kind| self.err(stmt, kind))?;
+                if self.included.insert(file) {
+                    let stmts = self.asm.parse_file(file)?;
+                    work.push(Work::Stmts {
+                        stmts,
+                        next: 0,
+                        caller: Some(self.origin),
+                    });
+                }
+            }
+            name => {
+                let Some(mac) = self.macros.get(name).cloned() else {
+                    return Err(self.err(stmt, MasmErrorKind::UnknownName(name.to_string())));
+                };
+                self.check(stmt, mac.params.len()..=mac.params.len(), 0..=0)?;
+                if self.active.iter().any(|active| active == name) {
+                    return Err(self.err(stmt, MasmErrorKind::RecursiveMacro(name.to_string())));
+                }
+                let names = (0..n)
+                    .map(|i| Ok((mac.params[i].clone(), self.value(stmt, i)?)))
+                    .collect::<Result<_, MasmError>>()?;
+                self.active.push(name.to_string());
+                self.scopes.push(Scope {
+                    names,
+                    owned: Vec::new(),
+                    barrier: true,
+                });
+                work.push(Work::Tail(Tail::Macro));
+                work.push(Work::Tail(Tail::Scope {
+                    origin: self.origin,
+                }));
+                work.push(Work::Stmts {
+                    stmts: Rc::clone(&mac.body),
+                    next: 0,
+                    caller: Some(self.origin),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn tail(&mut self, tail: Tail, work: &mut Vec<Work>) {
+        match tail {
+            Tail::Scope { origin } => {
+                self.origin = origin;
+                if let Some(scope) = self.scopes.pop() {
+                    for cell in scope.owned {
+                        self.clear(cell);
+                        self.free(cell);
+                    }
+                }
+            }
+            Tail::Macro => {
+                self.active.pop();
+            }
+            Tail::IfThen {
+                origin,
+                flag,
+                else_body,
+            } => {
+                self.origin = origin;
+                if let Some((else_flag, _)) = &else_body {
+                    self.add(*else_flag, 255);
+                }
+                self.clear(flag);
+                self.close_loop(flag);
+                self.free(flag);
+                if let Some((else_flag, body)) = else_body {
+                    self.open_loop(else_flag);
+                    let caller = self.map.origins[origin].caller;
+                    let tail = Tail::IfElse {
+                        origin,
+                        flag: else_flag,
+                    };
+                    self.block(body, caller, tail, work);
+                }
+            }
+            Tail::IfElse { origin, flag } => {
+                self.origin = origin;
+                self.add(flag, 255);
+                self.close_loop(flag);
+                self.free(flag);
+            }
+            Tail::While { origin, cell } => {
+                self.origin = origin;
+                self.close_loop(cell);
+            }
+        }
+    }
+}
@@ -83,14 +83,106 @@ impl Default for OptStateBuilder {
 /// Optimizer inner instruction representation
 pub mod opt_ins {
     use std::collections::BTreeMap;
+    use std::hash::{Hash, Hasher};
 
     use crate::ins::{BfCode, BfIns};
+    use crate::ins_parser::{SourceMap, Span};
+    use crate::visit::{Tree, Visit};
 
     /// Block of optimizer instruction
-    #[derive(Debug, Clone)]
+    ///
+    /// Like [`BfCode`], all traversals are iterative.
+    /// Has textual form (see [`super::text`])
+    #[derive(Debug)]
     pub struct OptCode(pub Vec<OptBlock>);
 
-    #[derive(Debug, Clone)]
+    impl Clone for OptCode {
+        fn clone(&self) -> Self {
+            let mut cur = (self.0.iter(), Vec::with_capacity(self.0.len()));
+            // enclosing loops with already cloned blocks
+            let mut stack = Vec::new();
+            loop {
+                match cur.0.next() {
+                    Some(OptBlock::Loop(inner)) => {
+                        let inner = (inner.0.iter(), Vec::with_capacity(inner.0.len()));
+                        stack.push(std::mem::replace(&mut cur, inner));
+                    }
+                    // not a loop, so clone isn't recursive
+                    Some(block) => cur.1.push(block.clone()),
+                    None => match stack.pop() {
+                        Some(parent) => {
+                            let (_, inner) = std::mem::replace(&mut cur, parent);
+                            cur.1.push(OptBlock::Loop(OptCode(inner)));
+                        }
+                        None => return OptCode(cur.1),
+                    },
+                }
+            }
+        }
+    }
+
+    impl PartialEq for OptCode {
+        fn eq(&self, other: &Self) -> bool {
+            if self.0.len() != other.0.len() {
+                return false;
+            }
+            let mut stack = vec![(self.0.iter(), other.0.iter())];
+            while let Some((a, b)) = stack.last_mut() {
+                match (a.next(), b.next()) {
+                    (Some(OptBlock::Loop(a)), Some(OptBlock::Loop(b))) => {
+                        if a.0.len() != b.0.len() {
+                            return false;
+                        }
+                        stack.push((a.0.iter(), b.0.iter()));
+                    }
+                    // at least one isn't a loop, so comparison isn't recursive
+                    (Some(a), Some(b)) if a != b => return false,
+                    (Some(_), Some(_)) => {}
+                    (None, None) => {
+                        stack.pop();
+                    }
+                    _ => return false,
+                }
+            }
+            true
+        }
+    }
+
+    impl Eq for OptCode {}
+
+    /// Hasher of all blocks in pre-order
+    struct HashBlocks<'a, H>(&'a mut H);
+
+    impl<H: Hasher> Visit<OptCode> for HashBlocks<'_, H> {
+        fn visit_node(&mut self, block: &OptBlock) {
+            block.hash(self.0);
+        }
+        fn enter_loop(&mut self, body: &OptCode) -> bool {
+            body.0.len().hash(self.0);
+            true
+        }
+    }
+
+    impl Hash for OptCode {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.0.len().hash(state);
+            self.visit(&mut HashBlocks(state));
+        }
+    }
+
+    impl Drop for OptCode {
+        fn drop(&mut self) {
+            // move nested loop bodies out, so each of them is dropped empty
+            let mut stack = std::mem::take(&mut self.0);
+            while let Some(block) = stack.pop() {
+                if let OptBlock::Loop(mut inner) = block {
+                    stack.append(&mut inner.0);
+                }
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
     /// Block type (loop or basic block)
     pub enum OptBlock {
         /// Loop over inner code
@@ -104,7 +196,7 @@ pub mod opt_ins {
     /// IO instruction 
     /// 
     /// Can't be reordered with other io instructions / simplified in other way
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     pub enum IOOptIns {
         /// Print current cell value (as u8) to output stream
         Putchar(isize),
@@ -113,7 +205,7 @@ pub mod opt_ins {
     }
 
     /// Block of cell changes with precalculated offset
-    #[derive(Debug, Clone)]
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
     pub struct BasicBlock {
         /// Data poiner offset per block
         /// 
@@ -125,78 +217,187 @@ pub mod opt_ins {
 
     impl From<BfCode> for OptCode {
         fn from(value: BfCode) -> Self {
-            let mut offset = 0isize;
-            let mut cells = BTreeMap::new();
-            let add_cell = |offset, val, cells: &mut BTreeMap<isize, u8>| {
-                if let Some(v) = cells.get_mut(&offset) {
-                    *v = v.wrapping_add(val);
-                } else {
-                    cells.insert(offset, val);
-                }
-            };
-            let mut res = Vec::new();
-            macro_rules! push_cells {
-                ($cond:expr) => {
-                    if $cond {
-                        let mut ins = BTreeMap::new();
-                        std::mem::swap(&mut ins, &mut cells);
-                        res.push(OptBlock::Block(BasicBlock {
-                            ptr_offset: offset,
-                            ins,
-                        }));
-                        offset = 0;
-                    }
+            bf_to_opt_impl(value, &mut None)
+        }
+    }
+
+    /// Span tracking state for [`bf_to_opt_impl`]
+    struct SpanTracker<'a> {
+        map: &'a SourceMap,
+        /// pre-order index of next [`BfCode`] instruction
+        node: usize,
+        /// spans of [`OptCode`] blocks in pre-order
+        spans: Vec<Span>,
+    }
+
+    impl SpanTracker<'_> {
+        fn span(&self, first: usize, last: usize) -> Span {
+            let at = |i: usize| self.map.spans.get(i).copied().unwrap_or_default();
+            at(first).join(at(last))
+        }
+    }
+
+    /// Convert [`BfCode`] into [`OptCode`] and compute source span of each [`OptBlock`]
+    ///
+    /// Returned spans are in pre-order (like [`SourceMap::spans`]).
+    /// Spans are only valid for unchanged code: optimization passes can merge or drop blocks.
+    /// ```
+    /// # use bf_tools::{ ins_parser::{ parse_str_with_spans, Span }, optimizer::opt_ins::bf_to_opt_with_spans };
+    /// let (code, map) = parse_str_with_spans("+>[-].").unwrap();
+    /// let (_code, spans) = bf_to_opt_with_spans(code, &map);
+    /// assert_eq!(spans, vec![Span::new(0, 2), Span::new(2, 5), Span::new(3, 4), Span::new(5, 6)]);
+    /// ```
+    pub fn bf_to_opt_with_spans(code: BfCode, map: &SourceMap) -> (OptCode, Vec<Span>) {
+        let mut tracker = Some(SpanTracker {
+            map,
+            node: 0,
+            spans: Vec::new(),
+        });
+        let code = bf_to_opt_impl(code, &mut tracker);
+        (code, tracker.map(|t| t.spans).unwrap_or_default())
+    }
+
+    /// Conversion state of single loop body (or whole code)
+    struct ConvFrame {
+        ins: std::vec::IntoIter<BfIns>,
+        offset: isize,
+        cells: BTreeMap<isize, u8>,
+        /// first & last instruction of not yet pushed block
+        pending: Option<(usize, usize)>,
+        res: Vec<OptBlock>,
+    }
+
+    impl ConvFrame {
+        fn new(mut code: BfCode) -> Self {
+            Self {
+                ins: std::mem::take(&mut code.0).into_iter(),
+                offset: 0,
+                cells: BTreeMap::new(),
+                pending: None,
+                res: Vec::new(),
+            }
+        }
+        fn add_cell(&mut self, val: u8) {
+            if let Some(v) = self.cells.get_mut(&self.offset) {
+                *v = v.wrapping_add(val);
+            } else {
+                self.cells.insert(self.offset, val);
+            }
+        }
+        fn push_cells(&mut self, tracker: &mut Option<SpanTracker<'_>>) {
+            let ins = std::mem::take(&mut self.cells);
+            self.res.push(OptBlock::Block(BasicBlock {
+                ptr_offset: self.offset,
+                ins,
+            }));
+            if let (Some(t), Some((first, last))) = (tracker.as_mut(), self.pending.take()) {
+                let span = t.span(first, last);
+                t.spans.push(span);
+            }
+            self.offset = 0;
+        }
+        fn finish(mut self, tracker: &mut Option<SpanTracker<'_>>) -> OptCode {
+            if !self.cells.is_empty() || self.offset != 0 {
+                self.push_cells(tracker);
+            }
+            OptCode(self.res)
+        }
+    }
+
+    fn bf_to_opt_impl(value: BfCode, tracker: &mut Option<SpanTracker<'_>>) -> OptCode {
+        let mut cur = ConvFrame::new(value);
+        // enclosing loops
+        let mut stack = Vec::new();
+        loop {
+            let Some(ins) = cur.ins.next() else {
+                let Some(parent) = stack.pop() else {
+                    return cur.finish(tracker);
                 };
+                let inner = std::mem::replace(&mut cur, parent).finish(tracker);
+                cur.res.push(OptBlock::Loop(inner));
+                continue;
+            };
+            let node = tracker.as_mut().map(|t| {
+                t.node += 1;
+                t.node - 1
+            });
+            if let (Some(node), BfIns::Add(_) | BfIns::Sub(_) | BfIns::PtrAdd(_) | BfIns::PtrSub(_)) =
+                (node, &ins)
+            {
+                cur.pending = Some((cur.pending.map_or(node, |(first, _)| first), node));
             }
-            for ins in value.0 {
-                match ins {
-                    BfIns::Add(val) => add_cell(offset, val, &mut cells),
-                    BfIns::Sub(val) => add_cell(offset, 0u8.wrapping_sub(val), &mut cells),
-                    BfIns::PtrAdd(d) => offset += d as isize,
-                    BfIns::PtrSub(d) => offset -= d as isize,
-                    BfIns::Putchar => {
-                        push_cells!(!cells.is_empty());
-                        res.push(OptBlock::IOIns(IOOptIns::Putchar(offset)));
-                    },
-                    BfIns::Getchar => {
-                        push_cells!(!cells.is_empty());
-                        res.push(OptBlock::IOIns(IOOptIns::Getchar(offset)));
-                    },
-                    BfIns::Loop(inner) => {
-                        push_cells!(!cells.is_empty() || offset != 0);
-                        res.push(OptBlock::Loop(inner.into()));
+            match ins {
+                BfIns::Add(val) => cur.add_cell(val),
+                BfIns::Sub(val) => cur.add_cell(0u8.wrapping_sub(val)),
+                BfIns::PtrAdd(d) => cur.offset += d as isize,
+                BfIns::PtrSub(d) => cur.offset -= d as isize,
+                BfIns::Putchar | BfIns::Getchar => {
+                    if !cur.cells.is_empty() {
+                        cur.push_cells(tracker);
                     }
+                    if let (Some(t), Some(node)) = (tracker.as_mut(), node) {
+                        let span = t.span(node, node);
+                        t.spans.push(span);
+                    }
+                    cur.res.push(OptBlock::IOIns(if matches!(ins, BfIns::Putchar) {
+                        IOOptIns::Putchar(cur.offset)
+                    } else {
+                        IOOptIns::Getchar(cur.offset)
+                    }));
+                }
+                BfIns::Loop(inner) => {
+                    if !cur.cells.is_empty() || cur.offset != 0 {
+                        cur.push_cells(tracker);
+                    }
+                    // pointer moves before loop are already part of pushed block
+                    cur.pending = None;
+                    if let (Some(t), Some(node)) = (tracker.as_mut(), node) {
+                        let span = t.span(node, node);
+                        t.spans.push(span);
+                    }
+                    stack.push(std::mem::replace(&mut cur, ConvFrame::new(inner)));
                 }
             }
-            if !cells.is_empty() || offset != 0 {
-                res.push(OptBlock::Block(BasicBlock {
-                    ptr_offset: offset,
-                    ins: cells,
-                }));
-            }
-            OptCode(res)
+        }
+    }
+
+    /// Push pointer move by `d` cells
+    fn push_ptr_move(code: &mut Vec<BfIns>, d: isize) {
+        if d > 0 {
+            code.push(BfIns::PtrAdd(d as usize));
+        } else {
+            code.push(BfIns::PtrSub(-d as usize));
         }
     }
 
     impl From<OptCode> for BfCode {
-        fn from(value: OptCode) -> Self {
-            let mut code = Vec::new();
-            let mut offset = 0isize;
-            for v in value.0 {
+        fn from(mut value: OptCode) -> Self {
+            // (remaining blocks, converted instructions, pointer offset)
+            let mut cur = (std::mem::take(&mut value.0).into_iter(), Vec::new(), 0isize);
+            // enclosing loops
+            let mut stack = Vec::new();
+            loop {
+                let Some(v) = cur.0.next() else {
+                    let Some(parent) = stack.pop() else {
+                        return BfCode(cur.1);
+                    };
+                    let (_, inner, _) = std::mem::replace(&mut cur, parent);
+                    cur.1.push(BfIns::Loop(BfCode(inner)));
+                    continue;
+                };
+                let (_, code, offset) = &mut cur;
                 match v {
-                    OptBlock::Loop(inner) => code.push(BfIns::Loop(BfCode::from(inner))),
+                    OptBlock::Loop(mut inner) => {
+                        let inner = (std::mem::take(&mut inner.0).into_iter(), Vec::new(), 0);
+                        stack.push(std::mem::replace(&mut cur, inner));
+                    }
                     OptBlock::IOIns(ins) => {
                         let new_offset = match ins {
                             IOOptIns::Putchar(offset) | IOOptIns::Getchar(offset) => offset
                         };
-                        if offset != new_offset {
-                            let d = new_offset - offset;
-                            offset = new_offset;
-                            if d > 0 {
-                                code.push(BfIns::PtrAdd(d as usize));
-                            } else {
-                                code.push(BfIns::PtrSub(-d as usize));
-                            }
+                        if *offset != new_offset {
+                            push_ptr_move(code, new_offset - *offset);
+                            *offset = new_offset;
                         }
                         code.push(match ins {
                             IOOptIns::Putchar(_) => BfIns::Putchar,
@@ -205,68 +406,127 @@ pub mod opt_ins {
                     }
                     OptBlock::Block(bb) => {
                         for (new_offset, val) in bb.ins {
-                            if offset != new_offset {
-                                let d = new_offset - offset;
-                                offset = new_offset;
-                                if d > 0 {
-                                    code.push(BfIns::PtrAdd(d as usize));
-                                } else {
-                                    code.push(BfIns::PtrSub(-d as usize));
-                                }
+                            if *offset != new_offset {
+                                push_ptr_move(code, new_offset - *offset);
+                                *offset = new_offset;
                             }
                             code.push(if val < 128 { BfIns::Add(val) } else { BfIns::Sub(0u8.wrapping_sub(val)) });
                         }
-                        if offset != bb.ptr_offset {
-                            let d = bb.ptr_offset - offset;
-                            if d > 0 {
-                                code.push(BfIns::PtrAdd(d as usize));
-                            } else {
-                                code.push(BfIns::PtrSub(-d as usize));
-                            }
+                        if *offset != bb.ptr_offset {
+                            push_ptr_move(code, bb.ptr_offset - *offset);
                         }
-                        offset = 0;
+                        *offset = 0;
                     }
                 }
             }
-            BfCode(code)
+        }
+    }
+
+    /// Counter of [`OptCode::ins_len`]
+    struct InsLen(usize);
+
+    impl Visit<OptCode> for InsLen {
+        fn visit_node(&mut self, block: &OptBlock) {
+            self.0 += match block {
+                OptBlock::Block(b) => b.ins.len(),
+                _ => 1,
+            };
+        }
+        fn enter_loop(&mut self, _body: &OptCode) -> bool {
+            self.0 += 1;
+            true
+        }
+    }
+
+    /// Checker of [`OptCode::has_side_effects`]
+    struct SideEffects(bool);
+
+    impl Visit<OptCode> for SideEffects {
+        fn visit_node(&mut self, block: &OptBlock) {
+            self.0 |= matches!(block, OptBlock::IOIns(_));
+        }
+        fn enter_loop(&mut self, _body: &OptCode) -> bool {
+            !self.0
+        }
+    }
+
+    /// Checker that every loop of [`OptCode::offset`] returns pointer back
+    struct Balanced(bool);
+
+    impl Visit<OptCode> for Balanced {
+        fn enter_loop(&mut self, body: &OptCode) -> bool {
+            //TODO fix for loops like [[-]]? (with single loop instruction inside)
+            self.0 &= body.own_offset() == 0;
+            self.0
+        }
+    }
+
+    /// Collector of [`OptCode::loop_offsets`]
+    #[derive(Default)]
+    struct LoopOffsets {
+        /// pre-order id of next block
+        id: usize,
+        /// (id, own offset, all inner loops are balanced) of enclosing loops
+        stack: Vec<(usize, isize, bool)>,
+        /// offsets by id of loop
+        offsets: Vec<(usize, Option<isize>)>,
+    }
+
+    impl Visit<OptCode> for LoopOffsets {
+        fn visit_node(&mut self, _block: &OptBlock) {
+            self.id += 1;
+        }
+        fn enter_loop(&mut self, body: &OptCode) -> bool {
+            self.stack.push((self.id, body.own_offset(), true));
+            self.id += 1;
+            true
+        }
+        fn leave_loop(&mut self, _body: &OptCode) {
+            if let Some((id, offset, inner)) = self.stack.pop() {
+                // same check as `Balanced` for every loop of parent's body
+                if let Some(parent) = self.stack.last_mut() {
+                    parent.2 &= inner && offset == 0;
+                }
+                self.offsets.push((id, inner.then_some(offset)));
+            }
         }
     }
 
     impl OptCode {
         /// OptCode len in instruction (without offset's counting)
         pub fn ins_len(&self) -> usize {
-            self.0.iter().fold(0usize, |l, b| {
-                l + match b {
-                    OptBlock::Block(b) => b.ins.len(),
-                    OptBlock::Loop(inner) => 1 + inner.ins_len(),
-                    OptBlock::IOIns(_) => 1
-                }
-            })
+            let mut len = InsLen(0);
+            self.visit(&mut len);
+            len.0
         }
         /// Get data poiner offset
         pub fn offset(&self) -> Option<isize> {
-            let mut offset = 0;
-            for ins in &self.0 {
-                match ins {
-                    OptBlock::Block(bb) => offset += bb.ptr_offset,
-                    OptBlock::IOIns(_) => {}
-                    OptBlock::Loop(inner) => {
-                        //TODO fix for loops like [[-]]? (with single loop instruction inside)
-                        if !matches!(inner.offset(), Some(0)) {
-                            return None;
-                        }
-                    }
-                }
-            }
-            Some(offset)
+            let mut balanced = Balanced(true);
+            self.visit(&mut balanced);
+            balanced.0.then(|| self.own_offset())
+        }
+        /// [`Self::offset`] of body of every loop, computed in single pass
+        ///
+        /// Loops are identified by pre-order index among all blocks (loop itself is counted before it's body),
+        /// result is sorted by it
+        pub(crate) fn loop_offsets(&self) -> Vec<(usize, Option<isize>)> {
+            let mut offsets = LoopOffsets::default();
+            self.visit(&mut offsets);
+            offsets.offsets.sort_unstable();
+            offsets.offsets
+        }
+        /// Sum of pointer offsets of top level blocks
+        fn own_offset(&self) -> isize {
+            self.0.iter().map(|b| match b {
+                OptBlock::Block(bb) => bb.ptr_offset,
+                _ => 0,
+            }).sum()
         }
         /// Check for Putchar|Getchar instructions in code block
         pub fn has_side_effects(&self) -> bool {
-            self.0.iter().any(|b| match b {
-                OptBlock::Block(_) => false,
-                OptBlock::IOIns(_) => true,
-                OptBlock::Loop(inner) => inner.has_side_effects(),
-            })
+            let mut side_effects = SideEffects(false);
+            self.visit(&mut side_effects);
+            side_effects.0
         }
     }
 }
@@ -274,6 +534,31 @@ pub mod opt_ins {
 pub use opt_ins::OptCode;
 
 /// Optimization pass trait
+///
+/// Passes usually walk code with [`crate::visit::Fold`], so only changed blocks need to be handled
+/// ```
+/// # use bf_tools::{ bf, ins::BfCode, optimizer::{ OptCode, OptPass, OptState, opt_ins::OptBlock }, visit::{ Fold, Tree } };
+/// // remove blocks which do nothing
+/// #[derive(Debug)]
+/// struct RemoveNop;
+/// impl Fold<OptCode> for RemoveNop {
+///     fn fold_node(&mut self, block: OptBlock, res: &mut Vec<OptBlock>) {
+///         if !matches!(&block, OptBlock::Block(bb) if bb.ptr_offset == 0 && bb.ins.values().all(|v| *v == 0)) {
+///             res.push(block);
+///         }
+///     }
+/// }
+/// impl OptPass for RemoveNop {
+///     fn optimize(&self, code: OptCode, is_changed: &mut bool) -> OptCode {
+///         let len = code.ins_len();
+///         let code = code.fold(&mut RemoveNop);
+///         *is_changed |= code.ins_len() != len;
+///         code
+///     }
+/// }
+/// let mut state = OptState::builder().add_pass(Box::new(RemoveNop)).build();
+/// assert_eq!(BfCode::from(state.run_passes(bf!(+-[-<>]>).into())), bf!([-]>));
+/// ```
 pub trait OptPass: std::fmt::Debug {
     /// Function for pass invocation
     ///
@@ -283,6 +568,14 @@ pub trait OptPass: std::fmt::Debug {
 
 /// Useless instruction pass
 pub mod group_instructions;
+/// Static data pointer bounds analysis
+pub mod bounds;
+/// Textual form of [`OptCode`]
+pub mod text;
+/// Size-minimizing emitter of [`OptCode`]
+pub mod golf;
+/// Decompiler of [`OptCode`] to pseudo-C
+pub mod decompile;
 
 /// All built-in passes grouped in one module
 pub mod passes {
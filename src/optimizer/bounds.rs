@@ -0,0 +1,222 @@
+use super::opt_ins::{IOOptIns, OptBlock};
+
+/// Range of pointer positions `min..=max` (`None` bound is infinite)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PtrRange {
+    /// Lowest position (`None` - unbounded)
+    pub min: Option<isize>,
+    /// Highest position (`None` - unbounded)
+    pub max: Option<isize>,
+}
+
+impl PtrRange {
+    /// Range with single position
+    #[inline]
+    pub const fn point(pos: isize) -> Self {
+        Self {
+            min: Some(pos),
+            max: Some(pos),
+        }
+    }
+    /// Range of all positions
+    pub const UNBOUNDED: Self = Self {
+        min: None,
+        max: None,
+    };
+    /// Smallest range that covers both ranges
+    #[inline]
+    #[must_use]
+    pub fn join(self, other: Self) -> Self {
+        Self {
+            min: self.min.zip(other.min).map(|(a, b)| a.min(b)),
+            max: self.max.zip(other.max).map(|(a, b)| a.max(b)),
+        }
+    }
+    /// Positions `a + b` for all `a` from `self` and `b` from `other`
+    #[inline]
+    #[must_use]
+    pub fn sum(self, other: Self) -> Self {
+        let sum = |a: Option<isize>, b: Option<isize>| a.zip(b).and_then(|(a, b)| a.checked_add(b));
+        Self {
+            min: sum(self.min, other.min),
+            max: sum(self.max, other.max),
+        }
+    }
+    /// Check that all positions are not negative
+    #[inline]
+    pub fn is_non_negative(&self) -> bool {
+        self.min.is_some_and(|min| min >= 0)
+    }
+}
+
+/// Effect of code on data pointer, relative to pointer position before code
+///
+/// Computed by abstract interpretation over intervals:
+/// each loop is widened to infinity in directions it moves pointer per iteration
+/// ```
+/// # use bf_tools::{ bf, optimizer::{ OptCode, bounds::{ Bounds, PtrRange } } };
+/// let bounds = Bounds::of(&OptCode::from(bf!(>>[-<+>]<<)).0);
+/// assert_eq!(bounds.exit, PtrRange::point(0));
+/// assert_eq!(bounds.access, PtrRange { min: Some(0), max: Some(2) });
+///
+/// // `[>]` moves right by unknown distance
+/// let bounds = Bounds::of(&OptCode::from(bf!(+[>]<)).0);
+/// assert_eq!(bounds.exit, PtrRange { min: Some(-1), max: None });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Bounds {
+    /// Possible pointer positions after code
+    pub exit: PtrRange,
+    /// All pointer positions and accessed cells during code execution
+    pub access: PtrRange,
+}
+
+impl Bounds {
+    /// Bounds of code that doesn't touch tape
+    pub const EMPTY: Self = Self {
+        exit: PtrRange::point(0),
+        access: PtrRange::point(0),
+    };
+    /// Bounds of sequence of blocks
+    pub fn of(code: &[OptBlock]) -> Self {
+        let table = BoundsTable::new(code);
+        table.seq(0..table.blocks.len())
+    }
+    /// Bounds of single block
+    pub fn of_block(block: &OptBlock) -> Self {
+        BoundsTable::new(std::slice::from_ref(block)).blocks[0].bounds
+    }
+    /// Bounds of block without looking into loop body (see [`BoundsTable::new`])
+    fn of_leaf(block: &OptBlock) -> Self {
+        match block {
+            OptBlock::Block(bb) => {
+                let keys = bb.ins.keys().copied();
+                let min = keys
+                    .clone()
+                    .chain([0, bb.ptr_offset])
+                    .min()
+                    .unwrap_or_default();
+                let max = keys.chain([0, bb.ptr_offset]).max().unwrap_or_default();
+                Self {
+                    exit: PtrRange::point(bb.ptr_offset),
+                    access: PtrRange {
+                        min: Some(min),
+                        max: Some(max),
+                    },
+                }
+            }
+            OptBlock::IOIns(IOOptIns::Putchar(offset) | IOOptIns::Getchar(offset)) => Self {
+                exit: PtrRange::point(0),
+                access: PtrRange::point(0).join(PtrRange::point(*offset)),
+            },
+            OptBlock::Loop(_) => Self::EMPTY,
+        }
+    }
+    /// Bounds of `self` followed by `next`
+    #[must_use]
+    pub fn then(self, next: Self) -> Self {
+        Self {
+            exit: self.exit.sum(next.exit),
+            access: self.access.join(self.exit.sum(next.access)),
+        }
+    }
+    /// Bounds of `self` followed by loop with `body`
+    #[must_use]
+    pub fn then_loop(self, body: Self) -> Self {
+        let head = loop_head(self.exit, body);
+        Self {
+            exit: head,
+            access: self.access.join(head).join(head.sum(body.access)),
+        }
+    }
+    /// Absolute bounds of code executed with pointer in `entry` range
+    #[inline]
+    #[must_use]
+    pub fn at(self, entry: PtrRange) -> Self {
+        Self {
+            exit: entry.sum(self.exit),
+            access: entry.sum(self.access),
+        }
+    }
+}
+
+/// Pointer positions at condition check of loop with `body` entered with pointer in `entry` range
+pub fn loop_head(entry: PtrRange, body: Bounds) -> PtrRange {
+    PtrRange {
+        min: entry
+            .min
+            .filter(|_| body.exit.min.is_some_and(|min| min >= 0)),
+        max: entry
+            .max
+            .filter(|_| body.exit.max.is_some_and(|max| max <= 0)),
+    }
+}
+
+/// Bounds of single block in [`BoundsTable`]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BlockBounds {
+    /// Bounds of block
+    pub bounds: Bounds,
+    /// Bounds of loop body ([`Bounds::EMPTY`] for other blocks)
+    pub body: Bounds,
+    /// Count of blocks in subtree (including block itself)
+    pub size: usize,
+    is_loop: bool,
+}
+
+/// Bounds of all blocks of code in pre-order (index is same as block id in `DebugInfo`)
+///
+/// Computed without recursion, so it works for arbitrary deeply nested code
+#[derive(Debug, Clone)]
+pub(crate) struct BoundsTable {
+    pub blocks: Vec<BlockBounds>,
+}
+
+impl BoundsTable {
+    pub fn new(code: &[OptBlock]) -> Self {
+        let mut blocks: Vec<BlockBounds> = Vec::new();
+        // (remaining blocks, id of loop) for each enclosing loop
+        let mut stack = vec![(code.iter(), None::<usize>)];
+        while let Some((iter, parent)) = stack.last_mut() {
+            let Some(block) = iter.next() else {
+                if let Some(id) = *parent {
+                    let size = blocks.len() - id;
+                    blocks[id].size = size;
+                }
+                stack.pop();
+                continue;
+            };
+            let id = blocks.len();
+            blocks.push(BlockBounds {
+                bounds: Bounds::of_leaf(block),
+                body: Bounds::EMPTY,
+                size: 1,
+                is_loop: matches!(block, OptBlock::Loop(_)),
+            });
+            if let OptBlock::Loop(inner) = block {
+                stack.push((inner.0.iter(), Some(id)));
+            }
+        }
+        let mut table = Self { blocks };
+        // inner loops have greater ids, so they're done before outer ones
+        for id in (0..table.blocks.len()).rev() {
+            if table.blocks[id].is_loop {
+                let body = table.seq(id + 1..id + table.blocks[id].size);
+                let b = &mut table.blocks[id];
+                b.body = body;
+                b.bounds = Bounds::EMPTY.then_loop(body);
+            }
+        }
+        table
+    }
+    /// Bounds of sibling blocks which subtrees cover `ids`
+    pub fn seq(&self, ids: std::ops::Range<usize>) -> Bounds {
+        let mut id = ids.start;
+        let mut acc = Bounds::EMPTY;
+        while id < ids.end {
+            acc = acc.then(self.blocks[id].bounds);
+            id += self.blocks[id].size;
+        }
+        acc
+    }
+}
@@ -0,0 +1,247 @@
+use super::opt_ins::{IOOptIns, OptBlock, OptCode};
+use crate::visit::{Tree, Visit};
+use std::fmt::Write;
+
+/// Nesting of loops which is shown with indentation (output of deep loops stays linear)
+const MAX_INDENT: usize = 16;
+
+/// Straight-line statement, cells are offsets from `p`
+#[derive(Debug, Clone, Copy)]
+enum Stmt {
+    Add(isize, u8),
+    Set(isize, u8),
+    /// `c[dst] += c[src] * factor`
+    MulAdd {
+        dst: isize,
+        src: isize,
+        factor: u8,
+    },
+    Put(isize),
+    Get(isize),
+}
+
+impl Stmt {
+    const fn mentions(self, cell: isize) -> bool {
+        match self {
+            Self::Add(c, _) | Self::Set(c, _) | Self::Put(c) | Self::Get(c) => c == cell,
+            Self::MulAdd { dst, src, .. } => dst == cell || src == cell,
+        }
+    }
+}
+
+/// Name of cell with offset from `p`
+struct CellName(isize);
+
+impl std::fmt::Display for CellName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            0 => write!(f, "c[p]"),
+            1.. => write!(f, "c[p+{}]", self.0),
+            _ => write!(f, "c[p-{}]", self.0.unsigned_abs()),
+        }
+    }
+}
+
+/// Sign and absolute value of cell change
+const fn signed(val: u8) -> (char, u8) {
+    if val < 128 {
+        ('+', val)
+    } else {
+        ('-', val.wrapping_neg())
+    }
+}
+
+impl std::fmt::Display for Stmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::Add(cell, val) => {
+                let (sign, val) = signed(val);
+                write!(f, "{} {sign}= {val};", CellName(cell))
+            }
+            Self::Set(cell, val) => write!(f, "{} = {val};", CellName(cell)),
+            Self::MulAdd { dst, src, factor } => {
+                let (sign, factor) = signed(factor);
+                write!(f, "{} {sign}= {}", CellName(dst), CellName(src))?;
+                if factor == 1 {
+                    write!(f, ";")
+                } else {
+                    write!(f, " * {factor};")
+                }
+            }
+            Self::Put(cell) => write!(f, "putchar({});", CellName(cell)),
+            Self::Get(cell) => write!(f, "{} = getchar();", CellName(cell)),
+        }
+    }
+}
+
+/// Decompiler state (see [`decompile`])
+struct Decompiler {
+    out: String,
+    /// nesting of loop which is decompiled now
+    depth: usize,
+    /// pointer moves which aren't applied to `p` yet
+    offset: isize,
+    /// statements since last loop boundary (they're merged before printing)
+    pending: Vec<Stmt>,
+}
+
+impl Decompiler {
+    fn line(&mut self, text: impl std::fmt::Display) {
+        for _ in 0..self.depth.min(MAX_INDENT) {
+            self.out.push_str("    ");
+        }
+        // writing to `String` doesn't fail
+        let _ = writeln!(self.out, "{text}");
+    }
+    fn flush(&mut self) {
+        for stmt in std::mem::take(&mut self.pending) {
+            self.line(stmt);
+        }
+    }
+    /// Apply pointer moves to `p`
+    fn move_ptr(&mut self) {
+        self.flush();
+        let (sign, offset) = if self.offset < 0 {
+            ('-', -self.offset)
+        } else {
+            ('+', self.offset)
+        };
+        if offset != 0 {
+            self.line(format_args!("p {sign}= {offset};"));
+        }
+        self.offset = 0;
+    }
+    /// Add statement, changes of cells which are set are merged into assignments
+    fn push(&mut self, stmt: Stmt) {
+        if let Stmt::Add(cell, val) = stmt {
+            let last = self.pending.iter().rposition(|s| s.mentions(cell));
+            match last.map(|i| (i, &mut self.pending[i])) {
+                Some((_, Stmt::Set(_, prev))) => *prev = prev.wrapping_add(val),
+                Some((i, Stmt::Add(_, prev))) if prev.wrapping_add(val) == 0 => {
+                    self.pending.remove(i);
+                }
+                Some((_, Stmt::Add(_, prev))) => *prev = prev.wrapping_add(val),
+                _ => self.pending.push(stmt),
+            }
+        } else {
+            self.pending.push(stmt);
+        }
+    }
+    /// Push statements of loop with `body` if it's set or multiplication loop
+    fn idiom(&mut self, body: &OptCode) -> bool {
+        let [OptBlock::Block(bb)] = body.0.as_slice() else {
+            return false;
+        };
+        let base = self.offset;
+        let step = bb.ins.get(&0).copied().unwrap_or_default();
+        if bb.ptr_offset != 0 || step % 2 == 0 || (bb.ins.len() > 1 && step != 1 && step != 255) {
+            return false;
+        }
+        // loop runs `c[p]` times if step is -1 and `-c[p]` times if it's +1
+        let sign = if step == 1 { 255u8 } else { 1 };
+        for (&dst, &val) in bb.ins.iter().filter(|(dst, _)| **dst != 0) {
+            self.push(Stmt::MulAdd {
+                dst: base + dst,
+                src: base,
+                factor: val.wrapping_mul(sign),
+            });
+        }
+        self.push(Stmt::Set(base, 0));
+        true
+    }
+}
+
+impl Visit<OptCode> for Decompiler {
+    fn visit_node(&mut self, block: &OptBlock) {
+        match block {
+            OptBlock::Block(bb) => {
+                for (cell, val) in &bb.ins {
+                    self.push(Stmt::Add(self.offset + cell, *val));
+                }
+                self.offset += bb.ptr_offset;
+            }
+            OptBlock::IOIns(IOOptIns::Putchar(cell)) => self.push(Stmt::Put(self.offset + cell)),
+            OptBlock::IOIns(IOOptIns::Getchar(cell)) => self.push(Stmt::Get(self.offset + cell)),
+            OptBlock::Loop(_) => {}
+        }
+    }
+    fn enter_loop(&mut self, body: &OptCode) -> bool {
+        if self.idiom(body) {
+            return false;
+        }
+        // scan loop `[>>]`
+        if let [OptBlock::Block(bb)] = body.0.as_slice() {
+            if bb.ins.is_empty() && bb.ptr_offset != 0 {
+                self.move_ptr();
+                let (sign, step) = if bb.ptr_offset < 0 {
+                    ('-', -bb.ptr_offset)
+                } else {
+                    ('+', bb.ptr_offset)
+                };
+                self.line(format_args!("while (c[p]) p {sign}= {step};"));
+                return false;
+            }
+        }
+        // `p` is moved to loop base, so cells inside are named relative to it
+        self.move_ptr();
+        if body.0.is_empty() {
+            self.line("while (c[p]) {}");
+            return false;
+        }
+        self.line("while (c[p]) {");
+        self.depth += 1;
+        true
+    }
+    fn leave_loop(&mut self, _body: &OptCode) {
+        // body of balanced loop without inner loops doesn't move `p`
+        self.move_ptr();
+        self.depth -= 1;
+        self.line("}");
+    }
+}
+
+/// Decompile [`OptCode`] into readable pseudo-C
+///
+/// Tape is `c`, data pointer is `p` (starts from 0).
+/// Pointer moves are applied to `p` only at loop boundaries,
+/// so loop is always `while (c[p])` and cells are named relative to loop base (`c[p+2]`).
+/// Loop body is indented up to 16 levels deep.
+/// Set loops (`[-]`), multiplication loops (`[->++<]`) and scan loops (`[>]`) are collapsed
+/// into assignments, and changes of cells which are set are merged into them
+/// ```
+/// # use bf_tools::{ bf, ins::BfCode, optimizer::{ OptCode, decompile::decompile } };
+/// let code = OptCode::from(bf!(>[-]++++++[<++++++++>-]<+.>>,[-<+>]<[>+<[-]]>[>]<.));
+/// let expected = [
+///     "c[p+1] = 6;",
+///     "c[p] += c[p+1] * 8;",
+///     "c[p+1] = 0;",
+///     "c[p] += 1;",
+///     "putchar(c[p]);",
+///     "c[p+2] = getchar();",
+///     "c[p+1] += c[p+2];",
+///     "c[p+2] = 0;",
+///     "p += 1;",
+///     "while (c[p]) {",
+///     "    c[p+1] += 1;",
+///     "    c[p] = 0;",
+///     "}",
+///     "p += 1;",
+///     "while (c[p]) p += 1;",
+///     "putchar(c[p-1]);",
+/// ];
+/// assert_eq!(decompile(&code).lines().collect::<Vec<_>>(), expected);
+///
+/// let deep = OptCode::from(("[.".repeat(100) + &"]".repeat(100)).parse::<BfCode>().unwrap());
+/// assert!(decompile(&deep).lines().all(|line| line.len() <= 16 * 4 + "putchar(c[p]);".len()));
+/// ```
+pub fn decompile(code: &OptCode) -> String {
+    let mut decompiler = Decompiler {
+        out: String::new(),
+        depth: 0,
+        offset: 0,
+        pending: Vec::new(),
+    };
+    code.visit(&mut decompiler);
+    decompiler.flush();
+    decompiler.out
+}
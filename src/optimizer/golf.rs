@@ -0,0 +1,481 @@
+use super::bounds::{Bounds, BoundsTable, PtrRange};
+use super::opt_ins::{BasicBlock, IOOptIns, OptBlock, OptCode};
+use crate::ins::{BfCode, BfIns};
+use crate::interpreter::{run::RunStatus, InterpCode, InterpreteError, Interpreter};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Max counter and step of multiplication loop
+const MAX_FACTOR: u8 = 32;
+
+/// Length of `+`/`-` sequence adding `d` to cell
+pub(crate) const fn add_len(d: u8) -> usize {
+    if d < 128 {
+        d as usize
+    } else {
+        256 - d as usize
+    }
+}
+
+/// Push `+`/`-` sequence adding `d` to cell
+pub(crate) fn push_add(code: &mut Vec<BfIns>, d: u8) {
+    match d {
+        0 => {}
+        1..=127 => code.push(BfIns::Add(d)),
+        _ => code.push(BfIns::Sub(d.wrapping_neg())),
+    }
+}
+
+/// Push pointer move by `d` cells
+pub(crate) fn push_move(code: &mut Vec<BfIns>, d: isize) {
+    match d {
+        0 => {}
+        1.. => code.push(BfIns::PtrAdd(d.unsigned_abs())),
+        _ => code.push(BfIns::PtrSub(d.unsigned_abs())),
+    }
+}
+
+/// Loop `+{counter}[>{step}<-]` which runs in zero scratch cell next to target cell
+#[derive(Clone, Copy)]
+struct MulLoop {
+    counter: u8,
+    /// value added to target cell per iteration
+    step: u8,
+}
+
+impl MulLoop {
+    /// Length in characters (`[`, `]`, two moves and counter decrement are 5 chars)
+    const fn len(self) -> usize {
+        self.counter as usize + add_len(self.step) + 5
+    }
+    /// Value added to target cell
+    const fn value(self) -> u8 {
+        self.counter.wrapping_mul(self.step)
+    }
+    /// Shortest loop with correction after it that adds `d`, if it's shorter than plain `+`/`-`
+    fn find(d: u8) -> Option<Self> {
+        let mut best: Option<(usize, Self)> = None;
+        for counter in 2..=MAX_FACTOR {
+            for step in 2..=MAX_FACTOR {
+                for step in [step, step.wrapping_neg()] {
+                    let mul = Self { counter, step };
+                    let len = mul.len() + add_len(d.wrapping_sub(mul.value()));
+                    if !matches!(best, Some((best, _)) if best <= len) {
+                        best = Some((len, mul));
+                    }
+                }
+            }
+        }
+        best.filter(|(len, _)| *len < add_len(d))
+            .map(|(_, mul)| mul)
+    }
+    /// Push loop, `dir` - direction from scratch cell to target cell
+    fn push(self, code: &mut Vec<BfIns>, dir: isize) {
+        code.push(BfIns::Add(self.counter));
+        let mut body = Vec::new();
+        push_move(&mut body, dir);
+        push_add(&mut body, self.step);
+        push_move(&mut body, -dir);
+        body.push(BfIns::Sub(1));
+        code.push(BfIns::Loop(BfCode(body)));
+    }
+}
+
+/// Known cell values, indexed by pointer position relative to code start
+#[derive(Default)]
+struct Known {
+    /// Explicitly tracked cells (`None` - value is unknown)
+    vals: BTreeMap<isize, Option<u8>>,
+    /// Cells which aren't tracked are 0 (except cells in `dirty`)
+    zero_default: bool,
+    /// Hull of all cells with forgotten values
+    dirty: Option<(isize, isize)>,
+    /// Positions are tape indices, so negative cells don't exist
+    absolute: bool,
+}
+
+impl Known {
+    /// Knowledge at code start: all cells are 0
+    fn start() -> Self {
+        Self {
+            zero_default: true,
+            absolute: true,
+            ..Self::default()
+        }
+    }
+    fn get(&self, pos: isize) -> Option<u8> {
+        match self.vals.get(&pos) {
+            Some(val) => *val,
+            None => (self.zero_default
+                && !self
+                    .dirty
+                    .is_some_and(|(min, max)| (min..=max).contains(&pos)))
+            .then_some(0),
+        }
+    }
+    /// Cell can be used as scratch space
+    ///
+    /// Cells that aren't tracked may be left of tape start unless positions are absolute,
+    /// tracked cells were accessed by code itself
+    fn is_free(&self, pos: isize) -> bool {
+        let exists = if self.absolute {
+            pos >= 0
+        } else {
+            self.vals.contains_key(&pos)
+        };
+        exists && self.get(pos) == Some(0)
+    }
+    fn add(&mut self, pos: isize, d: u8) {
+        let val = self.get(pos).map(|v| v.wrapping_add(d));
+        self.vals.insert(pos, val);
+    }
+    /// Forget values of cells `min..=max`
+    fn forget(&mut self, min: isize, max: isize) {
+        let keys: Vec<_> = self.vals.range(min..=max).map(|(pos, _)| *pos).collect();
+        for pos in keys {
+            self.vals.remove(&pos);
+        }
+        self.dirty = Some(
+            self.dirty
+                .map_or((min, max), |(a, b)| (a.min(min), b.max(max))),
+        );
+    }
+    /// Knowledge at start of loop body iteration, loop accesses only cells `min..=max`
+    ///
+    /// Only neighbours of accessed cells can be scratch cells inside body, so other cells are not copied
+    fn inner(&self, min: isize, max: isize) -> Self {
+        let mut vals = BTreeMap::new();
+        for pos in [min - 1, max + 1] {
+            if let Some(val) = self.get(pos) {
+                vals.insert(pos, Some(val));
+            }
+        }
+        Self {
+            vals,
+            absolute: self.absolute,
+            ..Self::default()
+        }
+    }
+}
+
+/// Cell visit of [`BasicBlock`] emission
+#[derive(Clone, Copy)]
+struct Visit {
+    pos: isize,
+    /// Loop to the next cell in visiting direction (runs before `add`, while cell is still 0)
+    mul: Option<MulLoop>,
+    add: u8,
+}
+
+/// Emission state of single loop body (or whole code)
+struct Frame<'a> {
+    blocks: std::slice::Iter<'a, OptBlock>,
+    /// Pre-order id of loop (unused for whole code)
+    id: usize,
+    code: Vec<BfIns>,
+    known: Known,
+    /// Position of data pointer in [`OptCode`]
+    base: isize,
+    /// Position of data pointer in emitted code
+    ptr: isize,
+}
+
+impl Frame<'_> {
+    fn move_to(&mut self, pos: isize) {
+        push_move(&mut self.code, pos - self.ptr);
+        self.ptr = pos;
+    }
+    /// Cells of block with order of visiting, `dir` - sweep direction
+    fn plan(
+        &self,
+        cells: &[(isize, u8)],
+        dir: isize,
+        muls: Option<&[Option<MulLoop>; 256]>,
+    ) -> Vec<Visit> {
+        let mut visits = BTreeMap::new();
+        let mut targets = BTreeSet::new();
+        let ordered: Box<dyn Iterator<Item = &(isize, u8)>> = if dir > 0 {
+            Box::new(cells.iter())
+        } else {
+            Box::new(cells.iter().rev())
+        };
+        for &(pos, d) in ordered {
+            let scratch = pos - dir;
+            let mul = muls
+                .and_then(|muls| muls[usize::from(d)])
+                .filter(|_| self.known.is_free(scratch) && !targets.contains(&scratch));
+            let visit = |pos| Visit {
+                pos,
+                mul: None,
+                add: 0,
+            };
+            let add = match mul {
+                Some(mul) => {
+                    targets.insert(pos);
+                    visits.entry(scratch).or_insert_with(|| visit(scratch)).mul = Some(mul);
+                    d.wrapping_sub(mul.value())
+                }
+                None => d,
+            };
+            if add != 0 {
+                visits.entry(pos).or_insert_with(|| visit(pos)).add = add;
+            }
+        }
+        let mut visits: Vec<_> = visits.into_values().collect();
+        if dir < 0 {
+            visits.reverse();
+        }
+        visits
+    }
+    /// Length of code emitted for `visits`, with pointer move to `end` after them
+    fn plan_len(&self, visits: &[Visit], end: Option<isize>) -> usize {
+        let ops: usize = visits
+            .iter()
+            .map(|v| add_len(v.add) + v.mul.map_or(0, MulLoop::len))
+            .sum();
+        let moves = match (visits.first(), visits.last()) {
+            (Some(first), Some(last)) => {
+                self.ptr.abs_diff(first.pos)
+                    + first.pos.abs_diff(last.pos)
+                    + end.map_or(0, |end| end.abs_diff(last.pos))
+            }
+            _ => 0,
+        };
+        ops + moves
+    }
+    /// Emit block, `end` - position where pointer is needed after block (if it's known)
+    fn emit_block(&mut self, bb: &BasicBlock, end: Option<isize>, muls: &[Option<MulLoop>; 256]) {
+        let cells: Vec<_> = bb
+            .ins
+            .iter()
+            .filter(|(_, d)| **d != 0)
+            .map(|(offset, d)| (self.base + offset, *d))
+            .collect();
+        let plans = [(1, true), (-1, true), (1, false), (-1, false)]
+            .map(|(dir, mul)| (dir, self.plan(&cells, dir, mul.then_some(muls))));
+        if let Some((dir, visits)) = plans
+            .into_iter()
+            .min_by_key(|(_, visits)| self.plan_len(visits, end))
+        {
+            for visit in visits {
+                self.move_to(visit.pos);
+                if let Some(mul) = visit.mul {
+                    mul.push(&mut self.code, dir);
+                }
+                push_add(&mut self.code, visit.add);
+            }
+        }
+        for (pos, d) in cells {
+            self.known.add(pos, d);
+        }
+        self.base += bb.ptr_offset;
+    }
+}
+
+/// Range of cells accessed by loop with `bounds`, if loop returns pointer back
+fn loop_cells(bounds: Bounds) -> Option<(isize, isize)> {
+    match bounds.access {
+        PtrRange {
+            min: Some(min),
+            max: Some(max),
+        } if bounds.exit == PtrRange::point(0) => Some((min, max)),
+        _ => None,
+    }
+}
+
+/// Emit shortest found [`BfCode`] with same output as `code`
+///
+/// - pointer moves are delayed until cell is accessed, cells of [`BasicBlock`] are visited
+///   in order with shortest pointer path
+/// - constants are built with multiplication loop in known zero neighbour cell, if it's shorter
+/// - loops which start at known zero cell and code after last io or loop are removed
+///
+/// Only output is preserved: final tape and data pointer can differ.
+/// Code is expected not to move pointer left of tape start, negative cells are never used as scratch.
+/// Result can be checked with [`verify`]
+/// ```
+/// # use bf_tools::{ bf, ins::BfCode, optimizer::{ OptCode, golf::{ minimize, verify } } };
+/// let code: BfCode = ("+".repeat(65) + ".>>").parse().unwrap();
+/// let golfed = minimize(&OptCode::from(code.clone()));
+/// assert_eq!(golfed.to_string(), ">++++++++[<++++++++>-]<+.");
+/// assert!(verify(&code, &golfed, b"", 1000).is_ok());
+///
+/// // dead loop and cells changed after last output are removed
+/// assert_eq!(minimize(&OptCode::from(bf!([-.]+.+))), bf!(+.));
+/// ```
+pub fn minimize(code: &OptCode) -> BfCode {
+    let table = BoundsTable::new(&code.0);
+    let muls: [_; 256] = std::array::from_fn(|d| MulLoop::find(d as u8));
+    // blocks after last io or loop don't change output
+    let live = code
+        .0
+        .iter()
+        .rposition(|block| !matches!(block, OptBlock::Block(_)))
+        .map_or(0, |i| i + 1);
+    let mut cur = Frame {
+        blocks: code.0[..live].iter(),
+        id: 0,
+        code: Vec::new(),
+        known: Known::start(),
+        base: 0,
+        ptr: 0,
+    };
+    // enclosing loops
+    let mut stack = Vec::new();
+    // pre-order id of next block
+    let mut next_id = 0;
+    loop {
+        let Some(block) = cur.blocks.next() else {
+            let Some(parent) = stack.pop() else {
+                return BfCode(cur.code);
+            };
+            let mut body = std::mem::replace(&mut cur, parent);
+            body.move_to(body.base);
+            cur.code.push(BfIns::Loop(BfCode(body.code)));
+            match loop_cells(table.blocks[body.id].bounds) {
+                Some((min, max)) => cur.known.forget(cur.base + min, cur.base + max),
+                None => cur.known = Known::default(),
+            }
+            cur.known.vals.insert(cur.base, Some(0));
+            continue;
+        };
+        let id = next_id;
+        next_id += 1;
+        match block {
+            OptBlock::Block(bb) => {
+                let base = cur.base + bb.ptr_offset;
+                let end = match cur.blocks.as_slice().first() {
+                    Some(OptBlock::IOIns(
+                        IOOptIns::Putchar(offset) | IOOptIns::Getchar(offset),
+                    )) => Some(base + offset),
+                    Some(OptBlock::Loop(_)) => Some(base),
+                    Some(OptBlock::Block(_)) => None,
+                    // pointer returns to loop condition cell
+                    None => (!stack.is_empty()).then_some(base),
+                };
+                cur.emit_block(bb, end, &muls);
+            }
+            OptBlock::IOIns(io) => {
+                let (IOOptIns::Putchar(offset) | IOOptIns::Getchar(offset)) = *io;
+                cur.move_to(cur.base + offset);
+                cur.code.push(match io {
+                    IOOptIns::Putchar(_) => BfIns::Putchar,
+                    IOOptIns::Getchar(_) => {
+                        cur.known.vals.insert(cur.ptr, None);
+                        BfIns::Getchar
+                    }
+                });
+            }
+            OptBlock::Loop(body) => {
+                if cur.known.get(cur.base) == Some(0) {
+                    next_id += table.blocks[id].size - 1;
+                    continue;
+                }
+                cur.move_to(cur.base);
+                let known = match loop_cells(table.blocks[id].bounds) {
+                    Some((min, max)) => cur.known.inner(cur.base + min, cur.base + max),
+                    None => Known::default(),
+                };
+                let inner = Frame {
+                    blocks: body.0.iter(),
+                    id,
+                    code: Vec::new(),
+                    known,
+                    base: cur.base,
+                    ptr: cur.base,
+                };
+                stack.push(std::mem::replace(&mut cur, inner));
+            }
+        }
+    }
+}
+
+/// Error of [`verify`]
+#[derive(Debug)]
+pub enum VerifyError {
+    /// Programs print different output
+    OutputMismatch {
+        /// Output of original code
+        original: Vec<u8>,
+        /// Output of minimized code
+        minimized: Vec<u8>,
+    },
+    /// Original code failed
+    Original(InterpreteError),
+    /// Minimized code failed
+    Minimized(InterpreteError),
+}
+
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OutputMismatch {
+                original,
+                minimized,
+            } => write!(
+                f,
+                "output mismatch: {:?} (original) != {:?} (minimized)",
+                String::from_utf8_lossy(original),
+                String::from_utf8_lossy(minimized)
+            ),
+            Self::Original(err) => write!(f, "original code failed: {err}"),
+            Self::Minimized(err) => write!(f, "minimized code failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Run code with `input` for at most `max_steps` interpreter instructions
+fn run(
+    code: &BfCode,
+    input: &[u8],
+    max_steps: u64,
+) -> Result<(Vec<u8>, RunStatus), InterpreteError> {
+    let mut output = Vec::new();
+    let status = Interpreter::builder()
+        .set_stdin(input)
+        .set_stdout(&mut output)
+        .build()
+        .run_for(&InterpCode::from(code.clone()), max_steps)?;
+    Ok((output, status))
+}
+
+/// Check that `minimized` prints same output as `original` for `input`
+///
+/// Both codes are run in [`Interpreter`] for at most `max_steps` instructions.
+/// If any of them doesn't finish in time, only common prefix of outputs is compared
+/// ```
+/// # use bf_tools::{ bf, optimizer::golf::{ verify, VerifyError } };
+/// assert!(verify(&bf!(,+.), &bf!(,+.), b"a", 100).is_ok());
+/// assert!(matches!(verify(&bf!(,+.), &bf!(,.), b"a", 100), Err(VerifyError::OutputMismatch { .. })));
+/// // endless loops print same prefix
+/// assert!(verify(&bf!(+[.]), &bf!(+[..]), b"", 100).is_ok());
+/// ```
+/// # Errors
+/// return `Err` if outputs differ or any code fails
+pub fn verify(
+    original: &BfCode,
+    minimized: &BfCode,
+    input: &[u8],
+    max_steps: u64,
+) -> Result<(), VerifyError> {
+    let (original, original_status) =
+        run(original, input, max_steps).map_err(VerifyError::Original)?;
+    let (minimized, minimized_status) =
+        run(minimized, input, max_steps).map_err(VerifyError::Minimized)?;
+    let same = if original_status == RunStatus::Finished && minimized_status == RunStatus::Finished
+    {
+        original == minimized
+    } else {
+        let len = original.len().min(minimized.len());
+        original[..len] == minimized[..len]
+    };
+    if same {
+        Ok(())
+    } else {
+        Err(VerifyError::OutputMismatch {
+            original,
+            minimized,
+        })
+    }
+}
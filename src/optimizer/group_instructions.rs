@@ -1,43 +1,62 @@
 use super::{
-    opt_ins::{BasicBlock, OptBlock, OptCode, IOOptIns},
+    opt_ins::{OptBlock, OptCode},
     OptPass,
 };
+use crate::visit::{Fold, Tree};
 
 /// Group instructions like Add(1), Add(1) into single instruction Add(2)
+/// ```
+/// # use bf_tools::{ assert_passes, optimizer::passes::GroupInstructions };
+/// // zero changes are removed
+/// assert_passes!(GroupInstructions; "bb off=+1 { +0:0, +1:3 }" => "bb off=+1 { +1:3 }");
+/// // loop right after loop never starts
+/// assert_passes!(
+///     GroupInstructions;
+///     "loop {
+///         loop { bb off=+1 {} }
+///         loop { getchar @+0 }
+///         putchar @+0
+///     }"
+///     =>
+///     "loop {
+///         loop { bb off=+1 {} }
+///         putchar @+0
+///     }"
+/// );
+/// ```
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct GroupInstructions;
 
-impl OptPass for GroupInstructions {
-    fn optimize(&self, code: OptCode, is_changed: &mut bool) -> OptCode {
-        let ins_count = code.ins_len();
-        
-        let mut res = Vec::new();
-        for block in code.0 {
-            match block {
-                OptBlock::Loop(inner) => {
-                    // No sense to do loops like [a][b] (second never starts)
-                    if !matches!(res.last(), Some(OptBlock::Loop(_))) {
-                        res.push(OptBlock::Loop(self.optimize(inner, is_changed)));
+impl Fold<OptCode> for GroupInstructions {
+    fn fold_node(&mut self, block: OptBlock, res: &mut Vec<OptBlock>) {
+        match block {
+            OptBlock::Block(mut block) => {
+                block.ins.retain(|_offset, change| *change != 0);
+                if let Some(OptBlock::Block(last)) = res.last_mut() {
+                    last.ptr_offset += block.ptr_offset;
+                    for (offset, change) in block.ins {
+                        *last.ins.entry(offset).or_default() += change;
                     }
+                } else {
+                    res.push(OptBlock::Block(block));
                 }
-                OptBlock::Block(mut block) => {
-                    block.ins.retain(|_offset, change| *change != 0);
-                    if let Some(OptBlock::Block(last)) = res.last_mut() {
-                        last.ptr_offset += block.ptr_offset;
-                        for (offset, change) in block.ins {
-                            *last.ins.entry(offset).or_default() += change;
-                        }
-                    } else {
-                        res.push(OptBlock::Block(block));
-                    }
-                }
-                io @ OptBlock::IOIns(_) => res.push(io),
             }
+            block => res.push(block),
         }
+    }
+    fn enter_loop(&mut self, _body: &OptCode, res: &[OptBlock]) -> bool {
+        // No sense to do loops like [a][b] (second never starts)
+        !matches!(res.last(), Some(OptBlock::Loop(_)))
+    }
+}
+
+impl OptPass for GroupInstructions {
+    fn optimize(&self, code: OptCode, is_changed: &mut bool) -> OptCode {
+        let ins_count = code.ins_len();
         
         // TODO try group as many as possible instructions into single BB (concat BB with loop|io between if possible)
 
-        let res = OptCode(res);
+        let res = code.fold(&mut Self);
 
         *is_changed |= res.ins_len() < ins_count; // this pass only reduce instruction count
 
@@ -0,0 +1,344 @@
+use super::opt_ins::{BasicBlock, IOOptIns, OptBlock, OptCode};
+use crate::visit::{Tree, Visit};
+use std::collections::BTreeMap;
+
+/// Nesting of loops which is shown with indentation (output of deep loops stays linear)
+const MAX_INDENT: usize = 16;
+
+/*
+    text form:
+    `bb off=+2 { -1:255, +0:3 }`  basic block (pointer offset, cell changes)
+    `putchar @-1` `getchar @+0`    io instructions
+    `loop { ... }`                 loop with inner blocks
+    text after `;` is comment
+*/
+
+/// Error type of [`parse_opt`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptParseError {
+    /// Word isn't name of any block
+    UnknownBlock {
+        /// line number (starts from 1)
+        line: usize,
+    },
+    /// Block isn't written in it's format
+    InvalidSyntax {
+        /// line number (starts from 1)
+        line: usize,
+    },
+    /// Same cell changed twice in one block
+    DuplicateOffset {
+        /// line number (starts from 1)
+        line: usize,
+        /// cell offset
+        offset: isize,
+    },
+    /// `}` without loop
+    UnmatchedBrace {
+        /// line number (starts from 1)
+        line: usize,
+    },
+    /// Loop without `}`
+    UnclosedLoop {
+        /// line number of loop start (starts from 1)
+        line: usize,
+    },
+}
+
+impl std::fmt::Display for OptParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownBlock { line } => write!(f, "line {line}: unknown block"),
+            Self::InvalidSyntax { line } => write!(f, "line {line}: invalid syntax"),
+            Self::DuplicateOffset { line, offset } => {
+                write!(f, "line {line}: cell {offset:+} is changed twice")
+            }
+            Self::UnmatchedBrace { line } => write!(f, "line {line}: unmatched `}}`"),
+            Self::UnclosedLoop { line } => write!(f, "line {line}: loop isn't closed"),
+        }
+    }
+}
+
+impl std::error::Error for OptParseError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    Word(&'a str),
+    Num(&'a str),
+    Sym(char),
+}
+
+/// Split text into tokens with line numbers
+fn tokenize(s: &str) -> Result<Vec<(usize, Token<'_>)>, OptParseError> {
+    let mut res = Vec::new();
+    for (line, text) in s.lines().enumerate() {
+        let line = line + 1;
+        let text = text.split(';').next().unwrap_or_default();
+        let mut rest = text.trim_start();
+        while let Some(c) = rest.chars().next() {
+            let len = if c.is_ascii_alphabetic() || c == '_' {
+                rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            } else if c.is_ascii_digit() || c == '+' || c == '-' {
+                rest[1..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .map(|len| len + 1)
+            } else if "{},:=@".contains(c) {
+                Some(1)
+            } else {
+                return Err(OptParseError::InvalidSyntax { line });
+            }
+            .unwrap_or(rest.len());
+            let (token, tail) = rest.split_at(len);
+            res.push((
+                line,
+                match c {
+                    'a'..='z' | 'A'..='Z' | '_' => Token::Word(token),
+                    '0'..='9' | '+' | '-' => Token::Num(token),
+                    _ => Token::Sym(c),
+                },
+            ));
+            rest = tail.trim_start();
+        }
+    }
+    Ok(res)
+}
+
+/// Parse [`OptCode`] from it's text form
+///
+/// Blocks are separated by whitespace, offsets may have explicit sign.
+/// Nested loops are parsed without recursion
+/// ```
+/// # use bf_tools::{ bf, optimizer::{ OptCode, text::parse_opt } };
+/// let code = parse_opt("
+///     bb off=+0 { +0:3 }
+///     loop {
+///         bb off=+0 { +0:255, +1:2 } ; cells[1] += 2
+///     }
+///     putchar @+1
+/// ").unwrap();
+/// assert_eq!(code, OptCode::from(bf!(+++[->++<]>.<)));
+///
+/// // `Display` output is parsed back to same code
+/// assert_eq!(parse_opt(&code.to_string()), Ok(code));
+/// ```
+/// # Errors
+/// return `Err` if text isn't valid block sequence or loop braces are unbalanced
+pub fn parse_opt(s: &str) -> Result<OptCode, OptParseError> {
+    let mut tokens = Tokens(tokenize(s)?.into_iter().peekable());
+    let mut cur = Vec::new();
+    // enclosing loops with line of loop start
+    let mut stack: Vec<(Vec<OptBlock>, usize)> = Vec::new();
+    while let Some((line, token)) = tokens.0.next() {
+        match token {
+            Token::Word("bb") => {
+                tokens.expect(Token::Word("off"), line)?;
+                tokens.expect(Token::Sym('='), line)?;
+                let ptr_offset = tokens.offset(line)?;
+                tokens.expect(Token::Sym('{'), line)?;
+                let mut ins = BTreeMap::new();
+                if !tokens.skip(Token::Sym('}')) {
+                    loop {
+                        let offset = tokens.offset(line)?;
+                        tokens.expect(Token::Sym(':'), line)?;
+                        let val = tokens.num(line)?;
+                        if ins.insert(offset, val).is_some() {
+                            return Err(OptParseError::DuplicateOffset { line, offset });
+                        }
+                        if tokens.skip(Token::Sym('}')) {
+                            break;
+                        }
+                        tokens.expect(Token::Sym(','), line)?;
+                    }
+                }
+                cur.push(OptBlock::Block(BasicBlock { ptr_offset, ins }));
+            }
+            Token::Word(name @ ("putchar" | "getchar")) => {
+                tokens.expect(Token::Sym('@'), line)?;
+                let offset = tokens.offset(line)?;
+                cur.push(OptBlock::IOIns(if name == "putchar" {
+                    IOOptIns::Putchar(offset)
+                } else {
+                    IOOptIns::Getchar(offset)
+                }));
+            }
+            Token::Word("loop") => {
+                tokens.expect(Token::Sym('{'), line)?;
+                stack.push((std::mem::take(&mut cur), line));
+            }
+            Token::Sym('}') => {
+                let (parent, _) = stack.pop().ok_or(OptParseError::UnmatchedBrace { line })?;
+                let body = std::mem::replace(&mut cur, parent);
+                cur.push(OptBlock::Loop(OptCode(body)));
+            }
+            Token::Word(_) => return Err(OptParseError::UnknownBlock { line }),
+            _ => return Err(OptParseError::InvalidSyntax { line }),
+        }
+    }
+    match stack.pop() {
+        Some((_, line)) => Err(OptParseError::UnclosedLoop { line }),
+        None => Ok(OptCode(cur)),
+    }
+}
+
+/// Token stream of [`parse_opt`]
+struct Tokens<'a>(std::iter::Peekable<std::vec::IntoIter<(usize, Token<'a>)>>);
+
+impl Tokens<'_> {
+    /// Next token must be `expected` (`line` - line of block start for error)
+    fn expect(&mut self, expected: Token<'_>, line: usize) -> Result<(), OptParseError> {
+        match self.0.next() {
+            Some((_, t)) if t == expected => Ok(()),
+            _ => Err(OptParseError::InvalidSyntax { line }),
+        }
+    }
+    /// Skip next token if it's `token`
+    fn skip(&mut self, token: Token<'_>) -> bool {
+        self.0.next_if(|(_, t)| *t == token).is_some()
+    }
+    fn num<T: std::str::FromStr>(&mut self, line: usize) -> Result<T, OptParseError> {
+        match self.0.next() {
+            Some((_, Token::Num(n))) => n.strip_prefix('+').unwrap_or(n).parse().ok(),
+            _ => None,
+        }
+        .ok_or(OptParseError::InvalidSyntax { line })
+    }
+    /// Offset with optional sign
+    fn offset(&mut self, line: usize) -> Result<isize, OptParseError> {
+        self.num(line)
+    }
+}
+
+impl std::str::FromStr for OptCode {
+    type Err = OptParseError;
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_opt(s)
+    }
+}
+
+impl std::fmt::Display for BasicBlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bb off={:+} {{", self.ptr_offset)?;
+        for (i, (offset, val)) in self.ins.iter().enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            write!(f, "{sep}{offset:+}:{val}")?;
+        }
+        if self.ins.is_empty() {
+            f.write_str("}")
+        } else {
+            f.write_str(" }")
+        }
+    }
+}
+
+impl std::fmt::Display for IOOptIns {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Putchar(offset) => write!(f, "putchar @{offset:+}"),
+            Self::Getchar(offset) => write!(f, "getchar @{offset:+}"),
+        }
+    }
+}
+
+/// Printer of [`OptCode`] blocks, one block per line
+struct Printer<'a, 'b> {
+    f: &'a mut std::fmt::Formatter<'b>,
+    depth: usize,
+    res: std::fmt::Result,
+}
+
+impl Printer<'_, '_> {
+    fn line(&mut self, text: impl std::fmt::Display) {
+        if self.res.is_ok() {
+            self.res = (0..self.depth.min(MAX_INDENT))
+                .try_for_each(|_| self.f.write_str("    "))
+                .and_then(|()| writeln!(self.f, "{text}"));
+        }
+    }
+}
+
+impl Visit<OptCode> for Printer<'_, '_> {
+    fn visit_node(&mut self, block: &OptBlock) {
+        match block {
+            OptBlock::Block(bb) => self.line(bb),
+            OptBlock::IOIns(io) => self.line(io),
+            OptBlock::Loop(_) => {}
+        }
+    }
+    fn enter_loop(&mut self, body: &OptCode) -> bool {
+        if body.0.is_empty() {
+            self.line("loop {}");
+            return false;
+        }
+        self.line("loop {");
+        self.depth += 1;
+        true
+    }
+    fn leave_loop(&mut self, _body: &OptCode) {
+        self.depth -= 1;
+        self.line("}");
+    }
+}
+
+impl std::fmt::Display for OptCode {
+    /// One block per line, loop body is indented (up to 16 levels deep)
+    /// ```
+    /// # use bf_tools::{ bf, ins::BfCode, optimizer::OptCode };
+    /// let code = OptCode::from(bf!(>>+[-<]<.));
+    /// let expected = [
+    ///     "bb off=+2 { +2:1 }",
+    ///     "loop {",
+    ///     "    bb off=-1 { +0:255 }",
+    ///     "}",
+    ///     "putchar @-1",
+    ///     "bb off=-1 {}",
+    /// ];
+    /// assert_eq!(code.to_string().lines().collect::<Vec<_>>(), expected);
+    ///
+    /// let deep = OptCode::from(("[+".repeat(100) + &"]".repeat(100)).parse::<BfCode>().unwrap());
+    /// assert!(deep.to_string().lines().all(|line| line.len() <= 16 * 4 + "bb off=+0 { +0:1 }".len()));
+    /// ```
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut printer = Printer {
+            f,
+            depth: 0,
+            res: Ok(()),
+        };
+        self.visit(&mut printer);
+        printer.res
+    }
+}
+
+/// Run optimization passes on textual [`OptCode`] and compare result with expected code
+///
+/// Passes are run with [`crate::optimizer::OptState::run_passes`] (until code stops changing).
+/// On mismatch both codes are printed in text form
+/// ```
+/// # use bf_tools::{ assert_passes, optimizer::passes::GroupInstructions };
+/// assert_passes!(
+///     GroupInstructions;
+///     "loop { bb off=+0 { +0:255 } }
+///      loop { putchar @+0 }"
+///     =>
+///     "loop { bb off=+0 { +0:255 } }"
+/// );
+/// ```
+/// # Panics
+/// panics if input or expected text isn't valid code, or pass output doesn't match expected code
+#[macro_export]
+macro_rules! assert_passes {
+    ($($pass:expr),+ ; $input:expr => $expected:expr $(,)?) => {{
+        let input: $crate::optimizer::OptCode =
+            $input.parse().expect("input isn't valid OptCode text");
+        let expected: $crate::optimizer::OptCode =
+            $expected.parse().expect("expected output isn't valid OptCode text");
+        let mut state = $crate::optimizer::OptState::builder()
+            $(.add_pass(::std::boxed::Box::new($pass)))+
+            .build();
+        let output = state.run_passes(input);
+        assert!(
+            output == expected,
+            "pass output doesn't match expected code\n--- output\n{output}--- expected\n{expected}"
+        );
+    }};
+}
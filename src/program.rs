@@ -0,0 +1,375 @@
+use crate::ins::{BfCode, BfIns};
+use crate::optimizer::golf::{add_len, push_add, push_move};
+use std::collections::BTreeMap;
+
+/// Cell allocated by [`ProgramBuilder`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Cell(usize);
+
+impl Cell {
+    /// Position of cell on tape
+    #[inline]
+    pub const fn index(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct CellState {
+    used: bool,
+    /// cell is known to be zero (free cells are always zero)
+    zero: bool,
+}
+
+/// Loop which is built now
+#[derive(Debug)]
+struct Frame {
+    code: Vec<BfIns>,
+    /// cell states before loop
+    cells: Vec<CellState>,
+    /// pointer and length of enclosing code before loop
+    ptr: usize,
+    start: usize,
+    /// cells allocated in loop body (they're freed at it's end)
+    owned: Vec<Cell>,
+}
+
+/// Builder of [`BfCode`] which allocates cells and keeps track of data pointer
+///
+/// Cells are zero when they're allocated and are cleared when they're freed,
+/// new cells are allocated next to data pointer to keep pointer travel short.
+/// Builder knows which cells are zero, so it doesn't emit clears of them
+/// and skips conditional blocks which never run.
+/// Cells allocated in body of [`ProgramBuilder::if_nonzero`] or [`ProgramBuilder::while_nonzero`]
+/// are freed at it's end
+/// ```
+/// # use bf_tools::{ program::ProgramBuilder, interpreter::Interpreter };
+/// let mut b = ProgramBuilder::new();
+/// let n = b.cell("n");
+/// let digit = b.cell("digit");
+/// b.set(n, 3).set(digit, b'1');
+/// b.while_nonzero(n, |b| {
+///     b.print(digit).add(digit, 1).sub(n, 1);
+///     b.if_nonzero(n, |b| {
+///         b.print_str(", ");
+///     });
+/// });
+/// b.print_str("\n");
+/// let code = b.build();
+///
+/// let mut output = Vec::new();
+/// Interpreter::builder().set_stdout(&mut output).build().run(code).unwrap();
+/// assert_eq!(output, b"1, 2, 3\n");
+/// ```
+#[derive(Debug)]
+pub struct ProgramBuilder {
+    code: Vec<BfIns>,
+    /// open loops
+    frames: Vec<Frame>,
+    cells: Vec<CellState>,
+    names: BTreeMap<String, Cell>,
+    ptr: usize,
+}
+
+impl Default for ProgramBuilder {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgramBuilder {
+    /// Builder of empty program
+    pub const fn new() -> Self {
+        Self {
+            code: Vec::new(),
+            frames: Vec::new(),
+            cells: Vec::new(),
+            names: BTreeMap::new(),
+            ptr: 0,
+        }
+    }
+    /// Finish building
+    /// ```
+    /// # use bf_tools::program::ProgramBuilder;
+    /// let mut b = ProgramBuilder::new();
+    /// let (x, y) = (b.temp(), b.temp());
+    /// b.set(x, 2).set(y, 1).add_to(x, y);
+    /// assert_eq!(b.build().to_string(), "++>+<[->+<]");
+    /// ```
+    pub fn build(self) -> BfCode {
+        BfCode(self.code)
+    }
+
+    // cells
+
+    /// Cell with `name`, it's allocated on first use
+    pub fn cell(&mut self, name: &str) -> Cell {
+        if let Some(cell) = self.names.get(name) {
+            return *cell;
+        }
+        let cell = self.temp();
+        self.names.insert(name.to_string(), cell);
+        cell
+    }
+    /// Named cell if it's allocated
+    pub fn get(&self, name: &str) -> Option<Cell> {
+        self.names.get(name).copied()
+    }
+    /// Allocate unnamed cell (free cell closest to data pointer)
+    pub fn temp(&mut self) -> Cell {
+        let free = |i: &usize| !self.cells.get(*i).is_some_and(|c| c.used);
+        let right = (self.ptr..).find(free).unwrap_or(self.ptr);
+        let left = (0..self.ptr).rev().find(free);
+        let index = match left {
+            Some(left) if self.ptr - left <= right - self.ptr => left,
+            _ => right,
+        };
+        self.state(Cell(index)).used = true;
+        if let Some(frame) = self.frames.last_mut() {
+            frame.owned.push(Cell(index));
+        }
+        Cell(index)
+    }
+    /// Clear cell and make it free (it's name can be used for new cell)
+    pub fn free(&mut self, cell: Cell) -> &mut Self {
+        self.clear(cell);
+        self.names.retain(|_, c| *c != cell);
+        if let Some(state) = self.cells.get_mut(cell.0) {
+            state.used = false;
+        }
+        self
+    }
+
+    // emission
+
+    /// Code of innermost open loop
+    fn cur(&mut self) -> &mut Vec<BfIns> {
+        match self.frames.last_mut() {
+            Some(frame) => &mut frame.code,
+            None => &mut self.code,
+        }
+    }
+    fn move_ptr(&mut self, cell: Cell) {
+        let d = cell.0 as isize - self.ptr as isize;
+        push_move(self.cur(), d);
+        self.ptr = cell.0;
+    }
+    fn state(&mut self, cell: Cell) -> &mut CellState {
+        if cell.0 >= self.cells.len() {
+            self.cells.resize(
+                cell.0 + 1,
+                CellState {
+                    used: false,
+                    zero: true,
+                },
+            );
+        }
+        &mut self.cells[cell.0]
+    }
+    fn is_zero(&self, cell: Cell) -> bool {
+        self.cells.get(cell.0).is_none_or(|c| c.zero)
+    }
+    /// Open loop with condition `cell`, body is built until [`ProgramBuilder::close_loop`]
+    fn open_loop(&mut self, cell: Cell) {
+        let (ptr, start) = (self.ptr, self.cur().len());
+        self.move_ptr(cell);
+        let cells = self.cells.clone();
+        // value of cells is unknown on next iterations
+        for state in self.cells.iter_mut().filter(|c| c.used) {
+            state.zero = false;
+        }
+        self.frames.push(Frame {
+            code: Vec::new(),
+            cells,
+            ptr,
+            start,
+            owned: Vec::new(),
+        });
+    }
+    fn close_loop(&mut self, cell: Cell) -> &mut Self {
+        let owned = self.frames.last_mut().map(|f| std::mem::take(&mut f.owned));
+        for owned in owned.unwrap_or_default() {
+            self.free(owned);
+        }
+        self.move_ptr(cell);
+        let Some(frame) = self.frames.pop() else {
+            return self;
+        };
+        if frame.cells.get(cell.0).is_none_or(|c| c.zero) {
+            // loop never runs
+            (self.cells, self.ptr) = (frame.cells, frame.ptr);
+            self.cur().truncate(frame.start);
+            return self;
+        }
+        // cell is zero after loop if it's zero before loop and after each iteration
+        for (i, state) in self.cells.iter_mut().enumerate() {
+            state.zero &= frame.cells.get(i).is_none_or(|c| c.zero);
+        }
+        self.state(cell).zero = true;
+        self.cur().push(BfIns::Loop(BfCode(frame.code)));
+        self
+    }
+
+    // operations
+
+    /// Add `val` to cell (with overflow)
+    pub fn add(&mut self, cell: Cell, val: u8) -> &mut Self {
+        if val != 0 {
+            self.move_ptr(cell);
+            push_add(self.cur(), val);
+            self.state(cell).zero = false;
+        }
+        self
+    }
+    /// Subtract `val` from cell (with overflow)
+    pub fn sub(&mut self, cell: Cell, val: u8) -> &mut Self {
+        self.add(cell, val.wrapping_neg())
+    }
+    /// Set cell to zero
+    pub fn clear(&mut self, cell: Cell) -> &mut Self {
+        if !self.is_zero(cell) {
+            self.open_loop(cell);
+            self.sub(cell, 1);
+            self.close_loop(cell);
+        }
+        self
+    }
+    /// Set cell to `val`
+    pub fn set(&mut self, cell: Cell, val: u8) -> &mut Self {
+        self.clear(cell).add(cell, val)
+    }
+    /// Add `src` to `dst` and clear `src`
+    /// # Panics
+    /// panics if `src` and `dst` are same cell
+    pub fn add_to(&mut self, src: Cell, dst: Cell) -> &mut Self {
+        self.transfer(src, &[dst])
+    }
+    /// Add `src` to each cell of `dsts` and clear `src`
+    ///
+    /// Destinations are visited in order of their position, so pointer passes
+    /// each cell between `src` and destinations at most twice
+    /// # Panics
+    /// panics if `dsts` contains `src`
+    pub fn transfer(&mut self, src: Cell, dsts: &[Cell]) -> &mut Self {
+        self.transfer_by(src, dsts, 1)
+    }
+    /// Subtract `src` from `dst` (with overflow) and clear `src`
+    /// # Panics
+    /// panics if `src` and `dst` are same cell
+    pub fn sub_from(&mut self, src: Cell, dst: Cell) -> &mut Self {
+        self.transfer_by(src, &[dst], 255)
+    }
+    /// Add `src * k` to each cell of `dsts` and clear `src`
+    fn transfer_by(&mut self, src: Cell, dsts: &[Cell], k: u8) -> &mut Self {
+        assert!(!dsts.contains(&src), "cell is both source and destination");
+        let mut dsts = dsts.to_vec();
+        dsts.sort_unstable();
+        if !self.is_zero(src) {
+            self.open_loop(src);
+            self.sub(src, 1);
+            for dst in dsts {
+                self.add(dst, k);
+            }
+            self.close_loop(src);
+        }
+        self
+    }
+    /// Set `dst` to value of `src` using `tmp`, which is zero after copying
+    /// ```
+    /// # use bf_tools::{ program::ProgramBuilder, interpreter::Interpreter };
+    /// let mut b = ProgramBuilder::new();
+    /// let (a, c, tmp) = (b.cell("a"), b.cell("c"), b.temp());
+    /// b.set(a, 5).set(c, 9).copy(a, c, tmp);
+    /// let code = b.build();
+    ///
+    /// let mut interpreter = Interpreter::builder().build();
+    /// interpreter.run(code).unwrap();
+    /// assert_eq!(interpreter.tape[..3], [5, 5, 0]);
+    /// ```
+    /// # Panics
+    /// panics if any two cells are same
+    pub fn copy(&mut self, src: Cell, dst: Cell, tmp: Cell) -> &mut Self {
+        assert!(dst != tmp, "cell is both source and destination");
+        self.clear(dst).clear(tmp);
+        self.transfer(src, &[dst, tmp]).transfer(tmp, &[src])
+    }
+    /// Build `body` which runs if cell isn't zero (cell isn't changed)
+    pub fn if_nonzero(&mut self, cell: Cell, body: impl FnOnce(&mut Self)) -> &mut Self {
+        let (flag, tmp) = (self.temp(), self.temp());
+        self.copy(cell, flag, tmp).free(tmp);
+        self.open_loop(flag);
+        body(self);
+        self.clear(flag);
+        self.close_loop(flag);
+        self.free(flag)
+    }
+    /// Build `then` which runs if cell isn't zero and `otherwise` which runs if it's zero
+    /// (cell isn't changed)
+    /// ```
+    /// # use bf_tools::{ program::ProgramBuilder, interpreter::Interpreter };
+    /// let mut b = ProgramBuilder::new();
+    /// let x = b.cell("x");
+    /// b.read_into(x).if_else(x, |b| { b.print_str("yes"); }, |b| { b.print_str("no"); });
+    /// let code = b.build();
+    ///
+    /// let mut output = Vec::new();
+    /// Interpreter::builder().set_stdin(&[0][..]).set_stdout(&mut output).build().run(code).unwrap();
+    /// assert_eq!(output, b"no");
+    /// ```
+    pub fn if_else(
+        &mut self,
+        cell: Cell,
+        then: impl FnOnce(&mut Self),
+        otherwise: impl FnOnce(&mut Self),
+    ) -> &mut Self {
+        let (flag, tmp) = (self.temp(), self.temp());
+        self.copy(cell, flag, tmp).free(tmp);
+        let is_zero = self.temp();
+        self.add(is_zero, 1);
+        self.open_loop(flag);
+        then(self);
+        self.sub(is_zero, 1).clear(flag);
+        self.close_loop(flag);
+        self.open_loop(is_zero);
+        otherwise(self);
+        // `is_zero` is 1 here
+        self.sub(is_zero, 1).state(is_zero).zero = true;
+        self.close_loop(is_zero);
+        self.free(flag).free(is_zero)
+    }
+    /// Build `body` which runs while cell isn't zero
+    pub fn while_nonzero(&mut self, cell: Cell, body: impl FnOnce(&mut Self)) -> &mut Self {
+        self.open_loop(cell);
+        body(self);
+        self.close_loop(cell)
+    }
+    /// Print value of cell
+    pub fn print(&mut self, cell: Cell) -> &mut Self {
+        self.move_ptr(cell);
+        self.cur().push(BfIns::Putchar);
+        self
+    }
+    /// Print `text` with temporary cell
+    pub fn print_str(&mut self, text: impl AsRef<[u8]>) -> &mut Self {
+        let tmp = self.temp();
+        let mut val = 0u8;
+        for c in text.as_ref() {
+            self.add(tmp, c.wrapping_sub(val)).print(tmp);
+            val = *c;
+        }
+        // `[-]` is 3 chars
+        if add_len(val) <= 3 {
+            self.sub(tmp, val);
+            self.state(tmp).zero = true;
+        }
+        self.free(tmp)
+    }
+    /// Read input char into cell
+    pub fn read_into(&mut self, cell: Cell) -> &mut Self {
+        self.move_ptr(cell);
+        self.cur().push(BfIns::Getchar);
+        self.state(cell).zero = false;
+        self
+    }
+}
@@ -0,0 +1,281 @@
+use crate::ins::{BfCode, BfIns, Dialect};
+use crate::interpreter::{InterpreteError, Interpreter};
+use crate::optimizer::golf::{add_len, push_add, push_move};
+
+/// Max count of cells prepared by initialization loop
+const MAX_CELLS: usize = 8;
+/// Max counter of initialization loop
+const MAX_COUNTER: u8 = 16;
+/// Count of best layouts (for each count of cells) improved by local search
+const CLIMB_COUNT: usize = 3;
+
+/// Cells prepared by loop `+{counter}[>{steps[0]}>{steps[1]}...<-]`
+///
+/// Counter cell (0 after loop) is also used for printing
+#[derive(Clone)]
+struct Layout {
+    counter: u8,
+    steps: Vec<u8>,
+}
+
+impl Layout {
+    /// Layout without initialization loop (only counter cell is used)
+    const fn plain() -> Self {
+        Self {
+            counter: 0,
+            steps: Vec::new(),
+        }
+    }
+    fn init_len(&self) -> usize {
+        if self.steps.is_empty() {
+            return 0;
+        }
+        let steps: usize = self.steps.iter().map(|step| add_len(*step)).sum();
+        // `[`, `]`, counter decrement and moves to every cell and back
+        add_len(self.counter) + steps + 2 * self.steps.len() + 3
+    }
+    /// Print `text` from cell which is cheapest to reach for each char, returns length of printing code
+    fn print(&self, text: &[u8], mut code: Option<&mut Vec<BfIns>>) -> usize {
+        let mut cells: Vec<u8> = std::iter::once(0)
+            .chain(
+                self.steps
+                    .iter()
+                    .map(|step| self.counter.wrapping_mul(*step)),
+            )
+            .collect();
+        let (mut ptr, mut len) = (0usize, 0);
+        for &c in text {
+            let cost = |(i, val): (usize, &u8)| ptr.abs_diff(i) + add_len(c.wrapping_sub(*val));
+            let Some((i, cell_len)) = cells
+                .iter()
+                .enumerate()
+                .map(|cell| (cell.0, cost(cell)))
+                .min_by_key(|(_, len)| *len)
+            else {
+                break;
+            };
+            len += cell_len + 1;
+            if let Some(code) = code.as_deref_mut() {
+                push_move(code, i as isize - ptr as isize);
+                push_add(code, c.wrapping_sub(cells[i]));
+                code.push(BfIns::Putchar);
+            }
+            cells[i] = c;
+            ptr = i;
+        }
+        len
+    }
+    fn len(&self, text: &[u8]) -> usize {
+        self.init_len() + self.print(text, None)
+    }
+    fn emit(&self, text: &[u8]) -> BfCode {
+        let mut code = Vec::new();
+        if !self.steps.is_empty() {
+            push_add(&mut code, self.counter);
+            let mut body = Vec::new();
+            for step in &self.steps {
+                body.push(BfIns::PtrAdd(1));
+                push_add(&mut body, *step);
+            }
+            body.push(BfIns::PtrSub(self.steps.len()));
+            body.push(BfIns::Sub(1));
+            code.push(BfIns::Loop(BfCode(body)));
+        }
+        self.print(text, Some(&mut code));
+        BfCode(code)
+    }
+    /// Change counter and steps by 1 while code gets shorter
+    fn climb(mut self, text: &[u8]) -> (usize, Self) {
+        let mut len = self.len(text);
+        loop {
+            let mut improved = false;
+            for i in 0..=self.steps.len() {
+                for d in [1, 255] {
+                    let mut next = self.clone();
+                    match i.checked_sub(1) {
+                        Some(i) => next.steps[i] = next.steps[i].wrapping_add(d),
+                        None if (2..=MAX_COUNTER).contains(&self.counter.wrapping_add(d)) => {
+                            next.counter = self.counter.wrapping_add(d);
+                        }
+                        None => continue,
+                    }
+                    let next_len = next.len(text);
+                    if next_len < len {
+                        (len, self) = (next_len, next);
+                        improved = true;
+                    }
+                }
+            }
+            if !improved {
+                return (len, self);
+            }
+        }
+    }
+}
+
+/// Centers of `k` groups of text bytes with minimal sum of distances to them (`hist` - count of each byte)
+///
+/// Groups are contiguous ranges of sorted values, so they're found by dynamic programming
+/// with weighted median of each group as center
+fn centers(hist: &[usize; 256], k: usize) -> Vec<u8> {
+    let values: Vec<(u8, usize)> = (0..=255u8)
+        .zip(hist.iter().copied())
+        .filter(|(_, cnt)| *cnt > 0)
+        .collect();
+    let n = values.len();
+    // prefix sums of counts and weighted values
+    let mut w = vec![0; n + 1];
+    let mut s = vec![0; n + 1];
+    for (i, (val, cnt)) in values.iter().enumerate() {
+        w[i + 1] = w[i] + cnt;
+        s[i + 1] = s[i] + cnt * usize::from(*val);
+    }
+    // (cost, median) of group `values[i..=j]`
+    let mut group = vec![vec![(0, 0); n]; n];
+    for i in 0..n {
+        let mut m = i;
+        for j in i..n {
+            while 2 * (w[m + 1] - w[i]) < w[j + 1] - w[i] {
+                m += 1;
+            }
+            let median = usize::from(values[m].0);
+            let below = median * (w[m + 1] - w[i]) - (s[m + 1] - s[i]);
+            let above = (s[j + 1] - s[m + 1]) - median * (w[j + 1] - w[m + 1]);
+            group[i][j] = (below + above, m);
+        }
+    }
+    // best[g][j] - (cost, start of last group) of splitting `values[..j]` into `g` groups
+    let mut best = vec![vec![(usize::MAX, 0); n + 1]; k + 1];
+    best[0][0] = (0, 0);
+    for g in 1..=k {
+        for j in g..=n {
+            for i in g - 1..j {
+                let (prev, _) = best[g - 1][i];
+                if prev != usize::MAX && prev + group[i][j - 1].0 < best[g][j].0 {
+                    best[g][j] = (prev + group[i][j - 1].0, i);
+                }
+            }
+        }
+    }
+    let mut res = Vec::with_capacity(k);
+    let mut j = n;
+    for g in (1..=k).rev() {
+        let i = best[g][j].1;
+        res.push(values[group[i][j - 1].1].0);
+        j = i;
+    }
+    res.reverse();
+    res
+}
+
+/// Generate short [`BfCode`] which prints `text`
+///
+/// Text bytes are grouped into close values, cells for groups are prepared with single
+/// multiplication loop. Each char is printed from cell that is cheapest to reach and adjust,
+/// so cells follow text and are reused by next chars.
+/// Count of cells, loop counter and steps are chosen by search over generated code length
+/// ```
+/// # use bf_tools::{ ins::BfCode, text_gen::{ generate, verify } };
+/// let text = b"Hello, World!\n";
+/// let code = generate(text);
+/// assert!(code.chars_len() < 130);
+/// assert!(verify(&code, text).is_ok());
+///
+/// assert_eq!(generate(b"").chars_len(), 0);
+/// assert_eq!(generate(b"\x01\x02").to_string(), "+.+.");
+/// ```
+pub fn generate(text: &[u8]) -> BfCode {
+    let mut hist = [0; 256];
+    for c in text {
+        hist[usize::from(*c)] += 1;
+    }
+    let distinct = hist.iter().filter(|cnt| **cnt > 0).count();
+    let plain = Layout::plain();
+    let mut best = (plain.len(text), plain);
+    for k in 1..=MAX_CELLS.min(distinct) {
+        let centers = centers(&hist, k);
+        let mut layouts: Vec<_> = (2..=MAX_COUNTER)
+            .map(|counter| {
+                let steps = centers
+                    .iter()
+                    .map(|c| ((u32::from(*c) + u32::from(counter / 2)) / u32::from(counter)) as u8)
+                    .collect();
+                let layout = Layout { counter, steps };
+                (layout.len(text), layout)
+            })
+            .collect();
+        layouts.sort_by_key(|(len, _)| *len);
+        for (_, layout) in layouts.into_iter().take(CLIMB_COUNT) {
+            let climbed = layout.climb(text);
+            if climbed.0 < best.0 {
+                best = climbed;
+            }
+        }
+    }
+    best.1.emit(text)
+}
+
+/// Error of [`verify`]
+#[derive(Debug)]
+pub enum TextGenError {
+    /// Code prints other text
+    OutputMismatch {
+        /// Printed text
+        output: Vec<u8>,
+    },
+    /// Code failed in interpreter
+    Interpreter(InterpreteError),
+}
+
+impl std::fmt::Display for TextGenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OutputMismatch { output } => {
+                write!(f, "code prints {:?}", String::from_utf8_lossy(output))
+            }
+            Self::Interpreter(err) => write!(f, "code failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for TextGenError {}
+
+/// Check that `code` prints exactly `text` in [`Interpreter`] (code gets empty input)
+/// ```
+/// # use bf_tools::{ bf, text_gen::{ verify, TextGenError } };
+/// assert!(verify(&bf!(++++++++[>++++++++<-]>+.), b"A").is_ok());
+/// assert!(matches!(verify(&bf!(+.), b"A"), Err(TextGenError::OutputMismatch { .. })));
+/// ```
+/// # Errors
+/// return `Err` if code prints other text or fails
+pub fn verify(code: &BfCode, text: &[u8]) -> Result<(), TextGenError> {
+    let mut output = Vec::new();
+    Interpreter::builder()
+        .set_stdin(std::io::empty())
+        .set_stdout(&mut output)
+        .build()
+        .run(code.clone())
+        .map_err(TextGenError::Interpreter)?;
+    if output == text {
+        Ok(())
+    } else {
+        Err(TextGenError::OutputMismatch { output })
+    }
+}
+
+/// Generate code which prints `text` (see [`generate`]), check it with [`verify`] and write it in `dialect`
+/// ```
+/// # use bf_tools::{ ins::Dialect, text_gen::emit };
+/// let prompt = emit(b"> ", &Dialect::BRAINFUCK).unwrap();
+/// assert_eq!(prompt, "++++++++[>++++>++++++++<<-]>>--.<.");
+///
+/// let ook = emit(b"> ", &Dialect::OOK).unwrap();
+/// assert!(ook.starts_with("Ook. Ook. Ook. Ook."));
+/// ```
+/// # Errors
+/// return `Err` if generated code doesn't print `text`
+pub fn emit(text: &[u8], dialect: &Dialect) -> Result<String, TextGenError> {
+    let code = generate(text);
+    verify(&code, text)?;
+    Ok(dialect.render(&code))
+}
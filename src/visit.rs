@@ -0,0 +1,298 @@
+use crate::ins::{BfCode, BfIns};
+use crate::optimizer::opt_ins::{OptBlock, OptCode};
+
+/// Code tree where loops are the only nodes with children
+///
+/// Implemented for [`BfCode`] and [`OptCode`].
+/// Walking is iterative, so visitors work for code of any nesting depth
+pub trait Tree: Sized {
+    /// Single node of code (instruction or block)
+    type Node;
+
+    /// Nodes of code
+    fn nodes(&self) -> &[Self::Node];
+    /// Mutable nodes of code
+    fn nodes_mut(&mut self) -> &mut Vec<Self::Node>;
+    /// Create code from nodes
+    fn from_nodes(nodes: Vec<Self::Node>) -> Self;
+    /// Body of loop node (`None` for other nodes)
+    fn as_loop(node: &Self::Node) -> Option<&Self>;
+    /// Mutable body of loop node (`None` for other nodes)
+    fn as_loop_mut(node: &mut Self::Node) -> Option<&mut Self>;
+    /// Loop node with `body`
+    fn make_loop(body: Self) -> Self::Node;
+
+    /// Walk over all nodes in source order
+    /// ```
+    /// # use bf_tools::{ bf, ins::{ BfCode, BfIns }, visit::{ Tree, Visit } };
+    /// // max nesting depth of loops
+    /// #[derive(Default)]
+    /// struct Depth { cur: usize, max: usize }
+    /// impl Visit<BfCode> for Depth {
+    ///     fn enter_loop(&mut self, _body: &BfCode) -> bool {
+    ///         self.cur += 1;
+    ///         self.max = self.max.max(self.cur);
+    ///         true
+    ///     }
+    ///     fn leave_loop(&mut self, _body: &BfCode) {
+    ///         self.cur -= 1;
+    ///     }
+    /// }
+    /// let mut depth = Depth::default();
+    /// bf!(+[>[-]<[[-]]]).visit(&mut depth);
+    /// assert_eq!(depth.max, 3);
+    /// ```
+    fn visit<V: Visit<Self> + ?Sized>(&self, visitor: &mut V) {
+        // (loop body, remaining nodes) of current code
+        let mut cur = (self, self.nodes().iter());
+        // enclosing loops
+        let mut stack = Vec::new();
+        loop {
+            let Some(node) = cur.1.next() else {
+                let Some(parent) = stack.pop() else {
+                    return;
+                };
+                let (body, _) = std::mem::replace(&mut cur, parent);
+                visitor.leave_loop(body);
+                continue;
+            };
+            match Self::as_loop(node) {
+                Some(body) => {
+                    if visitor.enter_loop(body) {
+                        stack.push(std::mem::replace(&mut cur, (body, body.nodes().iter())));
+                    }
+                }
+                None => visitor.visit_node(node),
+            }
+        }
+    }
+
+    /// Walk over all nodes in source order with mutable access
+    ///
+    /// Loop body is taken out of the code while it's visited,
+    /// so it's restored only after [`VisitMut::leave_loop`] of inner loops
+    /// ```
+    /// # use bf_tools::{ bf, ins::{ BfCode, BfIns }, visit::{ Tree, VisitMut } };
+    /// // swap `+` and `-`
+    /// struct Invert;
+    /// impl VisitMut<BfCode> for Invert {
+    ///     fn visit_node(&mut self, ins: &mut BfIns) {
+    ///         match ins {
+    ///             BfIns::Add(v) => *ins = BfIns::Sub(*v),
+    ///             BfIns::Sub(v) => *ins = BfIns::Add(*v),
+    ///             _ => {}
+    ///         }
+    ///     }
+    /// }
+    /// let mut code = bf!(+[->++<]);
+    /// code.visit_mut(&mut Invert);
+    /// assert_eq!(code, bf!(-[+>--<]));
+    /// ```
+    fn visit_mut<V: VisitMut<Self> + ?Sized>(&mut self, visitor: &mut V) {
+        // (nodes, index of next node) of current code
+        let mut cur = (std::mem::take(self.nodes_mut()), 0);
+        // enclosing loops
+        let mut stack = Vec::new();
+        loop {
+            let (nodes, i) = &mut cur;
+            let Some(node) = nodes.get_mut(*i) else {
+                let Some(parent) = stack.pop() else {
+                    *self.nodes_mut() = cur.0;
+                    return;
+                };
+                let (nodes, _) = std::mem::replace(&mut cur, parent);
+                // current node of parent is loop which body was visited
+                if let Some(body) = cur.0.get_mut(cur.1 - 1).and_then(Self::as_loop_mut) {
+                    *body.nodes_mut() = nodes;
+                    visitor.leave_loop(body);
+                }
+                continue;
+            };
+            *i += 1;
+            match Self::as_loop_mut(node) {
+                Some(body) => {
+                    if visitor.enter_loop(body) {
+                        let nodes = std::mem::take(body.nodes_mut());
+                        stack.push(std::mem::replace(&mut cur, (nodes, 0)));
+                    }
+                }
+                None => visitor.visit_node(node),
+            }
+        }
+    }
+
+    /// Rebuild code bottom-up (loop body is folded before loop itself)
+    /// ```
+    /// # use bf_tools::{ bf, ins::BfCode, optimizer::{ OptCode, opt_ins::{ OptBlock, IOOptIns } }, visit::{ Fold, Tree } };
+    /// // remove all output
+    /// struct NoOutput;
+    /// impl Fold<OptCode> for NoOutput {
+    ///     fn fold_node(&mut self, block: OptBlock, res: &mut Vec<OptBlock>) {
+    ///         if !matches!(block, OptBlock::IOIns(IOOptIns::Putchar(_))) {
+    ///             res.push(block);
+    ///         }
+    ///     }
+    ///     // drop loops which became empty
+    ///     fn fold_loop(&mut self, body: OptCode, res: &mut Vec<OptBlock>) {
+    ///         if !body.0.is_empty() {
+    ///             res.push(OptBlock::Loop(body));
+    ///         }
+    ///     }
+    /// }
+    /// let code = OptCode::from(bf!(+.[.],[-.]));
+    /// let code = code.fold(&mut NoOutput);
+    /// assert_eq!(BfCode::from(code), bf!(+,[-]));
+    /// ```
+    fn fold<F: Fold<Self> + ?Sized>(mut self, folder: &mut F) -> Self {
+        // (remaining nodes, folded nodes) of current code
+        let mut cur = (std::mem::take(self.nodes_mut()).into_iter(), Vec::new());
+        // enclosing loops
+        let mut stack = Vec::new();
+        loop {
+            let (iter, res) = &mut cur;
+            let Some(mut node) = iter.next() else {
+                let Some(parent) = stack.pop() else {
+                    return Self::from_nodes(cur.1);
+                };
+                let (_, body) = std::mem::replace(&mut cur, parent);
+                folder.fold_loop(Self::from_nodes(body), &mut cur.1);
+                continue;
+            };
+            match Self::as_loop_mut(&mut node) {
+                Some(body) => {
+                    if folder.enter_loop(body, res) {
+                        let body = (std::mem::take(body.nodes_mut()).into_iter(), Vec::new());
+                        stack.push(std::mem::replace(&mut cur, body));
+                    }
+                }
+                None => folder.fold_node(node, res),
+            }
+        }
+    }
+}
+
+/// Read-only visitor (see [`Tree::visit`])
+///
+/// All methods do nothing by default, so only interesting nodes need to be handled
+pub trait Visit<T: Tree> {
+    /// Visit node which isn't loop
+    #[inline]
+    fn visit_node(&mut self, _node: &T::Node) {}
+    /// Visit loop before it's body, return `false` to skip body
+    #[inline]
+    fn enter_loop(&mut self, _body: &T) -> bool {
+        true
+    }
+    /// Visit loop after it's body (only if body isn't skipped)
+    #[inline]
+    fn leave_loop(&mut self, _body: &T) {}
+}
+
+/// Visitor with mutable access to nodes (see [`Tree::visit_mut`])
+///
+/// All methods do nothing by default, so only interesting nodes need to be handled
+pub trait VisitMut<T: Tree> {
+    /// Visit node which isn't loop
+    #[inline]
+    fn visit_node(&mut self, _node: &mut T::Node) {}
+    /// Visit loop before it's body, return `false` to skip body
+    #[inline]
+    fn enter_loop(&mut self, _body: &mut T) -> bool {
+        true
+    }
+    /// Visit loop after it's body (only if body isn't skipped)
+    #[inline]
+    fn leave_loop(&mut self, _body: &mut T) {}
+}
+
+/// Owning transformation of code (see [`Tree::fold`])
+///
+/// Every method gets already folded nodes `res` of same code and pushes own result into it.
+/// By default nodes are kept unchanged
+pub trait Fold<T: Tree> {
+    /// Fold node which isn't loop
+    #[inline]
+    fn fold_node(&mut self, node: T::Node, res: &mut Vec<T::Node>) {
+        res.push(node);
+    }
+    /// Check loop before it's body is folded, return `false` to remove loop
+    #[inline]
+    fn enter_loop(&mut self, _body: &T, _res: &[T::Node]) -> bool {
+        true
+    }
+    /// Fold loop with already folded body
+    #[inline]
+    fn fold_loop(&mut self, body: T, res: &mut Vec<T::Node>) {
+        res.push(T::make_loop(body));
+    }
+}
+
+impl Tree for BfCode {
+    type Node = BfIns;
+
+    #[inline]
+    fn nodes(&self) -> &[BfIns] {
+        &self.0
+    }
+    #[inline]
+    fn nodes_mut(&mut self) -> &mut Vec<BfIns> {
+        &mut self.0
+    }
+    #[inline]
+    fn from_nodes(nodes: Vec<BfIns>) -> Self {
+        Self(nodes)
+    }
+    #[inline]
+    fn as_loop(node: &BfIns) -> Option<&Self> {
+        match node {
+            BfIns::Loop(body) => Some(body),
+            _ => None,
+        }
+    }
+    #[inline]
+    fn as_loop_mut(node: &mut BfIns) -> Option<&mut Self> {
+        match node {
+            BfIns::Loop(body) => Some(body),
+            _ => None,
+        }
+    }
+    #[inline]
+    fn make_loop(body: Self) -> BfIns {
+        BfIns::Loop(body)
+    }
+}
+
+impl Tree for OptCode {
+    type Node = OptBlock;
+
+    #[inline]
+    fn nodes(&self) -> &[OptBlock] {
+        &self.0
+    }
+    #[inline]
+    fn nodes_mut(&mut self) -> &mut Vec<OptBlock> {
+        &mut self.0
+    }
+    #[inline]
+    fn from_nodes(nodes: Vec<OptBlock>) -> Self {
+        Self(nodes)
+    }
+    #[inline]
+    fn as_loop(node: &OptBlock) -> Option<&Self> {
+        match node {
+            OptBlock::Loop(body) => Some(body),
+            _ => None,
+        }
+    }
+    #[inline]
+    fn as_loop_mut(node: &mut OptBlock) -> Option<&mut Self> {
+        match node {
+            OptBlock::Loop(body) => Some(body),
+            _ => None,
+        }
+    }
+    #[inline]
+    fn make_loop(body: Self) -> OptBlock {
+        OptBlock::Loop(body)
+    }
+}